@@ -0,0 +1,232 @@
+//! The `build-wrap` command line: help, subcommands, and linking.
+
+use crate::{commands, config, linking, util};
+use anyhow::{Context, Result, anyhow, bail};
+use regex::Regex;
+use std::{
+    collections::BTreeMap,
+    env::{current_dir, current_exe},
+    ffi::OsString,
+    fs::read_to_string,
+    io::{IsTerminal, stdout},
+    path::Path,
+    str::FromStr,
+    sync::LazyLock,
+};
+
+/// Runs `build-wrap` with the arguments `args` (which begin with the program name, as from
+/// [`std::env::args_os`]).
+pub fn run(args: &[OsString]) -> Result<()> {
+    if args[1..]
+        .iter()
+        .all(|arg| matches!(arg.to_str(), Some("-h" | "--help")))
+    {
+        help();
+        return Ok(());
+    }
+
+    // smoelius: When `build-wrap` is invoked as a linker, its arguments may contain paths that are
+    // not valid UTF-8. Only subcommands' arguments are required to be.
+    let subcommand_args = || {
+        args[2..]
+            .iter()
+            .map(|arg| {
+                arg.to_str()
+                    .map(ToOwned::to_owned)
+                    .ok_or_else(|| anyhow!("argument `{}` is not valid UTF-8", arg.display()))
+            })
+            .collect::<Result<Vec<_>>>()
+    };
+
+    match args[1].to_str() {
+        Some("allow") => commands::allow::allow(&subcommand_args()?),
+        Some("config") => commands::config::config(&subcommand_args()?),
+        Some("gc") => commands::gc::gc(&subcommand_args()?),
+        Some("list") => commands::list::list(&subcommand_args()?),
+        Some("rewrap") => commands::unwrap::rewrap(&subcommand_args()?),
+        Some("run") => commands::run::run(&subcommand_args()?),
+        Some("trust") => commands::trust::trust(&subcommand_args()?),
+        Some("unwrap") => commands::unwrap::unwrap(&subcommand_args()?),
+        _ => linking::link(args),
+    }
+}
+
+static ENABLED: LazyLock<&str> = LazyLock::new(|| {
+    if stdout().is_terminal() {
+        "\x1b[1;32mENABLED\x1b[0m"
+    } else {
+        "ENABLED"
+    }
+});
+
+static DISABLED_YELLOW: LazyLock<&str> = LazyLock::new(|| {
+    if stdout().is_terminal() {
+        "\x1b[1;33mDISABLED\x1b[0m"
+    } else {
+        "DISABLED"
+    }
+});
+
+static DISABLED_RED: LazyLock<&str> = LazyLock::new(|| {
+    if stdout().is_terminal() {
+        "\x1b[1;31mDISABLED\x1b[0m"
+    } else {
+        "DISABLED"
+    }
+});
+
+fn help() {
+    println!(
+        "{} {}
+
+A linker replacement to help protect against malicious build scripts
+
+Commands:
+    {}
+    {}
+    {}
+    {}
+    {}
+    {}
+    {}
+    {}
+",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        commands::allow::USAGE,
+        commands::config::USAGE,
+        commands::gc::USAGE,
+        commands::list::USAGE,
+        commands::unwrap::REWRAP_USAGE,
+        commands::run::USAGE,
+        commands::trust::USAGE,
+        commands::unwrap::UNWRAP_USAGE,
+    );
+    let result = enabled();
+    if matches!(result, Ok(true)) {
+        let enabled = *ENABLED;
+        if current_dir().is_ok_and(|cwd| config::directory_allowed(&cwd)) {
+            let disabled = *DISABLED_YELLOW;
+            println!("build-wrap is {enabled} (but {disabled} in this directory)");
+        } else {
+            println!("build-wrap is {enabled}");
+        }
+        return;
+    }
+    let disabled = *DISABLED_RED;
+    let msg = result
+        .err()
+        .map(|error| format!(": {error}"))
+        .unwrap_or_default();
+    println!(
+        r#"build-wrap is {disabled}{msg}
+
+To enable build-wrap, create a `.cargo/config.toml` file in your home directory with the following contents:
+
+```
+[target.'cfg(all())']
+linker = "build-wrap"
+```{}"#,
+        if noble_numbat_or_later().unwrap_or(cfg!(target_os = "linux")) {
+            "
+
+And install the Bubblewrap AppArmor profile with the following commands:
+
+```
+sudo apt install apparmor-profiles
+sudo cp /usr/share/apparmor/extra-profiles/bwrap-userns-restrict /etc/apparmor.d
+sudo systemctl reload apparmor
+```"
+        } else {
+            ""
+        }
+    );
+}
+
+static BWRAP_APPARMOR_PROFILE_PATH: LazyLock<&Path> =
+    LazyLock::new(|| Path::new("/etc/apparmor.d/bwrap-userns-restrict"));
+
+fn enabled() -> Result<bool> {
+    let current_exe = current_exe()?;
+    let Some(home) = home::home_dir() else {
+        bail!("failed to determine home directory");
+    };
+    let path_buf = home.join(".cargo/config.toml");
+    let contents = read_to_string(&path_buf)
+        .with_context(|| format!("failed to read `{}`", path_buf.display()))?;
+    let table = contents.parse::<toml::Table>()?;
+    let Some(linker) = table
+        .get("target")
+        .and_then(toml::Value::as_table)
+        .and_then(|table| table.get("cfg(all())"))
+        .and_then(toml::Value::as_table)
+        .and_then(|table| table.get("linker"))
+        .and_then(toml::Value::as_str)
+    else {
+        bail!("`config.toml` has unexpected contents");
+    };
+    let path = util::which(linker)?;
+    if current_exe != path {
+        return Ok(false);
+    }
+    if noble_numbat_or_later()? && !BWRAP_APPARMOR_PROFILE_PATH.try_exists()? {
+        bail!("`{}` does not exist", BWRAP_APPARMOR_PROFILE_PATH.display());
+    }
+    Ok(true)
+}
+
+static OS_RELEASE_PATH: LazyLock<&Path> = LazyLock::new(|| Path::new("/etc/os-release"));
+
+static VERSION_ID_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"([0-9]+)\.[0-9]+").unwrap());
+
+fn noble_numbat_or_later() -> Result<bool> {
+    if !OS_RELEASE_PATH.try_exists()? {
+        return Ok(false);
+    }
+    let map = parse_env_file(&OS_RELEASE_PATH)?;
+    let Some(version_id) = map.get("VERSION_ID") else {
+        bail!(
+            "`{}` does not contain `VERSION_ID`",
+            OS_RELEASE_PATH.display()
+        );
+    };
+    let Some(captures) = VERSION_ID_RE.captures(version_id) else {
+        bail!("failed to parse version id: {version_id:?}");
+    };
+    assert_eq!(2, captures.len());
+    let version_major = u64::from_str(captures.get(1).unwrap().as_str())?;
+    Ok(version_major >= 24)
+}
+
+static ENV_LINE_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new("([A-Za-z0-9_]+)=(.*)").unwrap());
+
+fn parse_env_file(path: &Path) -> Result<BTreeMap<String, String>> {
+    let mut map = BTreeMap::new();
+    let contents = read_to_string(path)?;
+    for line in contents.lines() {
+        let Some(captures) = ENV_LINE_RE.captures(line) else {
+            bail!("failed to parse line: {line:?}");
+        };
+        assert_eq!(3, captures.len());
+        let key = captures.get(1).unwrap().as_str();
+        let mut value = captures.get(2).unwrap().as_str();
+        if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+            value = &value[1..value.len() - 1];
+        }
+        map.insert(key.to_owned(), value.to_owned());
+    }
+    Ok(map)
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn help() {
+        super::run(&["build-wrap".into(), "--help".into()]).unwrap();
+    }
+
+    #[test]
+    fn version() {
+        super::run(&["build-wrap".into(), "--version".into()]).unwrap();
+    }
+}
@@ -0,0 +1,29 @@
+//! `build-wrap allow`: adds packages to the `[allow]` section of config.toml.
+
+use crate::config;
+use anyhow::{Result, bail};
+
+pub const USAGE: &str = "build-wrap allow <PACKAGE>[@<VERSION>]...";
+
+pub fn allow(args: &[String]) -> Result<()> {
+    if args.is_empty() {
+        bail!("expected at least one package\n\nusage: {USAGE}");
+    }
+
+    for arg in args {
+        if arg.starts_with('-') {
+            bail!("unrecognized option `{arg}`\n\nusage: {USAGE}");
+        }
+        if arg.is_empty() || arg.starts_with('@') || arg.contains(char::is_whitespace) {
+            bail!("invalid package `{arg}`\n\nusage: {USAGE}");
+        }
+        let (path, added) = config::allow_package(arg)?;
+        if added {
+            println!("allowed `{arg}` in `{}`", path.display());
+        } else {
+            println!("`{arg}` is already allowed in `{}`", path.display());
+        }
+    }
+
+    Ok(())
+}
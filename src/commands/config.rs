@@ -0,0 +1,20 @@
+//! `build-wrap config check`: validates the configuration files and prints the effective
+//! configuration.
+
+use crate::config;
+use anyhow::{Result, bail};
+
+pub const USAGE: &str = "build-wrap config check";
+
+pub fn config(args: &[String]) -> Result<()> {
+    let [subcommand] = args else {
+        bail!("expected exactly one subcommand\n\nusage: {USAGE}");
+    };
+    if subcommand != "check" {
+        bail!("unrecognized subcommand `{subcommand}`\n\nusage: {USAGE}");
+    }
+
+    print!("{}", config::describe()?);
+
+    Ok(())
+}
@@ -0,0 +1,34 @@
+//! `build-wrap gc`: removes renamed original build scripts that no wrapper refers to.
+
+use super::{build_dirs, parse_target_dir};
+use crate::wrapper;
+use anyhow::{Result, bail};
+
+pub const USAGE: &str = "build-wrap gc [--target-dir <DIR>] [--dry-run]";
+
+pub fn gc(mut args: &[String]) -> Result<()> {
+    let mut target_dir = None;
+    let mut dry_run = false;
+
+    while !args.is_empty() {
+        if let Some(value) = parse_target_dir(&mut args)? {
+            target_dir = Some(value);
+            continue;
+        }
+        match args[0].as_str() {
+            "--dry-run" => dry_run = true,
+            arg => bail!("unrecognized argument `{arg}`\n\nusage: {USAGE}"),
+        }
+        args = &args[1..];
+    }
+
+    let verb = if dry_run { "would remove" } else { "removed" };
+
+    for dir in build_dirs(&super::target_dir(target_dir))? {
+        for sibling in wrapper::remove_stale_siblings(&dir, dry_run)? {
+            println!("{verb} `{}`", sibling.display());
+        }
+    }
+
+    Ok(())
+}
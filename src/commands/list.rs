@@ -0,0 +1,179 @@
+//! `build-wrap list`: summarizes the state of every build script in a target directory.
+
+use super::{build_dirs, package_name, parse_target_dir, proc_macro_sidecars};
+use crate::sidecar::{ProcMacroSidecar, Sidecar};
+use anyhow::{Result, bail};
+use serde::Serialize;
+use std::{fs::read_dir, path::PathBuf};
+
+pub const USAGE: &str = "build-wrap list [--target-dir <DIR>] [--json]";
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Entry {
+    dir: PathBuf,
+    state: State,
+    #[serde(flatten)]
+    sidecar: Option<Sidecar>,
+    #[serde(flatten)]
+    proc_macro: Option<ProcMacroSidecar>,
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum State {
+    /// The build script was wrapped.
+    Wrapped,
+    /// The build script was linked by `build-wrap` but not wrapped.
+    Exempt,
+    /// The build script was not linked by `build-wrap`, or was linked by an older version.
+    Unknown,
+    /// The proc macro was linked by `build-wrap`.
+    ProcMacro,
+    /// The proc macro was refused by the `[proc-macros]` policy.
+    Refused,
+}
+
+impl State {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Wrapped => "wrapped",
+            Self::Exempt => "exempt",
+            Self::Unknown => "unknown",
+            Self::ProcMacro => "proc-macro",
+            Self::Refused => "refused",
+        }
+    }
+}
+
+pub fn list(mut args: &[String]) -> Result<()> {
+    let mut target_dir = None;
+    let mut json = false;
+
+    while !args.is_empty() {
+        if let Some(value) = parse_target_dir(&mut args)? {
+            target_dir = Some(value);
+            continue;
+        }
+        match args[0].as_str() {
+            "--json" => json = true,
+            arg => bail!("unrecognized argument `{arg}`\n\nusage: {USAGE}"),
+        }
+        args = &args[1..];
+    }
+
+    let entries = entries(&super::target_dir(target_dir))?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+    } else {
+        print_table(&entries);
+    }
+
+    Ok(())
+}
+
+fn entries(target_dir: &std::path::Path) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+
+    for dir in build_dirs(target_dir)? {
+        let sidecar = Sidecar::read(&dir)?;
+        let state = match &sidecar {
+            Some(sidecar) if sidecar.sibling.is_some() => State::Wrapped,
+            Some(_) => State::Exempt,
+            None if contains_build_script(&dir)? => State::Unknown,
+            // smoelius: `dir` is where a build script is run (i.e., it contains `output`,
+            // `out/`, etc.), not where one is linked.
+            None => continue,
+        };
+        entries.push(Entry {
+            dir,
+            state,
+            sidecar,
+            proc_macro: None,
+        });
+    }
+
+    for path in proc_macro_sidecars(target_dir)? {
+        let proc_macro = ProcMacroSidecar::read(&path)?;
+        let state = if proc_macro.refused {
+            State::Refused
+        } else {
+            State::ProcMacro
+        };
+        entries.push(Entry {
+            dir: path.parent().map(Into::into).unwrap_or_default(),
+            state,
+            sidecar: None,
+            proc_macro: Some(proc_macro),
+        });
+    }
+
+    Ok(entries)
+}
+
+fn contains_build_script(dir: &std::path::Path) -> Result<bool> {
+    for result in read_dir(dir)? {
+        let entry = result?;
+        if entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.starts_with("build_script_"))
+        {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn print_table(entries: &[Entry]) {
+    let rows = entries
+        .iter()
+        .map(|entry| {
+            let (package, detail) = if let Some(proc_macro) = &entry.proc_macro {
+                (
+                    format!("{}@{}", proc_macro.package, proc_macro.version),
+                    proc_macro.approval.clone(),
+                )
+            } else {
+                let package = entry.sidecar.as_ref().map_or_else(
+                    || package_name(&entry.dir).unwrap_or_default().to_owned(),
+                    |sidecar| format!("{}@{}", sidecar.package, sidecar.version),
+                );
+                let detail = entry
+                    .sidecar
+                    .as_ref()
+                    .and_then(|sidecar| sidecar.cmd.as_ref().or(sidecar.exemption.as_ref()))
+                    .cloned()
+                    .unwrap_or_default();
+                (package, detail)
+            };
+            [
+                package,
+                entry.state.as_str().to_owned(),
+                entry.dir.display().to_string(),
+                detail,
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    let header =
+        ["PACKAGE", "STATE", "DIR", "COMMAND, EXEMPTION, OR APPROVAL"].map(ToOwned::to_owned);
+
+    let mut widths = header.clone().map(|s| s.len());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    for row in std::iter::once(&header).chain(&rows) {
+        let [package, state, dir, detail] = row;
+        // smoelius: Commands may span multiple lines (e.g., the Linux default). Print them on one.
+        let detail = detail.split_whitespace().collect::<Vec<_>>().join(" ");
+        println!(
+            "{package:<0$}  {state:<1$}  {dir:<2$}  {detail}",
+            widths[0], widths[1], widths[2]
+        );
+    }
+}
@@ -0,0 +1,145 @@
+//! Subcommands, i.e., what `build-wrap` does other than act as a linker.
+
+use crate::sidecar;
+use anyhow::{Result, bail};
+use std::{
+    env::var_os,
+    fs::read_dir,
+    path::{Path, PathBuf},
+};
+
+pub mod allow;
+pub mod config;
+pub mod gc;
+pub mod list;
+pub mod run;
+pub mod trust;
+pub mod unwrap;
+
+/// Returns the target directory to operate on: `target_dir` if provided, otherwise
+/// `CARGO_TARGET_DIR` if set, otherwise `target`.
+fn target_dir(target_dir: Option<PathBuf>) -> PathBuf {
+    target_dir
+        .or_else(|| var_os("CARGO_TARGET_DIR").map(PathBuf::from))
+        .unwrap_or_else(|| PathBuf::from("target"))
+}
+
+/// Returns the `build/<pkg>-<hash>` directories within `target_dir`, including those beneath a
+/// target triple (e.g., `target/x86_64-unknown-linux-gnu/debug/build`).
+fn build_dirs(target_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut build_dirs = Vec::new();
+
+    for dir in profile_dirs(target_dir)? {
+        let build = dir.join("build");
+        if build.is_dir() {
+            build_dirs.extend(subdirs(&build)?);
+        }
+    }
+
+    build_dirs.sort();
+
+    Ok(build_dirs)
+}
+
+/// Returns the proc-macro sidecars in the `deps` directories within `target_dir`, including those
+/// beneath a target triple.
+fn proc_macro_sidecars(target_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut sidecars = Vec::new();
+
+    for dir in profile_dirs(target_dir)? {
+        let deps = dir.join("deps");
+        if !deps.is_dir() {
+            continue;
+        }
+        for result in read_dir(&deps)? {
+            let path = result?.path();
+            if sidecar::is_proc_macro_sidecar(&path) {
+                sidecars.push(path);
+            }
+        }
+    }
+
+    sidecars.sort();
+
+    Ok(sidecars)
+}
+
+/// Returns the profile directories within `target_dir` (e.g., `target/debug`) and the directories
+/// beneath them (e.g., `target/x86_64-unknown-linux-gnu/debug`), which may also be profile
+/// directories.
+fn profile_dirs(target_dir: &Path) -> Result<Vec<PathBuf>> {
+    if !target_dir.is_dir() {
+        bail!("`{}` is not a directory", target_dir.display());
+    }
+
+    let mut profile_dirs = Vec::new();
+
+    for profile_dir in subdirs(target_dir)? {
+        profile_dirs.extend(subdirs(&profile_dir)?);
+        profile_dirs.push(profile_dir);
+    }
+
+    Ok(profile_dirs)
+}
+
+fn subdirs(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut subdirs = Vec::new();
+    for result in read_dir(dir)? {
+        let entry = result?;
+        if entry.file_type()?.is_dir() {
+            subdirs.push(entry.path());
+        }
+    }
+    Ok(subdirs)
+}
+
+/// Returns the package name of a `build/<pkg>-<hash>` directory.
+fn package_name(build_dir: &Path) -> Option<&str> {
+    let file_name = build_dir.file_name()?.to_str()?;
+    let (name, hash) = file_name.rsplit_once('-')?;
+    if hash.len() != 16 || !hash.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(name)
+}
+
+/// Parses `--target-dir <DIR>` or `--target-dir=<DIR>` at the front of `args`, advancing `args`
+/// past it. Returns `Ok(None)` if `args` does not begin with `--target-dir`.
+fn parse_target_dir(args: &mut &[String]) -> Result<Option<PathBuf>> {
+    let Some(arg) = args.first() else {
+        return Ok(None);
+    };
+    if let Some(value) = arg.strip_prefix("--target-dir=") {
+        *args = &args[1..];
+        return Ok(Some(PathBuf::from(value)));
+    }
+    if arg != "--target-dir" {
+        return Ok(None);
+    }
+    let Some(value) = args.get(1) else {
+        bail!("`--target-dir` requires a value");
+    };
+    *args = &args[2..];
+    Ok(Some(PathBuf::from(value)))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    #[test]
+    fn package_name() {
+        assert_eq!(
+            Some("proc-macro2"),
+            super::package_name(Path::new("target/debug/build/proc-macro2-0123456789abcdef"))
+        );
+        assert_eq!(
+            None,
+            super::package_name(Path::new("target/debug/build/libc"))
+        );
+        assert_eq!(
+            None,
+            super::package_name(Path::new("target/debug/build/build-wrap"))
+        );
+    }
+}
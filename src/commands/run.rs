@@ -0,0 +1,259 @@
+//! `build-wrap run`: reruns a single build script under the current policy, using the environment
+//! recorded by its wrapper the last time Cargo ran it.
+
+use super::{build_dirs, package_name, parse_target_dir};
+use crate::{config, linking, sidecar::Sidecar, util};
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::{OsStr, OsString},
+    fs::read,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+pub const USAGE: &str = "build-wrap run [--target-dir <DIR>] [--shell] <PACKAGE|BUILD_DIR>";
+
+pub fn run(mut args: &[String]) -> Result<()> {
+    let mut target_dir = None;
+    let mut shell = false;
+    let mut positional = Vec::new();
+
+    while !args.is_empty() {
+        if let Some(value) = parse_target_dir(&mut args)? {
+            target_dir = Some(value);
+            continue;
+        }
+        match args[0].as_str() {
+            "--shell" => shell = true,
+            arg if arg.starts_with('-') => bail!("unrecognized option `{arg}`\n\nusage: {USAGE}"),
+            arg => positional.push(arg),
+        }
+        args = &args[1..];
+    }
+
+    let [package] = positional.as_slice() else {
+        bail!("expected exactly one package\n\nusage: {USAGE}");
+    };
+
+    config::check()?;
+
+    let build_dir = find_build_dir(&super::target_dir(target_dir), package)?;
+    let invocation = Invocation::read(&build_dir.join(util::INVOKED_FILE_NAME))?;
+
+    ensure!(
+        invocation.sibling_path.try_exists()?,
+        "original build script `{}` no longer exists; rebuild the package",
+        invocation.sibling_path.display()
+    );
+
+    let sidecar = invocation
+        .sibling_path
+        .parent()
+        .map(Sidecar::read)
+        .transpose()?
+        .flatten();
+
+    if let Some(sibling_sha256) = sidecar
+        .as_ref()
+        .and_then(|sidecar| sidecar.sibling_sha256.as_deref())
+    {
+        util::verify_sibling(&invocation.sibling_path, sibling_sha256)?;
+    }
+
+    // smoelius: With `--shell`, the shell is executed in place of the build script, i.e., under the
+    // same command the build script would be executed under.
+    let program = if shell {
+        env::var_os("SHELL").map_or_else(|| PathBuf::from("/bin/sh"), PathBuf::from)
+    } else {
+        invocation.sibling_path.clone()
+    };
+
+    // smoelius: The command is chosen as it would be if the build script were linked now, e.g., a
+    // package denied by config.toml is executed under the `strict` profile, and `BUILD_WRAP_CMD` is
+    // ignored for it.
+    let denied = sidecar
+        .as_ref()
+        .is_some_and(|sidecar| config::package_denied(&sidecar.package, &sidecar.version));
+    let cmd = if let Some(sidecar) = &sidecar {
+        linking::wrap_cmd(sidecar, denied)?
+    } else {
+        linking::build_wrap_cmd()?
+    };
+    let expanded_args = util::split_and_expand_with(&cmd, &program, |key| invocation.var(key))?;
+
+    // smoelius: `_seccomp_file` must outlive the spawning of `command` (see `util::seccomp`).
+    #[cfg(target_os = "linux")]
+    let (mut command, _seccomp_file) = {
+        let denied = sidecar.as_ref().map_or_else(Vec::new, |sidecar| {
+            config::denied_syscalls(&sidecar.package, &sidecar.version)
+        });
+        util::seccomp::command(&expanded_args, denied.iter().map(String::as_str))?
+    };
+    #[cfg(not(target_os = "linux"))]
+    let mut command = {
+        let mut command = std::process::Command::new(&expanded_args[0]);
+        command.args(&expanded_args[1..]);
+        command
+    };
+    // smoelius: The wrapper records only the variables Cargo sets for build scripts (see
+    // `RECORDED_VARS` in util/common.rs). Variables such as `CC` or `PKG_CONFIG_PATH` are taken
+    // from the current environment.
+    command.envs(&invocation.vars);
+    if let Some(manifest_dir) = invocation.vars.get(OsStr::new("CARGO_MANIFEST_DIR")) {
+        command.current_dir(manifest_dir);
+    }
+
+    let status = command
+        .status()
+        .with_context(|| format!("failed to execute `{}`", expanded_args[0].display()))?;
+    ensure!(
+        status.success(),
+        "command failed: {command:?}{}",
+        if denied {
+            "\nthe package is denied by config.toml, so its build script is never run unsandboxed"
+        } else {
+            ""
+        }
+    );
+
+    Ok(())
+}
+
+/// Resolves `package` to a `build/<pkg>-<hash>` directory containing an invocation record.
+/// `package` may be a path to such a directory or a package name.
+fn find_build_dir(target_dir: &Path, package: &str) -> Result<PathBuf> {
+    let path = Path::new(package);
+    if path.join(util::INVOKED_FILE_NAME).try_exists()? {
+        return Ok(path.to_path_buf());
+    }
+
+    let candidates = build_dirs(target_dir)?
+        .into_iter()
+        .filter(|build_dir| {
+            package_name(build_dir) == Some(package)
+                && build_dir.join(util::INVOKED_FILE_NAME).exists()
+        })
+        .collect::<Vec<_>>();
+
+    match candidates.as_slice() {
+        [] => bail!(
+            "found no build script for `{package}` in `{}` that was run by a wrapper",
+            target_dir.display()
+        ),
+        [build_dir] => Ok(build_dir.clone()),
+        _ => bail!(
+            "found multiple build scripts for `{package}`; pass one of the following directories \
+             instead:\n{}",
+            candidates
+                .iter()
+                .map(|build_dir| format!("    {}", build_dir.display()))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ),
+    }
+}
+
+/// The contents of a file written by `record_invocation` in util/common.rs.
+struct Invocation {
+    sibling_path: PathBuf,
+    vars: BTreeMap<OsString, OsString>,
+}
+
+impl Invocation {
+    fn read(path: &Path) -> Result<Self> {
+        let contents =
+            read(path).with_context(|| format!("failed to read `{}`", path.display()))?;
+        Self::parse(&contents).with_context(|| format!("failed to parse `{}`", path.display()))
+    }
+
+    fn parse(contents: &[u8]) -> Result<Self> {
+        let mut fields = contents
+            .strip_suffix(b"\0")
+            .ok_or_else(|| anyhow!("missing trailing NUL"))?
+            .split(|&c| c == 0);
+
+        let sibling_path = fields
+            .next()
+            .filter(|field| !field.is_empty())
+            .map(|field| PathBuf::from(OsStr::from_bytes(field)))
+            .ok_or_else(|| anyhow!("missing build script path"))?;
+
+        let mut vars = BTreeMap::new();
+        for field in fields {
+            let i = field
+                .iter()
+                .position(|&c| c == b'=')
+                .ok_or_else(|| anyhow!("malformed environment variable"))?;
+            vars.insert(
+                OsStr::from_bytes(&field[..i]).to_owned(),
+                OsStr::from_bytes(&field[i + 1..]).to_owned(),
+            );
+        }
+
+        Ok(Self { sibling_path, vars })
+    }
+
+    /// Looks up `key` in the recorded environment, taking into account the variables that
+    /// `build-wrap` treats as set.
+    fn var(&self, key: &str) -> Result<OsString, env::VarError> {
+        if let Some(result) = util::synthesized_var(
+            key,
+            Some(&self.sibling_path),
+            &|key| self.recorded_var(key),
+            &|| {
+                self.vars
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect()
+            },
+        ) {
+            return result;
+        }
+        self.recorded_var(key)
+    }
+
+    /// Looks up `key` in the recorded environment only.
+    fn recorded_var(&self, key: &str) -> Result<OsString, env::VarError> {
+        self.vars
+            .get(OsStr::new(key))
+            .cloned()
+            .ok_or(env::VarError::NotPresent)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Invocation;
+    use std::{
+        ffi::{OsStr, OsString},
+        path::Path,
+    };
+
+    #[test]
+    fn parse_invocation() {
+        let invocation =
+            Invocation::parse(b"/target/debug/build/a-0/.tmpAbCdEf\0OUT_DIR=/out\0X=a=b\0")
+                .unwrap();
+        assert_eq!(
+            Path::new("/target/debug/build/a-0/.tmpAbCdEf"),
+            invocation.sibling_path
+        );
+        assert_eq!(2, invocation.vars.len());
+        assert_eq!(Ok(OsString::from("/out")), invocation.var("OUT_DIR"));
+        assert_eq!(
+            Some(OsStr::new("a=b")),
+            invocation.vars.get(OsStr::new("X")).map(AsRef::as_ref)
+        );
+        assert!(invocation.var("UNKNOWN").is_err());
+    }
+
+    #[test]
+    fn parse_malformed_invocation() {
+        assert!(Invocation::parse(b"").is_err());
+        assert!(Invocation::parse(b"\0").is_err());
+        assert!(Invocation::parse(b"/path\0NO_EQUALS\0").is_err());
+        assert!(Invocation::parse(b"/path\0X=1").is_err());
+    }
+}
@@ -0,0 +1,38 @@
+//! `build-wrap trust`: trusts a project configuration file, so that it can allow packages and
+//! directories.
+
+use crate::config;
+use anyhow::{Result, anyhow, bail};
+use std::path::PathBuf;
+
+pub const USAGE: &str = "build-wrap trust [PATH]";
+
+pub fn trust(args: &[String]) -> Result<()> {
+    let project_path = match args {
+        [] => config::find_project_config(None).ok_or_else(|| {
+            anyhow!(
+                "found no `{}` in the current directory or its ancestors",
+                config::PROJECT_FILE_NAME
+            )
+        })?,
+        [arg] if !arg.starts_with('-') => {
+            let path = PathBuf::from(arg);
+            if path.is_dir() {
+                path.join(config::PROJECT_FILE_NAME)
+            } else {
+                path
+            }
+        }
+        _ => bail!("expected at most one path\n\nusage: {USAGE}"),
+    };
+
+    let (path, sha256) = config::trust_project(&project_path)?;
+
+    println!(
+        "trusted `{}` (SHA-256 {sha256}) in `{}`",
+        project_path.display(),
+        path.display()
+    );
+
+    Ok(())
+}
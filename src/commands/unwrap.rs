@@ -0,0 +1,207 @@
+//! `build-wrap unwrap` and `build-wrap rewrap`: change how already linked build scripts are
+//! executed, without `cargo clean`.
+
+use super::{build_dirs, package_name, parse_target_dir};
+use crate::{config, linking, sidecar::Sidecar};
+use anyhow::{Context, Result, bail};
+use std::{
+    fs::{hard_link, read_dir, remove_file, rename},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+pub const UNWRAP_USAGE: &str = "build-wrap unwrap [--target-dir <DIR>] [PACKAGE]...";
+
+pub const REWRAP_USAGE: &str = "build-wrap rewrap [--target-dir <DIR>] [PACKAGE]...";
+
+/// Restores the original build scripts in place of their wrappers.
+pub fn unwrap(args: &[String]) -> Result<()> {
+    for_each_sidecar(args, UNWRAP_USAGE, |sidecar| {
+        if sidecar.sibling.is_none() {
+            return Ok(None);
+        }
+        with_hard_links(&sidecar.original, || restore(&sidecar))?;
+        Ok(Some(
+            sidecar.exempt(String::from("unwrapped by `build-wrap unwrap`")),
+        ))
+    })
+}
+
+/// Regenerates the wrappers with the current policy. A build script that the current policy
+/// exempts is unwrapped, and one that was previously exempt is wrapped.
+pub fn rewrap(args: &[String]) -> Result<()> {
+    config::check()?;
+    let linker = linking::linker()?;
+
+    for_each_sidecar(args, REWRAP_USAGE, |sidecar| {
+        if let Some(exemption) = linking::exemption(&sidecar) {
+            if sidecar.sibling.is_none() {
+                return Ok(None);
+            }
+            with_hard_links(&sidecar.original, || restore(&sidecar))?;
+            return Ok(Some(sidecar.exempt(exemption)));
+        }
+        let result = with_hard_links(&sidecar.original, || {
+            if sidecar.sibling.is_some() {
+                restore(&sidecar)?;
+            }
+            linking::wrap(&linker, &sidecar)
+        });
+        match result {
+            Ok((sibling, cmd)) => Ok(Some(sidecar.wrapped(sibling, cmd))),
+            // smoelius: If the original build script was restored but could not be wrapped again,
+            // the sidecar must not continue to name the old, now nonexistent, sibling.
+            Err(error) if sidecar.original.try_exists()? && sidecar.sibling.is_some() => {
+                let dir = sidecar.original.parent().map(Path::to_path_buf);
+                sidecar
+                    .exempt(format!(
+                        "unwrapped because `build-wrap rewrap` failed: {error}"
+                    ))
+                    .write()?;
+                if let Some(dir) = dir {
+                    invalidate_run_fingerprints(&dir)?;
+                }
+                Err(error)
+            }
+            Err(error) => Err(error),
+        }
+    })
+}
+
+/// Calls `f` on the sidecar of each build script in the target directory whose package is named in
+/// `args` (or every build script, if `args` names no packages). If `f` returns a new sidecar, the
+/// sidecar is written and the build script's run fingerprint is removed so that Cargo reruns it.
+fn for_each_sidecar(
+    mut args: &[String],
+    usage: &str,
+    mut f: impl FnMut(Sidecar) -> Result<Option<Sidecar>>,
+) -> Result<()> {
+    let mut target_dir = None;
+    let mut packages = Vec::new();
+
+    while !args.is_empty() {
+        if let Some(value) = parse_target_dir(&mut args)? {
+            target_dir = Some(value);
+            continue;
+        }
+        match args[0].as_str() {
+            arg if arg.starts_with('-') => bail!("unrecognized option `{arg}`\n\nusage: {usage}"),
+            arg => packages.push(arg),
+        }
+        args = &args[1..];
+    }
+
+    for dir in build_dirs(&super::target_dir(target_dir))? {
+        let Some(sidecar) = Sidecar::read(&dir)? else {
+            continue;
+        };
+        if !packages.is_empty() && !packages.contains(&sidecar.package.as_str()) {
+            continue;
+        }
+        let package = format!("{}@{}", sidecar.package, sidecar.version);
+        let was_wrapped = sidecar.sibling.is_some();
+        let Some(sidecar) = f(sidecar).with_context(|| format!("failed to update `{package}`"))?
+        else {
+            continue;
+        };
+        sidecar.write()?;
+        invalidate_run_fingerprints(&dir)?;
+        let verb = match (was_wrapped, sidecar.sibling.is_some()) {
+            (_, false) => "unwrapped",
+            (false, true) => "wrapped",
+            (true, true) => "rewrapped",
+        };
+        println!("{verb} `{package}` in `{}`", dir.display());
+    }
+
+    Ok(())
+}
+
+/// Moves the renamed original build script back to its original path, replacing the wrapper.
+fn restore(sidecar: &Sidecar) -> Result<()> {
+    let Some(sibling) = &sidecar.sibling else {
+        return Ok(());
+    };
+    rename(sibling, &sidecar.original).with_context(|| {
+        format!(
+            "failed to rename `{}` to `{}`",
+            sibling.display(),
+            sidecar.original.display()
+        )
+    })
+}
+
+/// Calls `f`, which is expected to replace the file at `path`, and then makes each hard link to the
+/// old file (e.g., Cargo's `build-script-build`) a hard link to the new one. The links are updated
+/// even if `f` fails, since `f` may have replaced the file before failing.
+fn with_hard_links<T>(path: &Path, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let links = hard_links(path)?;
+    let result = f();
+    if path.try_exists()? {
+        for link in links {
+            remove_file(&link)?;
+            hard_link(path, &link)?;
+        }
+    }
+    result
+}
+
+/// Returns the paths of the other files in `path`'s directory that are hard links to `path`.
+fn hard_links(path: &Path) -> Result<Vec<PathBuf>> {
+    let metadata = path.metadata()?;
+    let mut links = Vec::new();
+    let Some(parent) = path.parent() else {
+        return Ok(links);
+    };
+    for result in read_dir(parent)? {
+        let entry = result?;
+        let other = entry.path();
+        if other == path {
+            continue;
+        }
+        let other_metadata = entry.metadata()?;
+        if other_metadata.dev() == metadata.dev() && other_metadata.ino() == metadata.ino() {
+            links.push(other);
+        }
+    }
+    Ok(links)
+}
+
+/// Removes the fingerprints of every run of `build_dir`'s package's build script, so that Cargo
+/// considers them dirty.
+///
+/// Cargo runs a build script from a different `build/<pkg>-<hash>` directory than the one the
+/// build script is linked in, and the two hashes are not related in a way `build-wrap` can
+/// determine. So the fingerprints of all of the package's runs are removed.
+fn invalidate_run_fingerprints(build_dir: &Path) -> Result<()> {
+    let Some(package) = package_name(build_dir) else {
+        return Ok(());
+    };
+    let Some(fingerprint_dir) = build_dir
+        .parent()
+        .and_then(Path::parent)
+        .map(|profile_dir| profile_dir.join(".fingerprint"))
+    else {
+        return Ok(());
+    };
+    if !fingerprint_dir.try_exists()? {
+        return Ok(());
+    }
+    for result in read_dir(&fingerprint_dir)? {
+        let entry = result?;
+        if package_name(&entry.path()) != Some(package) {
+            continue;
+        }
+        for result in read_dir(entry.path())? {
+            let entry = result?;
+            if entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.starts_with("run-build-script-"))
+            {
+                remove_file(entry.path())?;
+            }
+        }
+    }
+    Ok(())
+}
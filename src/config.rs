@@ -1,112 +1,1407 @@
+//! Loading of `$HOME/.config/build-wrap/config.toml` and project configuration files
+//! (`build-wrap.toml`). The functions that answer questions about the effective configuration
+//! (e.g., [`package_allowed`]) load the files once, on first use, unless [`load_for_output`] loaded
+//! them first.
+
+use crate::{
+    pattern::{self, Pattern},
+    proc_macros, profile,
+    sandbox::Rules,
+    util::{self, Action, AuditAction, AuditRule, Policy, sha256_hex},
+};
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use serde::Deserialize;
 use std::{
+    collections::BTreeMap,
     env,
-    fs::read_to_string,
+    ffi::{OsStr, OsString},
+    fs::{OpenOptions, canonicalize, metadata, read, read_to_string},
+    io::{ErrorKind, Write},
+    ops::Range,
+    os::unix::ffi::OsStrExt,
     path::{Path, PathBuf},
-    sync::LazyLock,
+    sync::OnceLock,
 };
+use toml::Spanned;
 
-static CONFIG: LazyLock<Config> = LazyLock::new(Config::load);
+/// Name of a project configuration file. `build-wrap` looks for one in the ancestors of the target
+/// directory and of the current directory.
+pub const PROJECT_FILE_NAME: &str = "build-wrap.toml";
 
+static LOADED: OnceLock<Loaded> = OnceLock::new();
+
+/// The result of loading the user and project configuration files
 #[derive(Default)]
-struct Config {
-    directories: Vec<PathBuf>,
-    packages: Vec<String>,
+struct Loaded {
+    /// The effective configuration
+    config: Config,
+    user_path: Option<PathBuf>,
+    project_path: Option<PathBuf>,
+    project_trusted: bool,
+    /// Errors encountered while loading. A file that could not be loaded is ignored.
+    errors: Vec<String>,
 }
 
-impl Config {
-    fn load() -> Self {
+/// Loads the configuration files that apply to linking the file at `output_path`, i.e., the user's
+/// file and the project configuration file nearest the target directory containing `output_path`.
+/// Has no effect if the files were already loaded, so it should be called before any other
+/// function in this module.
+pub fn load_for_output(output_path: &Path) {
+    let _: &Loaded = LOADED.get_or_init(|| Loaded::load(Some(output_path)));
+}
+
+fn loaded() -> &'static Loaded {
+    LOADED.get_or_init(|| Loaded::load(None))
+}
+
+impl Loaded {
+    fn load(output_path: Option<&Path>) -> Self {
         let base_directories = xdg::BaseDirectories::new();
-        let Some(path) = base_directories.find_config_file("build-wrap/config.toml") else {
-            return Self::default();
-        };
-        Self::load_from(&path)
+        let user_path = base_directories.find_config_file("build-wrap/config.toml");
+        let project_path = find_project_config(output_path);
+        Self::load_from(user_path, project_path)
     }
 
-    fn load_from(path: &Path) -> Self {
-        let Ok(contents) = read_to_string(path) else {
-            return Self::default();
-        };
-        let Ok(table) = contents.parse::<toml::Table>() else {
-            return Self::default();
-        };
+    fn load_from(user_path: Option<PathBuf>, project_path: Option<PathBuf>) -> Self {
+        let (config, project_trusted, errors) =
+            Config::load_lenient(user_path.as_deref(), project_path.as_deref());
+        Self {
+            config,
+            user_path,
+            project_path,
+            project_trusted,
+            errors,
+        }
+    }
+}
+
+/// An effective configuration, i.e., a user configuration file merged with a project configuration
+/// file
+#[derive(Debug, Default)]
+pub struct Config {
+    directories: Vec<Pattern>,
+    packages: Vec<Pattern>,
+    /// Packages that are always sandboxed, regardless of `directories`, `packages`, and
+    /// `BUILD_WRAP_ALLOW`
+    denied: Vec<Pattern>,
+    profiles: BTreeMap<String, Profile>,
+    /// Rules that extend the default sandbox for all packages
+    sandbox: Rules,
+    /// Rules that extend the default sandbox for particular packages
+    sandbox_packages: Vec<(Pattern, Rules)>,
+    /// Syscalls that are denied to all packages, in addition to the defaults
+    denied_syscalls: Vec<String>,
+    /// Syscalls that are denied to particular packages, in addition to the defaults
+    denied_syscalls_packages: PackageSyscalls,
+    policy: Policy,
+    /// Which proc macros may be linked
+    proc_macro_policy: proc_macros::Policy,
+    /// Proc macros that may be linked regardless of `proc_macro_policy`
+    proc_macro_packages: Vec<Pattern>,
+    /// Fingerprints of proc macros trusted on first use, keyed by `NAME@VERSION`
+    proc_macro_fingerprints: BTreeMap<String, String>,
+    /// Project configuration files that the user trusts, and their SHA-256 hashes
+    trusted: Vec<(PathBuf, String)>,
+}
+
+type PackageSyscalls = Vec<(Pattern, Vec<String>)>;
+
+/// A `[profiles.NAME]` section
+#[derive(Debug, Default)]
+struct Profile {
+    /// The command build scripts are executed under. `None` if the section does not redefine a
+    /// built-in profile's command.
+    cmd: Option<String>,
+    /// Directories whose build scripts are executed under the profile
+    directories: Vec<Pattern>,
+    /// Packages whose build scripts are executed under the profile
+    packages: Vec<Pattern>,
+}
+
+/// The contents of a configuration file
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct File {
+    allow: Section,
+    ignore: Section,
+    deny: DenySection,
+    profiles: BTreeMap<Spanned<String>, ProfileSection>,
+    sandbox: SandboxSection,
+    seccomp: SeccompSection,
+    directives: Directives,
+    audit: AuditSection,
+    #[serde(rename = "proc-macros")]
+    proc_macros: ProcMacrosSection,
+    trusted: BTreeMap<String, String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Section {
+    directories: Vec<Spanned<String>>,
+    packages: Vec<Spanned<String>>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct DenySection {
+    packages: Vec<Spanned<String>>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ProfileSection {
+    cmd: Option<Spanned<String>>,
+    directories: Vec<Spanned<String>>,
+    packages: Vec<Spanned<String>>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct SandboxSection {
+    writable: Vec<Spanned<String>>,
+    network: bool,
+    hide_target_dir: bool,
+    packages: BTreeMap<Spanned<String>, RulesSection>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct RulesSection {
+    writable: Vec<Spanned<String>>,
+    network: bool,
+    hide_target_dir: bool,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SeccompSection {
+    deny: Vec<Spanned<String>>,
+    packages: BTreeMap<Spanned<String>, SeccompPackageSection>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SeccompPackageSection {
+    deny: Vec<Spanned<String>>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Directives {
+    rustc_link_arg: Option<Spanned<ActionName>>,
+    rustc_link_search: Option<Spanned<ActionName>>,
+    rustc_env: Option<Spanned<ActionName>>,
+    rustc_cfg: Option<Spanned<ActionName>>,
+    link_search_directories: Vec<Spanned<String>>,
+    allowed_cfgs: Vec<Spanned<String>>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ProcMacrosSection {
+    policy: Option<proc_macros::Policy>,
+    packages: Vec<Spanned<String>>,
+    fingerprints: BTreeMap<String, String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct AuditSection {
+    symlinks: Option<Spanned<ActionName>>,
+    executables: Option<Spanned<ActionName>>,
+    absolute_includes: Option<Spanned<ActionName>>,
+    large_files: Option<Spanned<ActionName>>,
+    max_file_size: Option<u64>,
+}
+
+/// The name of an [`Action`] or an [`AuditAction`]. Which names a key accepts is checked in
+/// `load_policy`.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ActionName {
+    Allow,
+    Warn,
+    Restrict,
+    Deny,
+}
+
+impl ActionName {
+    fn action(self) -> Option<Action> {
+        match self {
+            Self::Allow => Some(Action::Allow),
+            Self::Restrict => Some(Action::Restrict),
+            Self::Deny => Some(Action::Deny),
+            Self::Warn => None,
+        }
+    }
+
+    fn audit_action(self) -> Option<AuditAction> {
+        match self {
+            Self::Allow => Some(AuditAction::Allow),
+            Self::Warn => Some(AuditAction::Warn),
+            Self::Deny => Some(AuditAction::Deny),
+            Self::Restrict => None,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the user configuration file `home_config` (e.g.,
+    /// `$HOME/.config/build-wrap/config.toml`) and, if given, the project configuration file
+    /// `project`, and merges them as `build-wrap` does. A nonexistent `home_config` is treated as
+    /// empty. If `project` is a directory, the nearest [`PROJECT_FILE_NAME`] file in it or its
+    /// ancestors is used, if any.
+    ///
+    /// # Errors
+    ///
+    /// If either file cannot be read or is invalid.
+    pub fn load(home_config: &Path, project: Option<&Path>) -> Result<Self> {
+        let project = project.and_then(|project| {
+            if project.is_dir() {
+                find_project_config_from(project)
+            } else {
+                Some(project.to_path_buf())
+            }
+        });
+        let home_config = Some(home_config).filter(|path| path.exists());
+        let (config, _, errors) = Self::load_lenient(home_config, project.as_deref());
+        if !errors.is_empty() {
+            bail!("{}", errors.join("\n"));
+        }
+        Ok(config)
+    }
+
+    /// Like [`Config::load`], but a file that cannot be loaded is ignored. Returns the
+    /// configuration, whether the user trusts the project configuration file, and the errors
+    /// encountered.
+    fn load_lenient(
+        user_path: Option<&Path>,
+        project_path: Option<&Path>,
+    ) -> (Self, bool, Vec<String>) {
+        let mut config = Self::default();
+        let mut project_trusted = false;
+        let mut errors = Vec::new();
+
+        if let Some(path) = user_path {
+            match Self::load_from(path) {
+                Ok(user) => config = user,
+                Err(error) => errors.push(format!("{error:#}")),
+            }
+        }
+
+        // smoelius: An error in a project configuration file must not cause the user's
+        // configuration to be ignored. Otherwise, a repository could loosen the user's policy by
+        // committing a malformed file.
+        if let Some(path) = project_path {
+            match Self::load_from(path) {
+                Ok(project) => project_trusted = config.merge_project(path, project),
+                Err(error) => errors.push(format!("{error:#}")),
+            }
+        }
+
+        (config, project_trusted, errors)
+    }
+
+    /// Returns whether `path`, once canonicalized, is within an allowed directory.
+    #[must_use]
+    pub fn directory_allowed(&self, path: &Path) -> bool {
+        let path = canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        pattern::allowed(&self.directories, &path.to_string_lossy())
+    }
+
+    /// Returns whether package `name` is allowed, either in all versions (e.g., by an entry `foo`)
+    /// or in `version` specifically (e.g., by an entry `foo@1.2.3`).
+    #[must_use]
+    pub fn package_allowed(&self, name: &str, version: &str) -> bool {
+        package_matches(&self.packages, name, version)
+    }
+
+    /// Returns whether package `name` at `version` is denied, i.e., must be sandboxed regardless of
+    /// the other configuration.
+    #[must_use]
+    pub fn package_denied(&self, name: &str, version: &str) -> bool {
+        package_matches(&self.denied, name, version)
+    }
+
+    /// Returns the command of profile `name`, which may be defined in a configuration file or built
+    /// in.
+    ///
+    /// # Errors
+    ///
+    /// If there is no profile named `name`.
+    pub fn profile_cmd(&self, name: &str) -> Result<String> {
+        self.profiles
+            .get(name)
+            .and_then(|profile| profile.cmd.clone())
+            .or_else(|| profile::builtin_cmd(name))
+            .ok_or_else(|| anyhow!("unknown profile `{name}`"))
+    }
+
+    /// Returns the name of the profile under which the build script of package `name` at
+    /// `version`, located at `path`, should be executed, or `None` if no profile names the package
+    /// or directory. A profile that names the package takes precedence over one that names the
+    /// directory. Otherwise, the profile whose name sorts first takes precedence.
+    #[must_use]
+    pub fn profile_for(&self, name: &str, version: &str, path: &Path) -> Option<&str> {
+        let path = canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.profiles
+            .iter()
+            .find(|(_, profile)| package_matches(&profile.packages, name, version))
+            .or_else(|| {
+                self.profiles.iter().find(|(_, profile)| {
+                    pattern::allowed(&profile.directories, &path.to_string_lossy())
+                })
+            })
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Returns the policy for the `cargo:` directives that wrapped build scripts print.
+    #[must_use]
+    pub fn policy(&self) -> &Policy {
+        &self.policy
+    }
+
+    /// Returns which proc macros may be linked.
+    #[must_use]
+    pub fn proc_macro_policy(&self) -> proc_macros::Policy {
+        self.proc_macro_policy
+    }
+
+    /// Returns whether proc macro `name` at `version` is listed in the `[proc-macros]` section's
+    /// `packages`.
+    #[must_use]
+    pub fn proc_macro_approved(&self, name: &str, version: &str) -> bool {
+        package_matches(&self.proc_macro_packages, name, version)
+    }
+
+    /// Merges the project configuration `project`, loaded from `path`, into `self`. The project's
+    /// `[deny]`, `[seccomp]`, `[directives]`, `[audit]`, and `[proc-macros]` sections can only
+    /// tighten the policy. The project's `[allow]`, `[ignore]`, `[profiles]`, and `[sandbox]`
+    /// sections and `[proc-macros]` packages are considered, and its `[directives]` lists may be
+    /// extended, only if the user trusts the file's current contents (see `build-wrap trust`).
+    /// Returns whether the user trusts the file.
+    fn merge_project(&mut self, path: &Path, project: Self) -> bool {
+        let trusted = read(path).is_ok_and(|contents| self.trusts(path, &contents));
+
+        if !project.trusted.is_empty() {
+            eprintln!(
+                "warning: {}: ignoring `[trusted]`, which is allowed only in user configuration",
+                path.display()
+            );
+        }
+
+        if !project.proc_macro_fingerprints.is_empty() {
+            eprintln!(
+                "warning: {}: ignoring `[proc-macros.fingerprints]`, which is allowed only in user \
+                 configuration",
+                path.display()
+            );
+        }
+
+        if trusted {
+            self.directories.extend(project.directories);
+            self.packages.extend(project.packages);
+            for (name, project_profile) in project.profiles {
+                // smoelius: Only the user's configuration can redefine a built-in profile.
+                // Otherwise, a project could, e.g., make `strict` run build scripts unsandboxed.
+                let builtin = profile::BUILTIN_NAMES.contains(&name.as_str());
+                if builtin && project_profile.cmd.is_some() {
+                    eprintln!(
+                        "warning: {}: ignoring `cmd` of built-in profile `{name}`, which only user \
+                         configuration can redefine",
+                        path.display()
+                    );
+                }
+                let profile = self.profiles.entry(name).or_default();
+                if !builtin && project_profile.cmd.is_some() {
+                    profile.cmd = project_profile.cmd;
+                }
+                profile.directories.extend(project_profile.directories);
+                profile.packages.extend(project_profile.packages);
+            }
+            self.sandbox.extend(&project.sandbox);
+            self.sandbox_packages.extend(project.sandbox_packages);
+            self.proc_macro_packages.extend(project.proc_macro_packages);
+        } else {
+            if !project.directories.is_empty() || !project.packages.is_empty() {
+                eprintln!(
+                    "warning: {0}: ignoring `[allow]` and `[ignore]` because the file is not \
+                     trusted; run `build-wrap trust {0}` to trust it",
+                    path.display()
+                );
+            }
+            if !project.profiles.is_empty() {
+                eprintln!(
+                    "warning: {0}: ignoring `[profiles]` because the file is not trusted; run \
+                     `build-wrap trust {0}` to trust it",
+                    path.display()
+                );
+            }
+            if !project.sandbox.is_empty() || !project.sandbox_packages.is_empty() {
+                eprintln!(
+                    "warning: {0}: ignoring `[sandbox]` because the file is not trusted; run \
+                     `build-wrap trust {0}` to trust it",
+                    path.display()
+                );
+            }
+            if !project.proc_macro_packages.is_empty() {
+                eprintln!(
+                    "warning: {0}: ignoring `[proc-macros]` packages because the file is not \
+                     trusted; run `build-wrap trust {0}` to trust it",
+                    path.display()
+                );
+            }
+        }
+
+        self.denied.extend(project.denied);
+        self.denied_syscalls.extend(project.denied_syscalls);
+        self.denied_syscalls_packages
+            .extend(project.denied_syscalls_packages);
+
+        tighten(&mut self.policy, project.policy, trusted);
+        // smoelius: Under `tofu`, linking a proc macro can write to the user's configuration file.
+        // Only the user's configuration may enable such writes.
+        let mut proc_macro_policy = project.proc_macro_policy;
+        if proc_macro_policy == proc_macros::Policy::Tofu
+            && self.proc_macro_policy < proc_macros::Policy::Tofu
+        {
+            eprintln!(
+                "warning: {}: treating `[proc-macros]` policy `tofu` as `allowlist`, since only user \
+                 configuration can enable recording fingerprints",
+                path.display()
+            );
+            proc_macro_policy = proc_macros::Policy::Allowlist;
+        }
+        self.proc_macro_policy = self.proc_macro_policy.max(proc_macro_policy);
 
-        for key in table.keys() {
-            if key != "allow" && key != "ignore" {
-                eprintln!("warning: {}: unrecognized table `[{key}]`", path.display());
+        trusted
+    }
+
+    /// Returns the rules that extend the default sandbox for package `name` at `version`, i.e., the
+    /// rules of the `[sandbox]` section combined with those of each `[sandbox.packages.PATTERN]`
+    /// section whose pattern matches the package.
+    #[must_use]
+    pub fn sandbox_rules(&self, name: &str, version: &str) -> Rules {
+        let mut rules = self.sandbox.clone();
+        for (pattern, package_rules) in &self.sandbox_packages {
+            if package_matches(std::slice::from_ref(pattern), name, version) {
+                rules.extend(package_rules);
+            }
+        }
+        rules
+    }
+
+    /// Returns the syscalls denied to package `name` at `version` in addition to the default ones,
+    /// i.e., those of the `[seccomp]` section combined with those of each
+    /// `[seccomp.packages.PATTERN]` section whose pattern matches the package.
+    #[must_use]
+    pub fn denied_syscalls(&self, name: &str, version: &str) -> Vec<String> {
+        let mut denied = self.denied_syscalls.clone();
+        for (pattern, package_denied) in &self.denied_syscalls_packages {
+            if package_matches(std::slice::from_ref(pattern), name, version) {
+                denied.extend(package_denied.iter().cloned());
             }
         }
+        denied.sort();
+        denied.dedup();
+        denied
+    }
+
+    fn trusts(&self, path: &Path, contents: &[u8]) -> bool {
+        let Ok(path) = canonicalize(path) else {
+            return false;
+        };
+        let sha256 = sha256_hex(contents);
+        let Some((_, trusted_sha256)) = self.trusted.iter().find(|(p, _)| *p == path) else {
+            return false;
+        };
+        if *trusted_sha256 != sha256 {
+            eprintln!(
+                "warning: {0}: the file has changed since it was trusted; run `build-wrap trust \
+                 {0}` to trust it again",
+                path.display()
+            );
+            return false;
+        }
+        true
+    }
+
+    fn load_from(path: &Path) -> Result<Self> {
+        Self::load_from_with(path, &|key| util::var(key))
+    }
+
+    /// Like [`Config::load_from`], but looks up the variables in `directories` entries with `var`.
+    fn load_from_with(
+        path: &Path,
+        var: &dyn Fn(&str) -> Result<OsString, env::VarError>,
+    ) -> Result<Self> {
+        let contents =
+            read_to_string(path).with_context(|| format!("failed to read `{}`", path.display()))?;
+        let file = toml::from_str::<File>(&contents)
+            .map_err(|error| anyhow!("{}: {error}", path.display()))?;
+
+        // smoelius: Relative directories are relative to the directory containing the file.
+        let base = path.parent().unwrap_or(Path::new(""));
+        let resolve = |entry: &str| -> Result<PathBuf> {
+            let expanded = util::expand_with(entry, None, var)?;
+            Ok(base.join(expand_tilde(&expanded)))
+        };
+
+        let parse_directories = |entries: Vec<Spanned<String>>| {
+            entries
+                .into_iter()
+                .map(|entry| {
+                    Pattern::directory(entry.get_ref(), resolve).map_err(|error| {
+                        located(path, &contents, entry.span(), &format!("{error:#}"))
+                    })
+                })
+                .collect::<Result<Vec<_>>>()
+        };
+        let parse_packages = |entries: Vec<Spanned<String>>| {
+            entries
+                .into_iter()
+                .map(|entry| {
+                    Pattern::package(entry.get_ref()).map_err(|error| {
+                        located(path, &contents, entry.span(), &format!("{error:#}"))
+                    })
+                })
+                .collect::<Result<Vec<_>>>()
+        };
 
         let mut directories = Vec::new();
         let mut packages = Vec::new();
 
-        for section in ["allow", "ignore"] {
-            if let Some(table) = table.get(section).and_then(toml::Value::as_table) {
-                extend_directories(&mut directories, table.get("directories"));
-                extend_from_string_array(&mut packages, table.get("packages"));
+        for section in [file.allow, file.ignore] {
+            directories.extend(parse_directories(section.directories)?);
+            packages.extend(parse_packages(section.packages)?);
+        }
+
+        // smoelius: Negated entries are not allowed in `[deny]`. Otherwise, a project configuration
+        // file could exempt a package from the user's `[deny]` section.
+        let mut denied = Vec::new();
+        for entry in file.deny.packages {
+            let pattern = Pattern::package(entry.get_ref())
+                .and_then(|pattern| {
+                    ensure!(
+                        !pattern.is_negated(),
+                        "negated entries are not allowed in `[deny]`"
+                    );
+                    Ok(pattern)
+                })
+                .map_err(|error| located(path, &contents, entry.span(), &format!("{error:#}")))?;
+            denied.push(pattern);
+        }
+
+        let mut profiles = BTreeMap::new();
+        for (name, section) in file.profiles {
+            if section.cmd.is_none() && !profile::BUILTIN_NAMES.contains(&name.get_ref().as_str()) {
+                return Err(located(
+                    path,
+                    &contents,
+                    name.span(),
+                    &format!(
+                        "`{}` is not a built-in profile, so it must have a `cmd`",
+                        name.get_ref()
+                    ),
+                ));
             }
+            let profile = Profile {
+                cmd: section.cmd.map(Spanned::into_inner),
+                directories: parse_directories(section.directories)?,
+                packages: parse_packages(section.packages)?,
+            };
+            profiles.insert(name.into_inner(), profile);
         }
 
-        Self {
+        let (sandbox, sandbox_packages) = load_sandbox(path, &contents, file.sandbox)?;
+
+        let (denied_syscalls, denied_syscalls_packages) =
+            load_seccomp(path, &contents, file.seccomp)?;
+
+        let policy = load_policy(path, &contents, file.directives, file.audit)?;
+
+        let proc_macro_policy = file.proc_macros.policy.unwrap_or_default();
+        let proc_macro_packages = parse_packages(file.proc_macros.packages)?;
+        let proc_macro_fingerprints = file.proc_macros.fingerprints;
+
+        let trusted = file
+            .trusted
+            .into_iter()
+            .map(|(path, sha256)| (PathBuf::from(path), sha256))
+            .collect();
+
+        Ok(Self {
             directories,
             packages,
+            denied,
+            profiles,
+            sandbox,
+            sandbox_packages,
+            denied_syscalls,
+            denied_syscalls_packages,
+            policy,
+            proc_macro_policy,
+            proc_macro_packages,
+            proc_macro_fingerprints,
+            trusted,
+        })
+    }
+}
+
+/// Makes `policy` at least as strict as `other`. If both restrict a directive, the directive's
+/// list is extended by `other`'s list if `trusted`, and is intersected with it otherwise.
+fn tighten(policy: &mut Policy, other: Policy, trusted: bool) {
+    policy.rustc_link_arg = policy.rustc_link_arg.max(other.rustc_link_arg);
+    policy.rustc_env = policy.rustc_env.max(other.rustc_env);
+    tighten_with_list(
+        &mut policy.rustc_link_search,
+        &mut policy.link_search_directories,
+        other.rustc_link_search,
+        other.link_search_directories,
+        trusted,
+    );
+    tighten_with_list(
+        &mut policy.rustc_cfg,
+        &mut policy.allowed_cfgs,
+        other.rustc_cfg,
+        other.allowed_cfgs,
+        trusted,
+    );
+    for (rule, other_action) in other.audit {
+        let action = policy.audit_action(rule).max(other_action);
+        policy.audit.insert(rule, action);
+    }
+    policy.max_file_size = match (policy.max_file_size, other.max_file_size) {
+        (Some(size), Some(other_size)) => Some(size.min(other_size)),
+        (size, other_size) => size.or(other_size),
+    };
+}
+
+fn tighten_with_list(
+    action: &mut Action,
+    list: &mut Vec<String>,
+    other_action: Action,
+    other_list: Vec<String>,
+    trusted: bool,
+) {
+    match (*action).cmp(&other_action) {
+        std::cmp::Ordering::Less => {
+            *action = other_action;
+            *list = other_list;
+        }
+        std::cmp::Ordering::Equal if trusted => {
+            for item in other_list {
+                if !list.contains(&item) {
+                    list.push(item);
+                }
+            }
         }
+        std::cmp::Ordering::Equal => list.retain(|item| other_list.contains(item)),
+        std::cmp::Ordering::Greater => {}
+    }
+}
+
+/// Returns the path of the nearest project configuration file, if any. If `output_path` (the file
+/// being linked) is given, only the parent of its target directory and that directory's ancestors
+/// are searched. Otherwise (e.g., for a subcommand), the current directory and its ancestors are
+/// searched.
+#[must_use]
+pub fn find_project_config(output_path: Option<&Path>) -> Option<PathBuf> {
+    // smoelius: When rustc links a registry or git dependency, the current directory is the
+    // dependency's package root. So searching from the current directory would find a
+    // configuration file shipped with the dependency.
+    let start = if let Some(output_path) = output_path {
+        target_dir_of(output_path)?.parent()?.to_path_buf()
+    } else {
+        env::current_dir().ok()?
+    };
+    find_project_config_from(&start)
+}
+
+fn find_project_config_from(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+fn seccomp_value(config: &Config) -> toml::Value {
+    let deny_table = |denied: &[String]| {
+        let mut table = toml::Table::new();
+        table.insert(String::from("deny"), toml::Value::from(denied.to_vec()));
+        table
+    };
+    let mut seccomp = deny_table(&config.denied_syscalls);
+    let mut packages = toml::Table::new();
+    for (pattern, denied) in &config.denied_syscalls_packages {
+        packages.insert(pattern.to_string(), toml::Value::from(deny_table(denied)));
     }
+    seccomp.insert(String::from("packages"), toml::Value::from(packages));
+    toml::Value::from(seccomp)
 }
 
-fn extend_directories(vec: &mut Vec<PathBuf>, value: Option<&toml::Value>) {
-    if let Some(array) = value.and_then(toml::Value::as_array) {
-        for item in array {
-            if let Some(s) = item.as_str() {
-                vec.push(expand_tilde(s));
+/// Returns the target directory containing `path`, i.e., the nearest ancestor containing a
+/// `CACHEDIR.TAG` file.
+fn target_dir_of(path: &Path) -> Option<&Path> {
+    path.ancestors()
+        .find(|dir| dir.join("CACHEDIR.TAG").is_file())
+}
+
+fn load_sandbox(
+    path: &Path,
+    contents: &str,
+    section: SandboxSection,
+) -> Result<(Rules, Vec<(Pattern, Rules)>)> {
+    let parse_rules = |section: RulesSection| -> Result<Rules> {
+        let mut writable = Vec::new();
+        for entry in section.writable {
+            // smoelius: `{VAR}` groups are expanded when the build script is run. So rather than
+            // expand a leading `~` here, map it to `{HOME}`.
+            let expanded = match entry.get_ref().strip_prefix('~') {
+                Some(suffix) if suffix.is_empty() || suffix.starts_with('/') => {
+                    format!("{{HOME}}{suffix}")
+                }
+                _ => entry.get_ref().clone(),
+            };
+            if !expanded.starts_with('/') && !expanded.starts_with('{') {
+                return Err(located(
+                    path,
+                    contents,
+                    entry.span(),
+                    "entries in `writable` must be absolute paths",
+                ));
             }
+            writable.push(expanded);
         }
+        Ok(Rules {
+            writable,
+            network: section.network,
+            hide_target_dir: section.hide_target_dir,
+        })
+    };
+
+    let rules = parse_rules(RulesSection {
+        writable: section.writable,
+        network: section.network,
+        hide_target_dir: section.hide_target_dir,
+    })?;
+    let mut packages = Vec::new();
+    for (entry, section) in section.packages {
+        let pattern = Pattern::package(entry.get_ref())
+            .map_err(|error| located(path, contents, entry.span(), &format!("{error:#}")))?;
+        packages.push((pattern, parse_rules(section)?));
     }
+
+    Ok((rules, packages))
 }
 
-fn extend_from_string_array(vec: &mut Vec<String>, value: Option<&toml::Value>) {
-    if let Some(array) = value.and_then(toml::Value::as_array) {
-        for item in array {
-            if let Some(s) = item.as_str() {
-                vec.push(s.to_owned());
+fn load_seccomp(
+    path: &Path,
+    contents: &str,
+    section: SeccompSection,
+) -> Result<(Vec<String>, PackageSyscalls)> {
+    let parse_deny = |deny: Vec<Spanned<String>>| -> Result<Vec<String>> {
+        let mut syscalls = Vec::new();
+        for entry in deny {
+            // smoelius: Syscall numbers are architecture specific, so names are checked only where
+            // the filter is implemented.
+            #[cfg(target_os = "linux")]
+            if util::seccomp::syscall_number(entry.get_ref()).is_none() {
+                return Err(located(
+                    path,
+                    contents,
+                    entry.span(),
+                    &format!("unknown syscall `{}`", entry.get_ref()),
+                ));
             }
+            syscalls.push(entry.into_inner());
         }
+        Ok(syscalls)
+    };
+
+    let denied = parse_deny(section.deny)?;
+    let mut packages = Vec::new();
+    for (entry, section) in section.packages {
+        let pattern = Pattern::package(entry.get_ref())
+            .map_err(|error| located(path, contents, entry.span(), &format!("{error:#}")))?;
+        packages.push((pattern, parse_deny(section.deny)?));
     }
+
+    Ok((denied, packages))
+}
+
+fn load_policy(
+    path: &Path,
+    contents: &str,
+    directives: Directives,
+    audit: AuditSection,
+) -> Result<Policy> {
+    let action = |name: Option<Spanned<ActionName>>, key: &str, restrictable: bool| {
+        let Some(name) = name else {
+            return Ok(Action::Allow);
+        };
+        match name.get_ref().action() {
+            Some(Action::Restrict) if !restrictable => Err(located(
+                path,
+                contents,
+                name.span(),
+                &format!("`{key}` cannot be `restrict`; use `allow` or `deny`"),
+            )),
+            Some(action) => Ok(action),
+            None if restrictable => Err(located(
+                path,
+                contents,
+                name.span(),
+                &format!("`{key}` cannot be `warn`; use `allow`, `restrict`, or `deny`"),
+            )),
+            None => Err(located(
+                path,
+                contents,
+                name.span(),
+                &format!("`{key}` cannot be `warn`; use `allow` or `deny`"),
+            )),
+        }
+    };
+
+    let audit_action = |rule: AuditRule, name: Option<Spanned<ActionName>>| {
+        let Some(name) = name else {
+            return Ok(None);
+        };
+        name.get_ref().audit_action().map(Some).ok_or_else(|| {
+            located(
+                path,
+                contents,
+                name.span(),
+                &format!(
+                    "`{}` cannot be `restrict`; use `allow`, `warn`, or `deny`",
+                    rule.key()
+                ),
+            )
+        })
+    };
+
+    // smoelius: `BUILD_WRAP_POLICY` is line-based, so its values cannot contain newlines.
+    let single_lines = |items: Vec<Spanned<String>>, key: &str| {
+        items
+            .into_iter()
+            .map(|item| {
+                if item.get_ref().contains(['\n', '\r']) {
+                    return Err(located(
+                        path,
+                        contents,
+                        item.span(),
+                        &format!("entries in `{key}` cannot contain newlines"),
+                    ));
+                }
+                Ok(item.into_inner())
+            })
+            .collect::<Result<Vec<_>>>()
+    };
+
+    Ok(Policy {
+        rustc_link_arg: action(directives.rustc_link_arg, "rustc-link-arg", false)?,
+        rustc_link_search: action(directives.rustc_link_search, "rustc-link-search", true)?,
+        rustc_env: action(directives.rustc_env, "rustc-env", false)?,
+        rustc_cfg: action(directives.rustc_cfg, "rustc-cfg", true)?,
+        link_search_directories: single_lines(
+            directives.link_search_directories,
+            "link-search-directories",
+        )?
+        .iter()
+        .map(|dir| expand_tilde(dir).to_string_lossy().into_owned())
+        .collect(),
+        allowed_cfgs: single_lines(directives.allowed_cfgs, "allowed-cfgs")?,
+        audit: [
+            (AuditRule::Symlinks, audit.symlinks),
+            (AuditRule::Executables, audit.executables),
+            (AuditRule::AbsoluteIncludes, audit.absolute_includes),
+            (AuditRule::LargeFiles, audit.large_files),
+        ]
+        .into_iter()
+        .filter_map(|(rule, name)| {
+            audit_action(rule, name)
+                .map(|action| action.map(|action| (rule, action)))
+                .transpose()
+        })
+        .collect::<Result<_>>()?,
+        max_file_size: audit.max_file_size,
+    })
+}
+
+/// Returns an error whose message is `msg`, prefixed with the file, line, and column at which
+/// `span` begins.
+fn located(path: &Path, contents: &str, span: Range<usize>, msg: &str) -> anyhow::Error {
+    let before = &contents[..span.start];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rfind('\n')
+        .map_or(before, |i| &before[i + 1..])
+        .chars()
+        .count()
+        + 1;
+    anyhow!("{}:{line}:{column}: {msg}", path.display())
 }
 
-fn expand_tilde(s: &str) -> PathBuf {
+fn expand_tilde(s: impl AsRef<OsStr>) -> PathBuf {
     expand_tilde_with_home(s, home::home_dir())
 }
 
-fn expand_tilde_with_home(s: &str, home: Option<PathBuf>) -> PathBuf {
+fn expand_tilde_with_home(s: impl AsRef<OsStr>, home: Option<PathBuf>) -> PathBuf {
+    let s = s.as_ref();
+
     if s == "~" {
         return home.unwrap_or_else(|| PathBuf::from(s));
     }
 
-    if let Some(suffix) = s.strip_prefix("~/")
+    if let Some(suffix) = s.as_bytes().strip_prefix(b"~/")
         && let Some(home) = home
     {
-        return home.join(suffix);
+        return home.join(OsStr::from_bytes(suffix));
     }
 
     PathBuf::from(s)
 }
 
+/// Reports the errors encountered while loading the configuration files, if any. The errors are
+/// fatal, unless `BUILD_WRAP_STRICT_CONFIG` is set to `0`, in which case they are reported as
+/// warnings and the files that caused them are ignored.
+///
+/// Failing closed is the default because ignoring a file could silently loosen the user's policy,
+/// e.g., by dropping a `[deny]` section because of a typo elsewhere in the file.
+pub fn check() -> Result<()> {
+    let errors = &loaded().errors;
+    if errors.is_empty() {
+        return Ok(());
+    }
+    if env::var("BUILD_WRAP_STRICT_CONFIG").is_ok_and(|value| value == "0") {
+        for error in errors {
+            eprintln!("warning: {error}");
+            eprintln!("warning: ignoring the file because `BUILD_WRAP_STRICT_CONFIG=0`");
+        }
+        return Ok(());
+    }
+    bail!(
+        "{}\nset `BUILD_WRAP_STRICT_CONFIG=0` to ignore files with errors",
+        errors.join("\n")
+    );
+}
+
+/// Describes the configuration files consulted and the effective configuration, for
+/// `build-wrap config check`.
+///
+/// # Errors
+///
+/// If errors were encountered while loading the configuration files.
+pub fn describe() -> Result<String> {
+    let loaded = loaded();
+    if !loaded.errors.is_empty() {
+        bail!("{}", loaded.errors.join("\n"));
+    }
+
+    let config = &loaded.config;
+    let policy = &config.policy;
+
+    let patterns_value = |patterns: &[Pattern]| {
+        toml::Value::from(patterns.iter().map(ToString::to_string).collect::<Vec<_>>())
+    };
+
+    let mut allow = toml::Table::new();
+    allow.insert(
+        String::from("directories"),
+        patterns_value(&config.directories),
+    );
+    allow.insert(String::from("packages"), patterns_value(&config.packages));
+
+    let mut deny = toml::Table::new();
+    deny.insert(String::from("packages"), patterns_value(&config.denied));
+
+    let mut profiles = toml::Table::new();
+    for (name, profile) in &config.profiles {
+        let mut table = toml::Table::new();
+        if let Some(cmd) = &profile.cmd {
+            table.insert(String::from("cmd"), toml::Value::from(cmd.clone()));
+        }
+        table.insert(
+            String::from("directories"),
+            patterns_value(&profile.directories),
+        );
+        table.insert(String::from("packages"), patterns_value(&profile.packages));
+        profiles.insert(name.clone(), toml::Value::from(table));
+    }
+
+    let rules_table = |rules: &Rules| {
+        let mut table = toml::Table::new();
+        table.insert(
+            String::from("writable"),
+            toml::Value::from(rules.writable.clone()),
+        );
+        table.insert(String::from("network"), toml::Value::from(rules.network));
+        table.insert(
+            String::from("hide-target-dir"),
+            toml::Value::from(rules.hide_target_dir),
+        );
+        table
+    };
+    let mut sandbox = rules_table(&config.sandbox);
+    let mut sandbox_packages = toml::Table::new();
+    for (pattern, rules) in &config.sandbox_packages {
+        sandbox_packages.insert(pattern.to_string(), toml::Value::from(rules_table(rules)));
+    }
+    sandbox.insert(
+        String::from("packages"),
+        toml::Value::from(sandbox_packages),
+    );
+
+    let mut directives = toml::Table::new();
+    for (key, action) in [
+        ("rustc-link-arg", policy.rustc_link_arg),
+        ("rustc-link-search", policy.rustc_link_search),
+        ("rustc-env", policy.rustc_env),
+        ("rustc-cfg", policy.rustc_cfg),
+    ] {
+        directives.insert(key.to_owned(), toml::Value::from(action.as_str()));
+    }
+    directives.insert(
+        String::from("link-search-directories"),
+        toml::Value::from(policy.link_search_directories.clone()),
+    );
+    directives.insert(
+        String::from("allowed-cfgs"),
+        toml::Value::from(policy.allowed_cfgs.clone()),
+    );
+
+    let mut table = toml::Table::new();
+    table.insert(String::from("allow"), toml::Value::from(allow));
+    table.insert(String::from("deny"), toml::Value::from(deny));
+    table.insert(String::from("profiles"), toml::Value::from(profiles));
+    table.insert(String::from("sandbox"), toml::Value::from(sandbox));
+    table.insert(String::from("seccomp"), seccomp_value(config));
+    table.insert(String::from("directives"), toml::Value::from(directives));
+    table.insert(String::from("audit"), audit_value(policy));
+    table.insert(String::from("proc-macros"), proc_macros_value(config));
+
+    let describe_path = |path: Option<&PathBuf>| {
+        path.map_or_else(
+            || String::from("none"),
+            |path| format!("{}", path.display()),
+        )
+    };
+    let project_status = match (&loaded.project_path, loaded.project_trusted) {
+        (None, _) => "",
+        (Some(_), true) => " (trusted)",
+        (Some(_), false) => " (not trusted)",
+    };
+
+    Ok(format!(
+        "# User configuration: {}\n# Project configuration: {}{project_status}\n\n{}",
+        describe_path(loaded.user_path.as_ref()),
+        describe_path(loaded.project_path.as_ref()),
+        toml::to_string(&table)?
+    ))
+}
+
+/// Returns the effective `[proc-macros]` section, for [`describe`].
+fn proc_macros_value(config: &Config) -> toml::Value {
+    let mut proc_macros = toml::Table::new();
+    proc_macros.insert(
+        String::from("policy"),
+        toml::Value::from(config.proc_macro_policy.as_str()),
+    );
+    proc_macros.insert(
+        String::from("packages"),
+        toml::Value::from(
+            config
+                .proc_macro_packages
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+        ),
+    );
+    proc_macros.insert(
+        String::from("fingerprints"),
+        toml::Value::from(
+            config
+                .proc_macro_fingerprints
+                .iter()
+                .map(|(entry, fingerprint)| (entry.clone(), toml::Value::from(fingerprint.clone())))
+                .collect::<toml::Table>(),
+        ),
+    );
+    toml::Value::from(proc_macros)
+}
+
+/// Returns the effective `[audit]` section, for [`describe`].
+fn audit_value(policy: &Policy) -> toml::Value {
+    let mut audit = toml::Table::new();
+    for rule in AuditRule::ALL {
+        audit.insert(
+            rule.key().to_owned(),
+            toml::Value::from(policy.audit_action(rule).as_str()),
+        );
+    }
+    if let Some(max_file_size) = policy.max_file_size {
+        // smoelius: TOML integers are signed.
+        audit.insert(
+            String::from("max-file-size"),
+            toml::Value::from(i64::try_from(max_file_size).unwrap_or(i64::MAX)),
+        );
+    }
+    toml::Value::from(audit)
+}
+
+/// Returns whether `path`, once canonicalized, is within an allowed directory.
+#[must_use]
 pub fn directory_allowed(path: &Path) -> bool {
-    CONFIG.directories.iter().any(|d| path.starts_with(d))
+    loaded().config.directory_allowed(path)
+}
+
+/// Returns whether package `name` is allowed, either in all versions (e.g., by an entry `foo`) or
+/// in `version` specifically (e.g., by an entry `foo@1.2.3`).
+#[must_use]
+pub fn package_allowed(name: &str, version: &str) -> bool {
+    loaded().config.package_allowed(name, version)
+}
+
+/// Returns whether package `name` at `version` is denied, i.e., must be sandboxed regardless of the
+/// other configuration.
+#[must_use]
+pub fn package_denied(name: &str, version: &str) -> bool {
+    loaded().config.package_denied(name, version)
+}
+
+/// Returns the command of profile `name`, which may be defined in a configuration file or built in.
+pub fn profile_cmd(name: &str) -> Result<String> {
+    loaded().config.profile_cmd(name)
+}
+
+/// Returns the name of the profile under which the build script of package `name` at `version`,
+/// located at `path`, should be executed (see [`Config::profile_for`]).
+#[must_use]
+pub fn profile_for(name: &str, version: &str, path: &Path) -> Option<&'static str> {
+    loaded().config.profile_for(name, version, path)
+}
+
+/// Returns the rules that extend the default sandbox for package `name` at `version` (see
+/// [`Config::sandbox_rules`]).
+#[must_use]
+pub fn sandbox_rules(name: &str, version: &str) -> Rules {
+    loaded().config.sandbox_rules(name, version)
+}
+
+/// Returns the syscalls denied to package `name` at `version` in addition to
+/// [`util::DEFAULT_DENIED_SYSCALLS`] (see [`Config::denied_syscalls`]).
+#[must_use]
+pub fn denied_syscalls(name: &str, version: &str) -> Vec<String> {
+    loaded().config.denied_syscalls(name, version)
+}
+
+/// Returns the policy for the `cargo:` directives that wrapped build scripts print.
+#[must_use]
+pub fn policy() -> &'static Policy {
+    loaded().config.policy()
+}
+
+/// Returns which proc macros may be linked.
+#[must_use]
+pub fn proc_macro_policy() -> proc_macros::Policy {
+    loaded().config.proc_macro_policy()
+}
+
+/// Returns whether proc macro `name` at `version` is listed in the `[proc-macros]` section's
+/// `packages`.
+#[must_use]
+pub fn proc_macro_approved(name: &str, version: &str) -> bool {
+    loaded().config.proc_macro_approved(name, version)
+}
+
+/// Returns the fingerprint recorded when proc macro `name` at `version` was first linked, if any.
+pub fn proc_macro_fingerprint(name: &str, version: &str) -> Option<&'static str> {
+    loaded()
+        .config
+        .proc_macro_fingerprints
+        .get(&format!("{name}@{version}"))
+        .map(String::as_str)
 }
 
-pub fn package_allowed() -> bool {
-    let Ok(name) = env::var("CARGO_PKG_NAME") else {
-        return false;
+fn package_matches(patterns: &[Pattern], name: &str, version: &str) -> bool {
+    pattern::allowed(patterns, &format!("{name}@{version}"))
+}
+
+/// Adds `entry` (e.g., `foo` or `foo@1.2.3`) to the `packages` list of config.toml's `[allow]`
+/// section, creating the file if necessary. Returns config.toml's path and whether `entry` was
+/// added, i.e., was not already present.
+pub fn allow_package(entry: &str) -> Result<(PathBuf, bool)> {
+    let base_directories = xdg::BaseDirectories::new();
+    let path = base_directories.place_config_file("build-wrap/config.toml")?;
+    let added = allow_package_in(&path, entry)?;
+    Ok((path, added))
+}
+
+fn allow_package_in(path: &Path, entry: &str) -> Result<bool> {
+    edit(path, |document| {
+        let packages = document
+            .entry("allow")
+            .or_insert_with(toml_edit::table)
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("`allow` is not a table"))?
+            .entry("packages")
+            .or_insert_with(|| toml_edit::value(toml_edit::Array::new()))
+            .as_array_mut()
+            .ok_or_else(|| anyhow!("`allow.packages` is not an array"))?;
+        if packages.iter().any(|value| value.as_str() == Some(entry)) {
+            return Ok(false);
+        }
+        packages.push(entry);
+        Ok(true)
+    })
+}
+
+/// Records `fingerprint` for `entry` (e.g., `foo@1.2.3`) in config.toml's
+/// `[proc-macros.fingerprints]` section, creating the file if necessary. Returns config.toml's path.
+pub fn record_proc_macro_fingerprint(entry: &str, fingerprint: &str) -> Result<PathBuf> {
+    let base_directories = xdg::BaseDirectories::new();
+    let path = base_directories.place_config_file("build-wrap/config.toml")?;
+    record_proc_macro_fingerprint_in(&path, entry, fingerprint)?;
+    Ok(path)
+}
+
+fn record_proc_macro_fingerprint_in(path: &Path, entry: &str, fingerprint: &str) -> Result<bool> {
+    edit(path, |document| {
+        let proc_macros = document
+            .entry("proc-macros")
+            .or_insert_with(|| {
+                // smoelius: Avoid an empty `[proc-macros]` header above `[proc-macros.fingerprints]`.
+                let mut table = toml_edit::Table::new();
+                table.set_implicit(true);
+                toml_edit::Item::Table(table)
+            })
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("`proc-macros` is not a table"))?;
+        let fingerprints = proc_macros
+            .entry("fingerprints")
+            .or_insert_with(toml_edit::table)
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("`proc-macros.fingerprints` is not a table"))?;
+        if fingerprints.get(entry).and_then(toml_edit::Item::as_str) == Some(fingerprint) {
+            return Ok(false);
+        }
+        fingerprints.insert(entry, toml_edit::value(fingerprint));
+        Ok(true)
+    })
+}
+
+/// Records in config.toml's `[trusted]` section that the project configuration file at
+/// `project_path` is trusted with its current contents. Returns config.toml's path and the file's
+/// SHA-256 hash.
+pub fn trust_project(project_path: &Path) -> Result<(PathBuf, String)> {
+    let project_path = canonicalize(project_path)
+        .with_context(|| format!("failed to canonicalize `{}`", project_path.display()))?;
+    let contents = read(&project_path)
+        .with_context(|| format!("failed to read `{}`", project_path.display()))?;
+    let sha256 = sha256_hex(&contents);
+    let base_directories = xdg::BaseDirectories::new();
+    let path = base_directories.place_config_file("build-wrap/config.toml")?;
+    trust_project_in(&path, &project_path, &sha256)?;
+    Ok((path, sha256))
+}
+
+fn trust_project_in(path: &Path, project_path: &Path, sha256: &str) -> Result<bool> {
+    let key = project_path
+        .to_str()
+        .ok_or_else(|| anyhow!("`{}` is not valid UTF-8", project_path.display()))?;
+    edit(path, |document| {
+        let trusted = document
+            .entry("trusted")
+            .or_insert_with(toml_edit::table)
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("`trusted` is not a table"))?;
+        if trusted.get(key).and_then(toml_edit::Item::as_str) == Some(sha256) {
+            return Ok(false);
+        }
+        trusted.insert(key, toml_edit::value(sha256));
+        Ok(true)
+    })
+}
+
+/// Applies `f` to config.toml at `path`, creating the file if necessary. The file is written only if
+/// `f` returns true.
+///
+/// The new contents are written to a temporary file that is then renamed over config.toml, so a
+/// reader (e.g., a concurrent link) sees either the old contents or the new ones, never a partially
+/// written file.
+fn edit(path: &Path, f: impl FnOnce(&mut toml_edit::DocumentMut) -> Result<bool>) -> Result<bool> {
+    // smoelius: If config.toml is a symlink (e.g., into a dotfiles repository), replace its target
+    // rather than the symlink.
+    let path = canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let parent = path
+        .parent()
+        .ok_or_else(|| anyhow!("failed to get `{}` parent", path.display()))?;
+
+    // smoelius: Cargo links proc macros in parallel, and each may record a fingerprint. Locking a
+    // separate file keeps concurrent edits from losing one another's changes. config.toml itself
+    // cannot be locked, since it is replaced rather than written in place.
+    let mut lock_path = path.clone().into_os_string();
+    lock_path.push(".lock");
+    let lock_path = PathBuf::from(lock_path);
+    let lock_file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&lock_path)
+        .with_context(|| format!("failed to open `{}`", lock_path.display()))?;
+    lock_file
+        .lock()
+        .with_context(|| format!("failed to lock `{}`", lock_path.display()))?;
+
+    let contents = match read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == ErrorKind::NotFound => String::new(),
+        Err(error) => {
+            return Err(error).with_context(|| format!("failed to read `{}`", path.display()));
+        }
     };
-    CONFIG.packages.iter().any(|p| p == &name)
+
+    // smoelius: `toml_edit` is used so that the user's comments and formatting are preserved.
+    let mut document = contents
+        .parse::<toml_edit::DocumentMut>()
+        .with_context(|| format!("failed to parse `{}`", path.display()))?;
+
+    if !f(&mut document)? {
+        return Ok(false);
+    }
+
+    let mut temp_file = tempfile::NamedTempFile::new_in(parent).with_context(|| {
+        format!(
+            "failed to create a temporary file in `{}`",
+            parent.display()
+        )
+    })?;
+    // smoelius: Keep config.toml's permissions, rather than the temporary file's (i.e., 0600).
+    if let Ok(metadata) = metadata(&path) {
+        temp_file
+            .as_file()
+            .set_permissions(metadata.permissions())?;
+    }
+    temp_file
+        .write_all(document.to_string().as_bytes())
+        .and_then(|()| temp_file.as_file().sync_all())
+        .with_context(|| format!("failed to write `{}`", temp_file.path().display()))?;
+    temp_file
+        .persist(&path)
+        .with_context(|| format!("failed to write `{}`", path.display()))?;
+
+    Ok(true)
 }
 
 #[cfg(test)]
 #[allow(clippy::disallowed_methods)]
 mod test {
     use super::*;
-    use std::{fs::write, path::PathBuf};
+    use std::{
+        fs::{Permissions, create_dir_all, read_to_string, write},
+        os::unix::fs::PermissionsExt,
+    };
 
     const EXAMPLE_CONFIG: &str = r#"
 [allow]
@@ -124,16 +1419,16 @@ packages = ["svm-rs-builds"]
         let path_buf = dir.path().join("config.toml");
         write(&path_buf, EXAMPLE_CONFIG).unwrap();
 
-        let config = Config::load_from(&path_buf);
+        let config = Config::load_from(&path_buf).unwrap();
 
         assert_eq!(
-            config.directories,
-            vec![
-                PathBuf::from("/home/user/project-a"),
-                PathBuf::from("/home/user/project-b")
-            ]
+            sources(&config.directories),
+            vec!["/home/user/project-a", "/home/user/project-b"]
+        );
+        assert_eq!(
+            sources(&config.packages),
+            vec!["aws-lc-fips-sys", "svm-rs-builds"]
         );
-        assert_eq!(config.packages, vec!["aws-lc-fips-sys", "svm-rs-builds"]);
     }
 
     #[test]
@@ -149,9 +1444,11 @@ packages = ["svm-rs-builds"]
 
     #[test]
     fn missing_config() {
-        let config = Config::load_from(Path::new("/nonexistent/config.toml"));
-        assert!(config.directories.is_empty());
-        assert!(config.packages.is_empty());
+        let error = Config::load_from(Path::new("/nonexistent/config.toml")).unwrap_err();
+        assert_eq!(
+            "failed to read `/nonexistent/config.toml`",
+            error.to_string()
+        );
     }
 
     #[test]
@@ -160,7 +1457,7 @@ packages = ["svm-rs-builds"]
         let path_buf = dir.path().join("config.toml");
         write(&path_buf, "").unwrap();
 
-        let config = Config::load_from(&path_buf);
+        let config = Config::load_from(&path_buf).unwrap();
         assert!(config.directories.is_empty());
         assert!(config.packages.is_empty());
     }
@@ -178,9 +1475,9 @@ packages = ["foo"]
         )
         .unwrap();
 
-        let config = Config::load_from(&path_buf);
+        let config = Config::load_from(&path_buf).unwrap();
         assert!(config.directories.is_empty());
-        assert_eq!(config.packages, vec!["foo"]);
+        assert_eq!(sources(&config.packages), vec!["foo"]);
     }
 
     #[test]
@@ -196,8 +1493,8 @@ directories = ["/tmp/myproject"]
         )
         .unwrap();
 
-        let config = Config::load_from(&path_buf);
-        assert_eq!(config.directories, vec![PathBuf::from("/tmp/myproject")]);
+        let config = Config::load_from(&path_buf).unwrap();
+        assert_eq!(sources(&config.directories), vec!["/tmp/myproject"]);
         assert!(config.packages.is_empty());
     }
 
@@ -214,8 +1511,723 @@ packages = ["foo"]
         )
         .unwrap();
 
-        let config = Config::load_from(&path_buf);
+        let error = Config::load_from(&path_buf).unwrap_err().to_string();
+        assert!(error.contains("line 2, column 2"), "{error}");
+        assert!(error.contains("unknown field `allowed`"), "{error}");
+    }
+
+    #[test]
+    fn non_string_package() {
+        let dir = tempfile::tempdir().unwrap();
+        let path_buf = dir.path().join("config.toml");
+        write(
+            &path_buf,
+            r#"
+[allow]
+packages = ["foo", 1]
+"#,
+        )
+        .unwrap();
+
+        let error = Config::load_from(&path_buf).unwrap_err().to_string();
+        assert!(error.contains("line 3, column 20"), "{error}");
+    }
+
+    const EXAMPLE_DIRECTIVES: &str = r#"
+[directives]
+rustc-link-arg = "deny"
+rustc-link-search = "restrict"
+rustc-env = "allow"
+rustc-cfg = "restrict"
+link-search-directories = ["/opt/mylib/lib"]
+allowed-cfgs = ["has_foo", 'backend="simd"']
+"#;
+
+    const EXAMPLE_PROC_MACROS: &str = r#"
+[proc-macros]
+policy = "tofu"
+packages = ["serde_derive", "thiserror-impl@2.*"]
+"#;
+
+    const EXAMPLE_AUDIT: &str = r#"
+[audit]
+executables = "warn"
+absolute-includes = "deny"
+max-file-size = 104857600
+"#;
+
+    #[test]
+    fn package_matches() {
+        let package_matches = |entry: &str, name: &str, version: &str| {
+            super::package_matches(&[Pattern::package(entry).unwrap()], name, version)
+        };
+        assert!(package_matches("foo", "foo", "1.2.3"));
+        assert!(package_matches("foo@1.2.3", "foo", "1.2.3"));
+        assert!(!package_matches("foo@1.2.4", "foo", "1.2.3"));
+        assert!(!package_matches("foo", "foo-sys", "1.2.3"));
+        assert!(package_matches("foo*", "foo-sys", "1.2.3"));
+    }
+
+    #[test]
+    fn directory_patterns() {
+        let dir = tempfile::tempdir().unwrap();
+        let path_buf = dir.path().join("config.toml");
+        write(
+            &path_buf,
+            r#"
+[allow]
+directories = ["{BUILD_WRAP_TEST_DIR}/*", "!{BUILD_WRAP_TEST_DIR}/b", "c"]
+"#,
+        )
+        .unwrap();
+
+        let var = |key: &str| match key {
+            "BUILD_WRAP_TEST_DIR" => Ok(OsString::from("/work")),
+            _ => Err(env::VarError::NotPresent),
+        };
+        let config = Config::load_from_with(&path_buf, &var).unwrap();
+        let base = canonicalize(dir.path()).unwrap();
+        assert_eq!(
+            sources(&config.directories),
+            vec![
+                String::from("/work/*"),
+                String::from("!/work/b"),
+                base.join("c").to_string_lossy().into_owned()
+            ]
+        );
+
+        let allowed = |path: &str| pattern::allowed(&config.directories, path);
+        assert!(allowed("/work/a/build.rs"));
+        assert!(!allowed("/work/b/build.rs"));
+        assert!(allowed(&base.join("c/build.rs").to_string_lossy()));
+
+        write(
+            &path_buf,
+            r#"
+[allow]
+directories = ["{BUILD_WRAP_UNDEFINED}"]
+"#,
+        )
+        .unwrap();
+        let error = Config::load_from(&path_buf).unwrap_err().to_string();
+        assert!(
+            error.contains(":3:16: environment variable `BUILD_WRAP_UNDEFINED` not found"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn allow_package_in() {
+        let dir = tempfile::tempdir().unwrap();
+        let path_buf = dir.path().join("config.toml");
+
+        assert!(super::allow_package_in(&path_buf, "foo").unwrap());
+        assert!(!super::allow_package_in(&path_buf, "foo").unwrap());
+
+        write(
+            &path_buf,
+            r#"# Comment
+[allow]
+directories = ["/home/user/project-a"]
+packages = ["foo"]
+"#,
+        )
+        .unwrap();
+        assert!(super::allow_package_in(&path_buf, "bar@1.2.3").unwrap());
+        assert_eq!(
+            r#"# Comment
+[allow]
+directories = ["/home/user/project-a"]
+packages = ["foo", "bar@1.2.3"]
+"#,
+            read_to_string(&path_buf).unwrap()
+        );
+
+        let config = Config::load_from(&path_buf).unwrap();
+        assert_eq!(sources(&config.packages), vec!["foo", "bar@1.2.3"]);
+
+        // smoelius: A symlinked config.toml remains a symlink, and its target keeps its
+        // permissions.
+        let target = dir.path().join("target.toml");
+        write(&target, "").unwrap();
+        std::fs::set_permissions(&target, Permissions::from_mode(0o644)).unwrap();
+        let link = dir.path().join("link.toml");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        assert!(super::allow_package_in(&link, "foo").unwrap());
+        assert!(link.symlink_metadata().unwrap().is_symlink());
+        assert_eq!(
+            "[allow]\npackages = [\"foo\"]\n",
+            read_to_string(&target).unwrap()
+        );
+        assert_eq!(
+            0o644,
+            metadata(&target).unwrap().permissions().mode() & 0o777
+        );
+    }
+
+    const EXAMPLE_PROJECT_CONFIG: &str = r#"
+[allow]
+packages = ["aws-lc-fips-sys"]
+
+[directives]
+rustc-link-arg = "deny"
+"#;
+
+    #[test]
+    fn merge_project() {
+        let dir = tempfile::tempdir().unwrap();
+        let project_path = dir.path().join(PROJECT_FILE_NAME);
+        write(&project_path, EXAMPLE_PROJECT_CONFIG).unwrap();
+
+        // smoelius: An untrusted project configuration file can tighten the policy, but cannot
+        // allow packages.
+        let project = || Config::load_from(&project_path).unwrap();
+
+        let mut config = Config::default();
+        assert!(!config.merge_project(&project_path, project()));
         assert!(config.packages.is_empty());
+        assert_eq!(Action::Deny, config.policy.rustc_link_arg);
+
+        let mut config = Config {
+            trusted: vec![(
+                canonicalize(&project_path).unwrap(),
+                sha256_hex(EXAMPLE_PROJECT_CONFIG.as_bytes()),
+            )],
+            ..Config::default()
+        };
+        assert!(config.merge_project(&project_path, project()));
+        assert_eq!(sources(&config.packages), vec!["aws-lc-fips-sys"]);
+        assert_eq!(Action::Deny, config.policy.rustc_link_arg);
+
+        // smoelius: Modifying the file revokes trust.
+        write(&project_path, EXAMPLE_PROJECT_CONFIG.to_owned() + "\n").unwrap();
+        config.packages.clear();
+        assert!(!config.merge_project(&project_path, project()));
+        assert!(config.packages.is_empty());
+    }
+
+    #[test]
+    fn deny() {
+        let dir = tempfile::tempdir().unwrap();
+        let user_path = dir.path().join("config.toml");
+        let project_path = dir.path().join(PROJECT_FILE_NAME);
+        write(&user_path, "[allow]\npackages = [\"foo\"]\n").unwrap();
+        write(&project_path, "[deny]\npackages = [\"foo@1.*\"]\n").unwrap();
+
+        // smoelius: An untrusted project configuration file can deny packages.
+        let loaded = Loaded::load_from(Some(user_path), Some(project_path.clone()));
+        assert!(loaded.errors.is_empty());
+        assert!(!loaded.project_trusted);
+        assert!(super::package_matches(
+            &loaded.config.packages,
+            "foo",
+            "1.0.0"
+        ));
+        assert!(super::package_matches(
+            &loaded.config.denied,
+            "foo",
+            "1.0.0"
+        ));
+        assert!(!super::package_matches(
+            &loaded.config.denied,
+            "foo",
+            "2.0.0"
+        ));
+
+        write(&project_path, "[deny]\npackages = [\"!foo\"]\n").unwrap();
+        let error = Config::load_from(&project_path).unwrap_err().to_string();
+        assert!(
+            error.contains(":2:13: negated entries are not allowed in `[deny]`"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn proc_macros() {
+        let dir = tempfile::tempdir().unwrap();
+        let user_path = dir.path().join("config.toml");
+        let project_path = dir.path().join(PROJECT_FILE_NAME);
+        write(&user_path, EXAMPLE_PROC_MACROS).unwrap();
+        write(
+            &project_path,
+            "[proc-macros]\npolicy = \"allowlist\"\npackages = [\"foo\"]\n",
+        )
+        .unwrap();
+
+        // smoelius: An untrusted project configuration file can make the policy stricter, but
+        // cannot approve proc macros.
+        let loaded = Loaded::load_from(Some(user_path.clone()), Some(project_path.clone()));
+        assert!(loaded.errors.is_empty());
+        let config = &loaded.config;
+        assert_eq!(proc_macros::Policy::Allowlist, config.proc_macro_policy);
+        assert_eq!(
+            vec!["serde_derive", "thiserror-impl@2.*"],
+            sources(&config.proc_macro_packages)
+        );
+        assert!(super::package_matches(
+            &config.proc_macro_packages,
+            "thiserror-impl",
+            "2.0.18"
+        ));
+        assert!(!super::package_matches(
+            &config.proc_macro_packages,
+            "foo",
+            "1.0.0"
+        ));
+
+        assert!(
+            super::record_proc_macro_fingerprint_in(&user_path, "foo-derive@1.0.0", "0123")
+                .unwrap()
+        );
+        assert!(
+            !super::record_proc_macro_fingerprint_in(&user_path, "foo-derive@1.0.0", "0123")
+                .unwrap()
+        );
+        let config = Config::load_from(&user_path).unwrap();
+        assert_eq!(
+            Some("0123"),
+            config
+                .proc_macro_fingerprints
+                .get("foo-derive@1.0.0")
+                .map(String::as_str)
+        );
+
+        // smoelius: Concurrent edits do not lose one another's changes, and a concurrent reader
+        // never sees a partially written file.
+        std::thread::scope(|scope| {
+            for i in 0..8 {
+                let user_path = &user_path;
+                scope.spawn(move || {
+                    super::record_proc_macro_fingerprint_in(
+                        user_path,
+                        &format!("bar-{i}@1.0.0"),
+                        "0",
+                    )
+                    .unwrap()
+                });
+            }
+            scope.spawn(|| {
+                for _ in 0..256 {
+                    let contents = read_to_string(&user_path).unwrap();
+                    assert!(contents.contains("\"foo-derive@1.0.0\""), "{contents:?}");
+                }
+            });
+        });
+        let config = Config::load_from(&user_path).unwrap();
+        assert!((0..8).all(|i| {
+            config
+                .proc_macro_fingerprints
+                .contains_key(&format!("bar-{i}@1.0.0"))
+        }));
+
+        // smoelius: An untrusted project configuration file cannot enable recording fingerprints.
+        for (user_policy, expected) in [
+            ("allow", proc_macros::Policy::Allowlist),
+            ("tofu", proc_macros::Policy::Tofu),
+        ] {
+            write(
+                &user_path,
+                format!("[proc-macros]\npolicy = \"{user_policy}\"\n"),
+            )
+            .unwrap();
+            write(&project_path, "[proc-macros]\npolicy = \"tofu\"\n").unwrap();
+            let loaded = Loaded::load_from(Some(user_path.clone()), Some(project_path.clone()));
+            assert!(loaded.errors.is_empty());
+            assert_eq!(expected, loaded.config.proc_macro_policy);
+        }
+
+        // smoelius: The table is added without an empty `[proc-macros]` header.
+        let path_buf = dir.path().join("empty.toml");
+        assert!(
+            super::record_proc_macro_fingerprint_in(&path_buf, "foo-derive@1.0.0", "0123").unwrap()
+        );
+        assert_eq!(
+            "[proc-macros.fingerprints]\n\"foo-derive@1.0.0\" = \"0123\"\n",
+            read_to_string(&path_buf).unwrap()
+        );
+    }
+
+    #[test]
+    fn profiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let user_path = dir.path().join("config.toml");
+        let project_path = dir.path().join(PROJECT_FILE_NAME);
+        write(
+            &user_path,
+            r#"
+[profiles.sandboxer]
+cmd = "sandboxer.sh -- {}"
+packages = ["foo"]
+
+[profiles.network]
+packages = ["bar"]
+"#,
+        )
+        .unwrap();
+        write(
+            &project_path,
+            r#"
+[profiles.unsandboxed]
+cmd = "{}"
+packages = ["*"]
+"#,
+        )
+        .unwrap();
+
+        // smoelius: An untrusted project configuration file cannot define profiles.
+        let loaded = Loaded::load_from(Some(user_path.clone()), Some(project_path));
+        assert!(loaded.errors.is_empty());
+        let profiles = &loaded.config.profiles;
+        assert_eq!(
+            vec!["network", "sandboxer"],
+            profiles.keys().collect::<Vec<_>>()
+        );
+        assert_eq!(None, profiles["network"].cmd);
+        assert_eq!(
+            Some("sandboxer.sh -- {}"),
+            profiles["sandboxer"].cmd.as_deref()
+        );
+
+        // smoelius: A trusted project configuration file can define profiles, but cannot redefine
+        // built-in ones.
+        let contents = "[profiles.strict]\ncmd = \"{}\"\n\n[profiles.custom]\ncmd = \"{}\"\n";
+        let project_path = dir.path().join("trusted.toml");
+        write(&project_path, contents).unwrap();
+        let mut config = Config {
+            trusted: vec![(
+                canonicalize(&project_path).unwrap(),
+                sha256_hex(contents.as_bytes()),
+            )],
+            ..Config::default()
+        };
+        assert!(config.merge_project(&project_path, Config::load_from(&project_path).unwrap()));
+        assert_eq!(None, config.profiles["strict"].cmd);
+        assert_eq!(Some("{}"), config.profiles["custom"].cmd.as_deref());
+
+        write(&user_path, "\n[profiles.custom]\npackages = [\"foo\"]\n").unwrap();
+        let error = Config::load_from(&user_path).unwrap_err().to_string();
+        assert!(
+            error.contains(":2:11: `custom` is not a built-in profile, so it must have a `cmd`"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn sandbox() {
+        let dir = tempfile::tempdir().unwrap();
+        let user_path = dir.path().join("config.toml");
+        let project_path = dir.path().join(PROJECT_FILE_NAME);
+        write(
+            &user_path,
+            r#"
+[sandbox]
+writable = ["{CARGO_HOME_RESOLVED}/registry/cache"]
+
+[sandbox.packages."openssl-*"]
+writable = ["/opt/openssl", "{CARGO_HOME_RESOLVED}/registry/cache"]
+hide-target-dir = true
+
+[sandbox.packages."svm-rs-builds@0.5.*"]
+network = true
+"#,
+        )
+        .unwrap();
+        write(&project_path, "[sandbox]\nnetwork = true\n").unwrap();
+
+        // smoelius: An untrusted project configuration file cannot extend the sandbox.
+        let loaded = Loaded::load_from(Some(user_path.clone()), Some(project_path));
+        assert!(loaded.errors.is_empty());
+        let config = &loaded.config;
+        let cache = String::from("{CARGO_HOME_RESOLVED}/registry/cache");
+        assert_eq!(
+            Rules {
+                writable: vec![cache.clone()],
+                network: false,
+                hide_target_dir: false,
+            },
+            config.sandbox_rules("foo", "1.0.0")
+        );
+        assert_eq!(
+            Rules {
+                writable: vec![cache.clone(), String::from("/opt/openssl")],
+                network: false,
+                hide_target_dir: true,
+            },
+            config.sandbox_rules("openssl-sys", "0.9.0")
+        );
+        assert_eq!(
+            Rules {
+                writable: vec![cache],
+                network: true,
+                hide_target_dir: false,
+            },
+            config.sandbox_rules("svm-rs-builds", "0.5.1")
+        );
+        assert!(!config.sandbox_rules("svm-rs-builds", "0.4.0").network);
+
+        // smoelius: A leading `~` becomes `{HOME}`, which is expanded when the build script is run.
+        write(&user_path, "[sandbox]\nwritable = [\"~/.cache\", \"~x\"]\n").unwrap();
+        let error = Config::load_from(&user_path).unwrap_err().to_string();
+        assert!(
+            error.contains(":2:25: entries in `writable` must be absolute paths"),
+            "{error}"
+        );
+        write(&user_path, "[sandbox]\nwritable = [\"~/.cache\"]\n").unwrap();
+        let config = Config::load_from(&user_path).unwrap();
+        assert_eq!(vec!["{HOME}/.cache"], config.sandbox.writable);
+
+        write(&user_path, "[sandbox]\nwritable = [\"relative\"]\n").unwrap();
+        let error = Config::load_from(&user_path).unwrap_err().to_string();
+        assert!(
+            error.contains(":2:13: entries in `writable` must be absolute paths"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn seccomp() {
+        let dir = tempfile::tempdir().unwrap();
+        let user_path = dir.path().join("config.toml");
+        let project_path = dir.path().join(PROJECT_FILE_NAME);
+        write(
+            &user_path,
+            r#"
+[seccomp]
+deny = ["mount"]
+
+[seccomp.packages."openssl-*"]
+deny = ["io_uring_setup", "mount"]
+"#,
+        )
+        .unwrap();
+        write(&project_path, "[seccomp]\ndeny = [\"unshare\"]\n").unwrap();
+
+        // smoelius: Even an untrusted project configuration file can deny syscalls.
+        let loaded = Loaded::load_from(Some(user_path.clone()), Some(project_path));
+        assert!(loaded.errors.is_empty());
+        let config = &loaded.config;
+        assert_eq!(
+            vec!["mount", "unshare"],
+            config.denied_syscalls("foo", "1.0.0")
+        );
+        assert_eq!(
+            vec!["io_uring_setup", "mount", "unshare"],
+            config.denied_syscalls("openssl-sys", "0.9.0")
+        );
+
+        #[cfg(target_os = "linux")]
+        {
+            write(&user_path, "[seccomp]\ndeny = [\"fork\"]\n").unwrap();
+            let error = Config::load_from(&user_path).unwrap_err().to_string();
+            assert!(error.contains(":2:9: unknown syscall `fork`"), "{error}");
+        }
+    }
+
+    #[test]
+    fn tighten() {
+        let user = || Policy {
+            rustc_link_arg: Action::Deny,
+            rustc_link_search: Action::Restrict,
+            link_search_directories: vec![String::from("/a"), String::from("/b")],
+            ..Policy::default()
+        };
+        let project = || Policy {
+            rustc_link_search: Action::Restrict,
+            link_search_directories: vec![String::from("/b"), String::from("/c")],
+            rustc_cfg: Action::Restrict,
+            allowed_cfgs: vec![String::from("foo")],
+            ..Policy::default()
+        };
+
+        let mut policy = user();
+        super::tighten(&mut policy, project(), false);
+        assert_eq!(
+            Policy {
+                link_search_directories: vec![String::from("/b")],
+                rustc_cfg: Action::Restrict,
+                allowed_cfgs: vec![String::from("foo")],
+                ..user()
+            },
+            policy
+        );
+
+        let mut policy = user();
+        super::tighten(&mut policy, project(), true);
+        assert_eq!(vec!["/a", "/b", "/c"], policy.link_search_directories);
+
+        // smoelius: A less strict project policy does not loosen the user policy.
+        let mut policy = user();
+        super::tighten(&mut policy, Policy::default(), true);
+        assert_eq!(user(), policy);
+
+        // smoelius: An audit rule that a project sets to `allow` keeps its default action.
+        let mut policy = Policy {
+            max_file_size: Some(1024),
+            ..Policy::default()
+        };
+        super::tighten(
+            &mut policy,
+            Policy {
+                audit: [
+                    (AuditRule::Symlinks, AuditAction::Allow),
+                    (AuditRule::Executables, AuditAction::Warn),
+                ]
+                .into_iter()
+                .collect(),
+                max_file_size: Some(4096),
+                ..Policy::default()
+            },
+            true,
+        );
+        assert_eq!(AuditAction::Warn, policy.audit_action(AuditRule::Symlinks));
+        assert_eq!(
+            AuditAction::Warn,
+            policy.audit_action(AuditRule::Executables)
+        );
+        assert_eq!(Some(1024), policy.max_file_size);
+    }
+
+    #[test]
+    fn find_project_config_from() {
+        let dir = tempfile::tempdir().unwrap();
+        let project_path = dir.path().join(PROJECT_FILE_NAME);
+        let target_dir = dir.path().join("target");
+        let build_dir = target_dir.join("debug/build/foo-0123456789abcdef");
+        create_dir_all(&build_dir).unwrap();
+        write(target_dir.join("CACHEDIR.TAG"), "").unwrap();
+        write(build_dir.join(crate::sidecar::FILE_NAME), "").unwrap();
+
+        assert_eq!(None, super::find_project_config_from(&build_dir));
+
+        write(&project_path, "").unwrap();
+        assert_eq!(
+            Some(project_path.clone()),
+            super::find_project_config_from(&build_dir)
+        );
+        assert_eq!(
+            Some(project_path),
+            super::find_project_config_from(&dir.path().join("src"))
+        );
+    }
+
+    #[test]
+    fn trust_project_in() {
+        let dir = tempfile::tempdir().unwrap();
+        let path_buf = dir.path().join("config.toml");
+        let project_path = Path::new("/home/user/project/build-wrap.toml");
+
+        assert!(super::trust_project_in(&path_buf, project_path, "0123").unwrap());
+        assert!(!super::trust_project_in(&path_buf, project_path, "0123").unwrap());
+        assert!(super::trust_project_in(&path_buf, project_path, "4567").unwrap());
+
+        let config = Config::load_from(&path_buf).unwrap();
+        assert_eq!(
+            config.trusted,
+            vec![(project_path.to_path_buf(), String::from("4567"))]
+        );
+    }
+
+    #[test]
+    fn parse_directives() {
+        let dir = tempfile::tempdir().unwrap();
+        let path_buf = dir.path().join("config.toml");
+        write(&path_buf, EXAMPLE_DIRECTIVES).unwrap();
+
+        let config = Config::load_from(&path_buf).unwrap();
+        assert_eq!(
+            config.policy,
+            Policy {
+                rustc_link_arg: Action::Deny,
+                rustc_link_search: Action::Restrict,
+                rustc_env: Action::Allow,
+                rustc_cfg: Action::Restrict,
+                link_search_directories: vec![String::from("/opt/mylib/lib")],
+                allowed_cfgs: vec![String::from("has_foo"), String::from(r#"backend="simd""#)],
+                ..Policy::default()
+            }
+        );
+    }
+
+    #[test]
+    fn parse_audit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path_buf = dir.path().join("config.toml");
+        write(&path_buf, EXAMPLE_AUDIT).unwrap();
+
+        let config = Config::load_from(&path_buf).unwrap();
+        let policy = &config.policy;
+        assert_eq!(AuditAction::Warn, policy.audit_action(AuditRule::Symlinks));
+        assert_eq!(
+            AuditAction::Warn,
+            policy.audit_action(AuditRule::Executables)
+        );
+        assert_eq!(
+            AuditAction::Deny,
+            policy.audit_action(AuditRule::AbsoluteIncludes)
+        );
+        assert_eq!(
+            AuditAction::Warn,
+            policy.audit_action(AuditRule::LargeFiles)
+        );
+        assert_eq!(Some(104_857_600), policy.max_file_size);
+
+        write(&path_buf, "[audit]\nsymlinks = \"restrict\"\n").unwrap();
+        let error = Config::load_from(&path_buf).unwrap_err().to_string();
+        assert!(
+            error.contains(":2:12: `symlinks` cannot be `restrict`"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn invalid_directives() {
+        let dir = tempfile::tempdir().unwrap();
+        let path_buf = dir.path().join("config.toml");
+
+        let cases = [
+            ("rustc-link-arg = \"forbid\"", "line 3, column 18"),
+            ("rustc-env = true", "line 3, column 13"),
+            ("rustc-link-searches = \"deny\"", "line 3, column 1"),
+            (
+                "rustc-env = \"restrict\"",
+                ":3:13: `rustc-env` cannot be `restrict`",
+            ),
+            (
+                "rustc-cfg = \"warn\"",
+                ":3:13: `rustc-cfg` cannot be `warn`",
+            ),
+            (
+                r#"allowed-cfgs = ["ok", "not\nok"]"#,
+                ":3:23: entries in `allowed-cfgs` cannot contain newlines",
+            ),
+        ];
+
+        for (line, expected) in cases {
+            write(&path_buf, format!("\n[directives]\n{line}\n")).unwrap();
+            let error = Config::load_from(&path_buf).unwrap_err().to_string();
+            assert!(error.contains(expected), "{line:?}: {error}");
+        }
+    }
+
+    #[test]
+    fn project_error_preserves_user_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let user_path = dir.path().join("config.toml");
+        let project_path = dir.path().join(PROJECT_FILE_NAME);
+        write(&user_path, EXAMPLE_DIRECTIVES).unwrap();
+        write(
+            &project_path,
+            "[directives]\nrustc-link-arg = \"allow\"\nbogus = 1\n",
+        )
+        .unwrap();
+
+        let loaded = Loaded::load_from(Some(user_path), Some(project_path));
+        assert_eq!(1, loaded.errors.len());
+        assert_eq!(Action::Deny, loaded.config.policy.rustc_link_arg);
+    }
+
+    fn sources(patterns: &[Pattern]) -> Vec<String> {
+        patterns.iter().map(ToString::to_string).collect()
     }
 
     #[test]
@@ -225,4 +2237,28 @@ packages = ["foo"]
             Some("toml"),
         );
     }
+
+    #[test]
+    fn readme_contains_example_directives() {
+        super::super::util::assert_readme_contains_code_block(
+            EXAMPLE_DIRECTIVES.trim().lines(),
+            Some("toml"),
+        );
+    }
+
+    #[test]
+    fn readme_contains_example_proc_macros() {
+        super::super::util::assert_readme_contains_code_block(
+            EXAMPLE_PROC_MACROS.trim().lines(),
+            Some("toml"),
+        );
+    }
+
+    #[test]
+    fn readme_contains_example_audit() {
+        super::super::util::assert_readme_contains_code_block(
+            EXAMPLE_AUDIT.trim().lines(),
+            Some("toml"),
+        );
+    }
 }
@@ -0,0 +1,109 @@
+//! `build-wrap` as a library, so that other tools (e.g., build orchestrators or Cargo plugins) can
+//! reuse its semantics rather than invoking the `build-wrap` binary and parsing its output.
+//!
+//! The main entry points are:
+//!
+//! - the policy model: [`Policy`] (what `cargo:` directives wrapped build scripts may print and how
+//!   their `OUT_DIR`s are audited), [`Rules`] and [`Sandbox`] (how the default sandbox is
+//!   extended), and [`ProcMacroPolicy`] (which proc macros may be linked)
+//! - configuration loading: [`Config::load`], which reads a user configuration file (e.g.,
+//!   `$HOME/.config/build-wrap/config.toml`) and a project configuration file, and merges them as
+//!   `build-wrap` does
+//! - command expansion: [`split_and_expand_with`] and [`expand_with`], which expand a
+//!   `BUILD_WRAP_CMD` exactly as wrapped build scripts do
+//! - wrapper generation: [`wrap`], which replaces a linked build script with a wrapped one, and
+//!   [`link`], which does everything `build-wrap` does when invoked as a linker
+//!
+//! Items are public only if they are part of this API. The API follows semantic versioning along
+//! with the `build-wrap` binary.
+
+#![warn(missing_docs)]
+
+mod cli;
+mod commands;
+mod config;
+mod linking;
+mod pattern;
+mod proc_macros;
+mod profile;
+mod sandbox;
+mod sidecar;
+mod util;
+mod wrapper;
+
+pub use config::{Config, PROJECT_FILE_NAME};
+pub use linking::{link, wrap};
+pub use proc_macros::Policy as ProcMacroPolicy;
+pub use sandbox::{Rules, Sandbox};
+pub use sidecar::Sidecar;
+pub use util::{Action, AuditAction, AuditRule, Policy, expand_with, split_and_expand_with};
+pub use wrapper::Sibling;
+
+// smoelius: `run` is public only so that src/main.rs can call it. It is not part of the API.
+#[doc(hidden)]
+pub use cli::run;
+
+/// Returns the command build scripts are executed under on Linux, unless `BUILD_WRAP_CMD` is set
+/// or configuration says otherwise
+#[must_use]
+pub fn linux_default_cmd() -> String {
+    sandbox::Sandbox::default().linux_cmd()
+}
+
+/// Returns the command build scripts are executed under on macOS, unless `BUILD_WRAP_CMD` or
+/// `BUILD_WRAP_PROFILE` is set or configuration says otherwise
+#[must_use]
+pub fn macos_default_cmd() -> String {
+    sandbox::Sandbox::default().macos_cmd()
+}
+
+/// Returns the default command for the current platform, i.e., [`linux_default_cmd`] or
+/// [`macos_default_cmd`]
+#[must_use]
+pub fn default_cmd() -> String {
+    sandbox::Sandbox::default().cmd()
+}
+
+// smoelius: The following blog post is a useful `sandbox-exec` reference:
+// https://7402.org/blog/2020/macos-sandboxing-of-folder.html
+/// The command build scripts are executed under on macOS when `BUILD_WRAP_PROFILE` is set, unless
+/// `BUILD_WRAP_CMD` is set or configuration says otherwise
+pub const MACOS_PROFILE_CMD: &str = "sandbox-exec -f {BUILD_WRAP_PROFILE_PATH} {}";
+
+#[cfg(test)]
+mod test {
+    use regex::Regex;
+
+    #[test]
+    fn readme_contains_linux_default_cmd_with_comments() {
+        super::util::assert_readme_contains_code_block(
+            super::linux_default_cmd().lines().map(str::trim_start),
+            Some("sh"),
+        );
+    }
+
+    #[test]
+    fn readme_contains_linux_default_cmd_on_one_line() {
+        let re = Regex::new("\\s+").unwrap();
+        let cmd = re
+            .replace_all(&super::linux_default_cmd(), " ")
+            .into_owned();
+        super::util::assert_readme_contains_code_block(std::iter::once(cmd), Some("sh"));
+    }
+
+    #[test]
+    fn readme_contains_macos_default_cmd() {
+        super::util::assert_readme_contains_code_block(
+            super::macos_default_cmd().lines().map(str::trim),
+            Some("sh"),
+        );
+    }
+
+    #[test]
+    fn readme_contains_macos_profile_cmd() {
+        super::util::assert_readme_contains_code_block(
+            std::iter::once(super::MACOS_PROFILE_CMD),
+            Some("sh"),
+        );
+    }
+}
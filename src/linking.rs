@@ -1,36 +1,105 @@
-use crate::{DEFAULT_CMD, config, util, wrapper};
-use anyhow::Result;
+//! What `build-wrap` does when invoked as a linker: link, then wrap build scripts and check proc
+//! macros.
+
+use crate::{
+    MACOS_PROFILE_CMD, config, default_cmd, proc_macros, profile,
+    sandbox::Sandbox,
+    sidecar::Sidecar,
+    util,
+    wrapper::{self, Sibling},
+};
+use anyhow::{Result, anyhow};
 use std::{
-    env::{var, var_os},
-    ffi::OsStr,
-    fs::copy,
+    env::{current_exe, var, var_os},
+    ffi::{OsStr, OsString},
+    fs::{copy, rename},
     path::{Path, PathBuf},
     process::Command,
 };
 
-pub fn link(args: &[String]) -> Result<()> {
+/// Links with the arguments `args` (which begin with the program name, as from
+/// [`std::env::args_os`]) using the linker that `BUILD_WRAP_LD` names (by default, `cc`). Then,
+/// if the output file is a build script, wraps it and writes its sidecar, and if the output file
+/// is a proc macro, applies the `[proc-macros]` policy to it.
+pub fn link(args: &[OsString]) -> Result<()> {
     let linker = linker()?;
 
     let mut command = Command::new(&linker);
     command.args(&args[1..]);
     util::exec_forwarding_output(command, true)?;
 
+    let Some(path) = output_path(args.iter()) else {
+        return Ok(());
+    };
+
+    config::load_for_output(&path);
+
+    // smoelius: Proc macros run unsandboxed inside `rustc`, so the most `build-wrap` can do is
+    // refuse to link one that the user has not approved.
+    if proc_macros::is_proc_macro(args, &path)? {
+        config::check()?;
+        proc_macros::check(&path)?;
+    } else if is_build_script(&path) {
+        config::check()?;
+        let sidecar = Sidecar::new(&path);
+        let sidecar = if let Some(exemption) = link_exemption(&sidecar) {
+            sidecar.exempt(exemption)
+        } else {
+            let (sibling, cmd) = wrap(&linker, &sidecar)?;
+            sidecar.wrapped(sibling, cmd)
+        };
+        sidecar.write()?;
+        // smoelius: Cargo relinks a build script to the same path, so a sibling left over from a
+        // previous link is no longer referred to by any wrapper.
+        if let Some(parent) = path.parent() {
+            let _: Vec<PathBuf> = wrapper::remove_stale_siblings(parent, false)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Like [`exemption`], but also considers the environment the linker was invoked in.
+fn link_exemption(sidecar: &Sidecar) -> Option<String> {
+    // smoelius: A denied package is never exempt, not even when a `RUSTC_WRAPPER` (e.g., `sccache`)
+    // is set.
+    if config::package_denied(&sidecar.package, &sidecar.version) {
+        return None;
+    }
+
     // smoelius: Don't wrap if `RUSTC_WRAPPER` or `RUSTC_WORKSPACE_WRAPPER` is set. That usually
     // means that Clippy or Dylint is being run.
-    if var_os("RUSTC_WRAPPER").is_none()
-        && var_os("RUSTC_WORKSPACE_WRAPPER").is_none()
-        && let Some(path) = output_path(args.iter())
-        && is_build_script(&path)
-        && !config::directory_allowed(&path)
-        && !config::package_allowed()
-    {
-        wrap(&linker, &path)?;
+    for key in ["RUSTC_WRAPPER", "RUSTC_WORKSPACE_WRAPPER"] {
+        if var_os(key).is_some() {
+            return Some(format!("`{key}` is set"));
+        }
     }
 
-    Ok(())
+    exemption(sidecar)
+}
+
+/// Returns why the build script described by `sidecar` should not be wrapped, or `None` if it
+/// should be. A package denied by config.toml is never exempt.
+#[must_use]
+pub fn exemption(sidecar: &Sidecar) -> Option<String> {
+    if config::package_denied(&sidecar.package, &sidecar.version) {
+        return None;
+    }
+
+    if config::directory_allowed(&sidecar.original) {
+        return Some(String::from("directory is allowed by config.toml"));
+    }
+
+    if config::package_allowed(&sidecar.package, &sidecar.version) {
+        return Some(String::from("package is allowed by config.toml"));
+    }
+
+    None
 }
 
-fn linker() -> Result<String> {
+/// Returns the linker that `build-wrap` invokes, i.e., the value of `BUILD_WRAP_LD` if set, and
+/// [`util::DEFAULT_LD`] otherwise.
+pub fn linker() -> Result<String> {
     if var_os("BUILD_WRAP_LD").is_some() {
         var("BUILD_WRAP_LD").map_err(Into::into)
     } else {
@@ -38,9 +107,23 @@ fn linker() -> Result<String> {
     }
 }
 
-fn output_path<'a, I>(mut iter: I) -> Option<PathBuf>
+/// Returns the command that build scripts linked now would be executed under, i.e., the value of
+/// `BUILD_WRAP_CMD` if set, [`MACOS_PROFILE_CMD`] if on macOS and `BUILD_WRAP_PROFILE` is set, and
+/// [`default_cmd`] otherwise.
+pub fn build_wrap_cmd() -> Result<String> {
+    if var_os("BUILD_WRAP_CMD").is_some() {
+        var("BUILD_WRAP_CMD").map_err(Into::into)
+    } else if cfg!(target_os = "macos") && var_os("BUILD_WRAP_PROFILE").is_some() {
+        Ok(String::from(MACOS_PROFILE_CMD))
+    } else {
+        Ok(default_cmd())
+    }
+}
+
+/// Returns the argument that follows `-o` in linker arguments `iter`, if any.
+pub fn output_path<'a, I>(mut iter: I) -> Option<PathBuf>
 where
-    I: Iterator<Item = &'a String>,
+    I: Iterator<Item = &'a OsString>,
 {
     while let Some(arg) = iter.next() {
         if arg == "-o"
@@ -59,13 +142,123 @@ fn is_build_script(path: &Path) -> bool {
         .is_some_and(|name| name.starts_with("build_script_"))
 }
 
-fn wrap(linker: &str, build_script_path: &Path) -> Result<()> {
-    let wrapper_package = wrapper::package(build_script_path)?;
+/// Returns the command that the build script described by `sidecar` should be executed under,
+/// i.e., in order of precedence:
+///
+/// - the `strict` profile's command, if the package is denied by config.toml
+/// - the command of the profile named by `BUILD_WRAP_PROFILE_NAME`, if set
+/// - the command of the profile that names the package or directory in config.toml, if any
+/// - the default command extended with the package's `[sandbox]` rules, if there are any and
+///   neither `BUILD_WRAP_CMD` nor `BUILD_WRAP_PROFILE` is set
+/// - [`build_wrap_cmd`]
+pub fn wrap_cmd(sidecar: &Sidecar, denied: bool) -> Result<String> {
+    // smoelius: As with an explicit command (see below), silently discarding `[sandbox]` rules
+    // could leave the user believing the rules are in effect.
+    let rules = config::sandbox_rules(&sidecar.package, &sidecar.version);
+    let warn_rules_ignored = |reason: &str| {
+        if !rules.is_empty() {
+            eprintln!(
+                "warning: ignoring `[sandbox]` rules for `{}@{}` because {reason}",
+                sidecar.package, sidecar.version
+            );
+        }
+    };
+
+    if denied {
+        // smoelius: Silently discarding an explicit command could leave the user believing their
+        // command is in effect.
+        for key in ["BUILD_WRAP_CMD", "BUILD_WRAP_PROFILE_NAME"] {
+            if var_os(key).is_some() {
+                eprintln!(
+                    "warning: ignoring `{key}` for `{}@{}`, which is denied by config.toml; its \
+                     build script is executed under the `{}` profile",
+                    sidecar.package,
+                    sidecar.version,
+                    profile::STRICT
+                );
+            }
+        }
+        warn_rules_ignored("it is denied by config.toml");
+        // smoelius: Use the built-in command, even if the user's configuration overrides `strict`.
+        // A `[deny]` entry should only ever tighten the policy.
+        return profile::builtin_cmd(profile::STRICT)
+            .ok_or_else(|| anyhow!("`{}` is not a built-in profile", profile::STRICT));
+    }
+
+    if var_os("BUILD_WRAP_PROFILE_NAME").is_some() {
+        warn_rules_ignored("`BUILD_WRAP_PROFILE_NAME` is set");
+        return config::profile_cmd(&var("BUILD_WRAP_PROFILE_NAME")?);
+    }
+
+    if let Some(name) = config::profile_for(&sidecar.package, &sidecar.version, &sidecar.original) {
+        warn_rules_ignored(&format!(
+            "its build script is executed under the `{name}` profile"
+        ));
+        return config::profile_cmd(name);
+    }
+
+    for key in ["BUILD_WRAP_CMD", "BUILD_WRAP_PROFILE"] {
+        if var_os(key).is_some() {
+            warn_rules_ignored(&format!("`{key}` is set"));
+            return build_wrap_cmd();
+        }
+    }
+
+    if !rules.is_empty() {
+        if rules.hide_target_dir && !cfg!(target_os = "linux") {
+            eprintln!(
+                "warning: ignoring `hide-target-dir` for `{}@{}` because it is supported only on \
+                 Linux",
+                sidecar.package, sidecar.version
+            );
+        }
+        return Ok(Sandbox::new(&rules).cmd());
+    }
+
+    build_wrap_cmd()
+}
+
+/// Wraps the build script described by `sidecar`. Returns the renamed original build script and the
+/// command the build script is executed under (see `wrap_cmd`).
+pub fn wrap(linker: &str, sidecar: &Sidecar) -> Result<(Sibling, String)> {
+    let build_script_path = &sidecar.original;
+    let denied = config::package_denied(&sidecar.package, &sidecar.version);
+    let cmd = wrap_cmd(sidecar, denied)?;
+
+    let (wrapper_package, sibling) = wrapper::package(build_script_path)?;
+
+    // smoelius: `wrapper::package` moved the original build script to the sibling's path. If the
+    // wrapper cannot be built or installed, move it back so that the build script is not lost.
+    build_wrapper(linker, sidecar, denied, &cmd, wrapper_package.path()).inspect_err(|_| {
+        let _: std::io::Result<()> = rename(&sibling.path, build_script_path);
+    })?;
+
+    Ok((sibling, cmd))
+}
 
+/// Builds the wrapper package at `wrapper_package` and copies the resulting wrapper build script
+/// to `sidecar.original`.
+fn build_wrapper(
+    linker: &str,
+    sidecar: &Sidecar,
+    denied: bool,
+    cmd: &str,
+    wrapper_package: &Path,
+) -> Result<()> {
     let mut command = util::cargo_build();
-    if var_os("BUILD_WRAP_CMD").is_none() {
-        command.env("BUILD_WRAP_CMD", DEFAULT_CMD);
+    command.env("BUILD_WRAP_CMD", cmd);
+    // smoelius: A denied package's build script must not fall back to running unsandboxed, e.g.,
+    // because `BUILD_WRAP_ALLOW` is set when the build script is run.
+    if denied {
+        command.env("BUILD_WRAP_DENIED", "1");
     }
+    command.env("BUILD_WRAP_POLICY", config::policy().to_env_string());
+    command.env(
+        "BUILD_WRAP_DENIED_SYSCALLS",
+        config::denied_syscalls(&sidecar.package, &sidecar.version).join(","),
+    );
+    // smoelius: The wrapper build script invokes `build-wrap allow` to persist interactive answers.
+    command.env("BUILD_WRAP_EXE", current_exe()?);
     // smoelius: When building the wrapper, do *not* use `build-wrap`.
     command.args([
         "--config",
@@ -75,14 +268,12 @@ fn wrap(linker: &str, build_script_path: &Path) -> Result<()> {
     command.env_remove("CARGO_TARGET_DIR");
     // smoelius: `cd` into `wrapper_package`'s directory to avoid any `.cargo/config.toml` that may
     // be in ancestors of the current directory.
-    command.current_dir(&wrapper_package);
+    command.current_dir(wrapper_package);
     util::exec_forwarding_output(command, true)?;
 
     copy(
-        wrapper_package
-            .path()
-            .join("target/debug/build_script_wrapper"),
-        build_script_path,
+        wrapper_package.join("target/debug/build_script_wrapper"),
+        &sidecar.original,
     )?;
 
     Ok(())
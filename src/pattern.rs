@@ -0,0 +1,287 @@
+//! Patterns in the `directories` and `packages` lists of a configuration file
+
+use anyhow::{Context, Result, bail};
+use regex::Regex;
+use std::{
+    fmt::{self, Write},
+    fs::canonicalize,
+    path::{Component, Path, PathBuf},
+};
+
+/// Prefix of an entry that is a regular expression rather than a glob
+const REGEX_PREFIX: &str = "re:";
+
+/// A `directories` or `packages` entry. An entry may contain the glob metacharacters `*`, `?`, and
+/// `[...]`, or may be a regular expression prefixed with `re:`. Either form may be negated by
+/// prefixing it with `!`.
+#[derive(Debug)]
+pub struct Pattern {
+    negated: bool,
+    /// The entry with any `!` removed and, for a directory glob, variables expanded and the path
+    /// canonicalized
+    source: String,
+    regex: Regex,
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.negated {
+            f.write_str("!")?;
+        }
+        f.write_str(&self.source)
+    }
+}
+
+impl Pattern {
+    /// Parses a `directories` entry. `resolve` is applied to the entry after any `!` is removed,
+    /// and should expand variables and make the path absolute. The longest prefix of the result
+    /// not containing glob metacharacters is canonicalized, if it exists, so that symlinked
+    /// directories match. A directory pattern matches a directory and all of its subdirectories.
+    ///
+    /// An entry of the form `re:REGEX` is not resolved. `REGEX` must match a prefix of the path
+    /// ending at a `/` or the end of the path.
+    pub fn directory(entry: &str, resolve: impl FnOnce(&str) -> Result<PathBuf>) -> Result<Self> {
+        let (negated, entry) = strip_negation(entry);
+        if let Some(regex) = entry.strip_prefix(REGEX_PREFIX) {
+            return Self::regex(negated, entry, &format!("^(?:{regex})(/.*)?$"));
+        }
+        let path = canonicalize_literal_prefix(&resolve(entry)?);
+        let Some(source) = path.to_str() else {
+            bail!("`{}` is not valid UTF-8", path.display());
+        };
+        let glob = glob_to_regex(source.trim_end_matches('/'), '/')?;
+        let regex = Regex::new(&format!("^{glob}(/.*)?$"))?;
+        Ok(Self {
+            negated,
+            source: source.to_owned(),
+            regex,
+        })
+    }
+
+    /// Parses a `packages` entry, i.e., a name glob optionally followed by `@` and a version glob,
+    /// or `re:REGEX`, where `REGEX` must match the entirety of `NAME@VERSION`.
+    pub fn package(entry: &str) -> Result<Self> {
+        let (negated, entry) = strip_negation(entry);
+        if let Some(regex) = entry.strip_prefix(REGEX_PREFIX) {
+            return Self::regex(negated, entry, &format!("^(?:{regex})$"));
+        }
+        let (name, version) = entry.split_once('@').unwrap_or((entry, "*"));
+        if name.is_empty() || version.is_empty() {
+            bail!("`{entry}` is not of the form `PACKAGE` or `PACKAGE@VERSION`");
+        }
+        let name = glob_to_regex(name, '@')?;
+        let version = glob_to_regex(version, '@')?;
+        let regex = Regex::new(&format!("^{name}@{version}$"))?;
+        Ok(Self {
+            negated,
+            source: entry.to_owned(),
+            regex,
+        })
+    }
+
+    fn regex(negated: bool, entry: &str, regex: &str) -> Result<Self> {
+        let regex = Regex::new(regex).with_context(|| format!("invalid regex in `{entry}`"))?;
+        Ok(Self {
+            negated,
+            source: entry.to_owned(),
+            regex,
+        })
+    }
+
+    /// Returns whether the entry begins with `!`, i.e., excludes what it matches.
+    #[must_use]
+    pub fn is_negated(&self) -> bool {
+        self.negated
+    }
+
+    fn is_match(&self, s: &str) -> bool {
+        self.regex.is_match(s)
+    }
+}
+
+/// Returns whether `s` matches at least one of the non-negated patterns in `patterns` and none of
+/// the negated ones.
+#[must_use]
+pub fn allowed(patterns: &[Pattern], s: &str) -> bool {
+    let mut allowed = false;
+    for pattern in patterns.iter().filter(|pattern| pattern.is_match(s)) {
+        if pattern.negated {
+            return false;
+        }
+        allowed = true;
+    }
+    allowed
+}
+
+fn strip_negation(entry: &str) -> (bool, &str) {
+    entry
+        .strip_prefix('!')
+        .map_or((false, entry), |entry| (true, entry))
+}
+
+fn is_glob(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+fn canonicalize_literal_prefix(path: &Path) -> PathBuf {
+    let mut components = path.components();
+    let mut prefix = PathBuf::new();
+    for component in components.by_ref() {
+        if let Component::Normal(name) = component
+            && name.to_str().is_some_and(is_glob)
+        {
+            let mut path = canonicalize(&prefix).unwrap_or(prefix);
+            // smoelius: `components` has already consumed `component`, so put it back.
+            path.push(name);
+            if !components.as_path().as_os_str().is_empty() {
+                path.push(components.as_path());
+            }
+            return path;
+        }
+        prefix.push(component);
+    }
+    canonicalize(&prefix).unwrap_or(prefix)
+}
+
+/// Translates `glob` into a regular expression. `*` and `?` do not match `separator`, but `**`
+/// does.
+fn glob_to_regex(glob: &str, separator: char) -> Result<String> {
+    let escaped_separator = regex::escape(&separator.to_string());
+    let not_separator = format!("[^{escaped_separator}]");
+    let mut regex = String::new();
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.next_if_eq(&'*').is_some() => {
+                // smoelius: `a/**/b` should match `a/b`.
+                if chars.next_if_eq(&separator).is_some() {
+                    write!(regex, "(.*{escaped_separator})?").unwrap();
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => write!(regex, "{not_separator}*").unwrap(),
+            '?' => regex.push_str(&not_separator),
+            '[' => {
+                let mut class = String::from("[");
+                if chars.next_if_eq(&'!').is_some() {
+                    class.push('^');
+                }
+                // smoelius: As in a shell, a `]` immediately after the `[` or `[!` is literal.
+                if chars.next_if_eq(&']').is_some() {
+                    class.push_str("\\]");
+                }
+                loop {
+                    match chars.next() {
+                        Some(']') => break,
+                        Some(c @ ('\\' | '[' | '&' | '~' | '^')) => {
+                            class.push('\\');
+                            class.push(c);
+                        }
+                        Some(c) => class.push(c),
+                        None => bail!("unclosed `[` in `{glob}`"),
+                    }
+                }
+                class.push(']');
+                regex.push_str(&class);
+            }
+            _ => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    Ok(regex)
+}
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod test {
+    use super::{Pattern, allowed};
+    use std::{os::unix::fs::symlink, path::PathBuf};
+
+    fn directories(entries: &[&str]) -> Vec<Pattern> {
+        entries
+            .iter()
+            .map(|entry| Pattern::directory(entry, |s| Ok(PathBuf::from(s))).unwrap())
+            .collect()
+    }
+
+    fn packages(entries: &[&str]) -> Vec<Pattern> {
+        entries
+            .iter()
+            .map(|entry| Pattern::package(entry).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn directory_globs() {
+        let patterns = directories(&["/work/*/vendor", "/src/**/third_party", "!/src/x/**"]);
+        assert!(allowed(&patterns, "/work/a/vendor"));
+        assert!(allowed(&patterns, "/work/a/vendor/b/build.rs"));
+        assert!(!allowed(&patterns, "/work/a/b/vendor"));
+        assert!(!allowed(&patterns, "/work/a/vendored"));
+        assert!(allowed(&patterns, "/src/third_party"));
+        assert!(allowed(&patterns, "/src/a/b/third_party/c"));
+        assert!(!allowed(&patterns, "/src/x/third_party"));
+    }
+
+    #[test]
+    fn package_globs() {
+        let patterns = packages(&[
+            "aws-lc-*",
+            "!aws-lc-fips-sys",
+            "ring@0.17.*",
+            "openssl-sys@[!0]*",
+        ]);
+        assert!(allowed(&patterns, "aws-lc-sys@0.1.0"));
+        assert!(!allowed(&patterns, "aws-lc-fips-sys@0.1.0"));
+        assert!(allowed(&patterns, "ring@0.17.8"));
+        assert!(!allowed(&patterns, "ring@0.16.20"));
+        assert!(allowed(&patterns, "openssl-sys@1.0.0"));
+        assert!(!allowed(&patterns, "openssl-sys@0.9.0"));
+        assert!(!allowed(&patterns, "ringo@0.17.8"));
+    }
+
+    #[test]
+    fn regexes() {
+        let patterns = packages(&["re:(openssl|ring)-sys@0\\..*", "!re:openssl-sys@0\\.9\\.1"]);
+        assert!(allowed(&patterns, "openssl-sys@0.10.0"));
+        assert!(allowed(&patterns, "ring-sys@0.1.0"));
+        assert!(!allowed(&patterns, "openssl-sys@0.9.1"));
+        assert!(!allowed(&patterns, "openssl-sys@1.0.0"));
+        assert!(!allowed(&patterns, "xopenssl-sys@0.10.0"));
+        assert_eq!("!re:openssl-sys@0\\.9\\.1", patterns[1].to_string());
+
+        let patterns = directories(&["re:/work/[a-z]+/vendor"]);
+        assert!(allowed(&patterns, "/work/a/vendor"));
+        assert!(allowed(&patterns, "/work/a/vendor/b"));
+        assert!(!allowed(&patterns, "/work/a/vendored"));
+        assert!(!allowed(&patterns, "/work/A/vendor"));
+        assert!(!allowed(&patterns, "/x/work/a/vendor"));
+    }
+
+    #[test]
+    fn invalid_globs() {
+        assert!(Pattern::package("re:(").is_err());
+        assert!(Pattern::package("foo[").is_err());
+        assert!(Pattern::package("@1.0.0").is_err());
+        assert!(Pattern::directory("/a/[b", |s| Ok(PathBuf::from(s))).is_err());
+    }
+
+    #[test]
+    fn symlinked_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().join("real");
+        let link = dir.path().join("link");
+        std::fs::create_dir(&real).unwrap();
+        symlink(&real, &link).unwrap();
+
+        let real = real.canonicalize().unwrap();
+        for entry in [link.clone(), link.join("*")] {
+            let pattern =
+                Pattern::directory(entry.to_str().unwrap(), |s| Ok(PathBuf::from(s))).unwrap();
+            assert!(
+                pattern.is_match(real.join("x").to_str().unwrap()),
+                "{pattern}"
+            );
+        }
+    }
+}
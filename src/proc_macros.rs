@@ -0,0 +1,288 @@
+//! Recognition of proc macros at link time, and the `[proc-macros]` policy that decides whether
+//! they may be linked. A proc macro runs inside `rustc`, so it cannot be sandboxed the way a build
+//! script is. The policy at least gives users a review gate for such code.
+
+use crate::{config, sidecar::ProcMacroSidecar, util::sha256_hex};
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::{
+    ffi::{OsStr, OsString},
+    fs::{read, read_dir, remove_file, symlink_metadata},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+/// Prefix of the symbol through which `rustc` finds a proc-macro crate's macros. Every proc macro
+/// exports one, and no other kind of crate does.
+const DECLS_SYMBOL_PREFIX: &[u8] = b"__rustc_proc_macro_decls_";
+
+/// Which proc macros may be linked. Ordered from least to most strict.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Ord, PartialEq, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub enum Policy {
+    /// Any proc macro may be linked.
+    #[default]
+    Allow,
+    /// A proc macro that is not in the `packages` list may be linked if its sources match the
+    /// fingerprint recorded the first time it was linked. If no fingerprint was recorded, one is.
+    Tofu,
+    /// Only proc macros in the `packages` list may be linked.
+    Allowlist,
+}
+
+impl Policy {
+    /// Returns the policy's name, as written in a configuration file.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Allow => "allow",
+            Self::Tofu => "tofu",
+            Self::Allowlist => "allowlist",
+        }
+    }
+}
+
+/// Returns whether the file at `path`, which was just linked with arguments `args`, is a proc
+/// macro.
+pub fn is_proc_macro(args: &[OsString], path: &Path) -> Result<bool> {
+    if !path
+        .extension()
+        .is_some_and(|extension| extension == "so" || extension == "dylib")
+    {
+        return Ok(false);
+    }
+    // smoelius: `rustc` passes the linker the list of symbols that a dylib exports (a version
+    // script on Linux, and an exported symbols list on macOS). Unlike the dylib itself, the list is
+    // small.
+    let Some(list) = exported_symbols_list(args) else {
+        return Ok(false);
+    };
+    let contents = read(&list).with_context(|| format!("failed to read `{}`", list.display()))?;
+    Ok(contents
+        .windows(DECLS_SYMBOL_PREFIX.len())
+        .any(|window| window == DECLS_SYMBOL_PREFIX))
+}
+
+/// Returns the path of the version script or exported symbols list in linker arguments `args`, if
+/// any.
+fn exported_symbols_list(args: &[OsString]) -> Option<PathBuf> {
+    // smoelius: Options passed through a compiler driver such as `cc` are prefixed with `-Wl,` and
+    // separated by commas.
+    let words = args
+        .iter()
+        .flat_map(|arg| match arg.as_bytes().strip_prefix(b"-Wl,") {
+            Some(options) => options.split(|&c| c == b',').collect(),
+            None => vec![arg.as_bytes()],
+        })
+        .collect::<Vec<_>>();
+    let mut iter = words.into_iter();
+    while let Some(word) = iter.next() {
+        if let Some(path) = word.strip_prefix(b"--version-script=") {
+            return Some(PathBuf::from(OsStr::from_bytes(path)));
+        }
+        if word == b"--version-script" || word == b"-exported_symbols_list" {
+            return iter
+                .next()
+                .map(|path| PathBuf::from(OsStr::from_bytes(path)));
+        }
+    }
+    None
+}
+
+/// Applies the `[proc-macros]` policy to the proc macro at `path`, and records the outcome in a
+/// sidecar next to it. If the policy refuses the proc macro, `path` is removed so that `rustc`
+/// cannot load it.
+pub fn check(path: &Path) -> Result<()> {
+    let mut sidecar = ProcMacroSidecar::new(path);
+    let manifest_dir = std::env::var_os("CARGO_MANIFEST_DIR").unwrap_or_default();
+
+    let result = approve(&mut sidecar, Path::new(&manifest_dir));
+    if let Err(error) = &result {
+        sidecar.approval = format!("{error}");
+        sidecar.refused = true;
+        remove_file(path).with_context(|| format!("failed to remove `{}`", path.display()))?;
+    }
+    sidecar.write()?;
+
+    result
+}
+
+/// Determines whether the proc macro described by `sidecar`, whose package is in `manifest_dir`,
+/// may be linked, and fills in `sidecar`'s `approval` and `fingerprint` accordingly.
+fn approve(sidecar: &mut ProcMacroSidecar, manifest_dir: &Path) -> Result<()> {
+    let name = &sidecar.package;
+    let version = &sidecar.version;
+    let entry = format!("{name}@{version}");
+
+    let policy = config::proc_macro_policy();
+
+    if policy == Policy::Allow {
+        sidecar.approval = String::from("allowed by default");
+        return Ok(());
+    }
+
+    if config::proc_macro_approved(name, version) {
+        sidecar.approval = String::from("approved by config.toml");
+        return Ok(());
+    }
+
+    if policy == Policy::Allowlist {
+        bail!(
+            "proc macro `{entry}` is not approved; to approve it, add it to the `packages` list of \
+             the `[proc-macros]` section of config.toml"
+        );
+    }
+
+    // smoelius: The sources of a path dependency or workspace member are expected to change, so
+    // only packages that Cargo extracted from a registry or git repository are pinned.
+    if !manifest_dir.join(".cargo-ok").try_exists()? {
+        sidecar.approval = String::from("not pinned (not from a registry or git repository)");
+        return Ok(());
+    }
+
+    let fingerprint = fingerprint(manifest_dir)?;
+    sidecar.fingerprint = Some(fingerprint.clone());
+
+    match config::proc_macro_fingerprint(name, version) {
+        Some(expected) if expected == fingerprint => {
+            sidecar.approval = String::from("matches the fingerprint recorded on first use");
+        }
+        Some(expected) => bail!(
+            "the sources of proc macro `{entry}` changed since it was first linked (fingerprint \
+             `{expected}`, now `{fingerprint}`); if the change is expected, remove `{entry}` from \
+             the `[proc-macros.fingerprints]` section of config.toml"
+        ),
+        None => {
+            let path = config::record_proc_macro_fingerprint(&entry, &fingerprint)?;
+            eprintln!(
+                "build-wrap: trusting proc macro `{entry}` on first use; recorded its fingerprint \
+                 in `{}`",
+                path.display()
+            );
+            sidecar.approval = String::from("trusted on first use");
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the SHA-256 fingerprint of the package sources in `manifest_dir`, i.e., the hash of a
+/// list of the files' hashes and relative paths, sorted by path. Directories containing a
+/// `CACHEDIR.TAG` (e.g., target directories) and `.git` directories are skipped.
+pub fn fingerprint(manifest_dir: &Path) -> Result<String> {
+    let mut files = Vec::new();
+    let mut stack = vec![manifest_dir.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        if dir.join("CACHEDIR.TAG").try_exists()? {
+            continue;
+        }
+        for result in read_dir(&dir)
+            .with_context(|| format!("failed to read directory `{}`", dir.display()))?
+        {
+            let path = result?.path();
+            let file_type = symlink_metadata(&path)?.file_type();
+            if file_type.is_dir() {
+                if path.file_name().is_some_and(|name| name != ".git") {
+                    stack.push(path);
+                }
+            } else if file_type.is_file() {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+
+    let mut list = Vec::new();
+    for path in files {
+        let contents =
+            read(&path).with_context(|| format!("failed to read `{}`", path.display()))?;
+        let relative = path.strip_prefix(manifest_dir).unwrap_or(&path);
+        list.extend_from_slice(sha256_hex(&contents).as_bytes());
+        list.extend_from_slice(b"  ");
+        list.extend_from_slice(relative.as_os_str().as_bytes());
+        list.push(b'\n');
+    }
+    Ok(sha256_hex(&list))
+}
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod test {
+    use std::{
+        ffi::OsString,
+        fs::{create_dir_all, write},
+        path::Path,
+    };
+
+    #[test]
+    fn is_proc_macro() {
+        let dir = tempfile::tempdir().unwrap();
+        let proc_macro_list = dir.path().join("proc_macro_list");
+        let cdylib_list = dir.path().join("cdylib_list");
+        write(
+            &proc_macro_list,
+            "{\n  global:\n    __rustc_proc_macro_decls_0123__;\n\n  local:\n    *;\n};\n",
+        )
+        .unwrap();
+        write(
+            &cdylib_list,
+            "{\n  global:\n    foo;\n\n  local:\n    *;\n};\n",
+        )
+        .unwrap();
+
+        let args = |list: &Path, separate: bool| {
+            let mut args = vec![OsString::from("cc")];
+            if separate {
+                args.push(OsString::from("-Wl,-exported_symbols_list"));
+                args.push(OsString::from(format!("-Wl,{}", list.display())));
+            } else {
+                args.push(OsString::from(format!(
+                    "-Wl,--version-script={}",
+                    list.display()
+                )));
+            }
+            args.extend(["-o", "output"].map(OsString::from));
+            args
+        };
+        let so = Path::new("libfoo-0123456789abcdef.so");
+        let rlib = Path::new("libfoo-0123456789abcdef.rlib");
+
+        for separate in [false, true] {
+            assert!(super::is_proc_macro(&args(&proc_macro_list, separate), so).unwrap());
+            assert!(!super::is_proc_macro(&args(&cdylib_list, separate), so).unwrap());
+            assert!(!super::is_proc_macro(&args(&proc_macro_list, separate), rlib).unwrap());
+        }
+        assert!(!super::is_proc_macro(&[OsString::from("cc")], so).unwrap());
+        assert!(
+            super::is_proc_macro(
+                &[
+                    OsString::from("--version-script"),
+                    proc_macro_list.into_os_string()
+                ],
+                so
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn fingerprint() {
+        let dir = tempfile::tempdir().unwrap();
+        create_dir_all(dir.path().join("src")).unwrap();
+        write(dir.path().join("Cargo.toml"), "[package]\n").unwrap();
+        write(dir.path().join("src/lib.rs"), "").unwrap();
+
+        let fingerprint = super::fingerprint(dir.path()).unwrap();
+
+        // smoelius: Build products and version-control metadata do not affect the fingerprint.
+        create_dir_all(dir.path().join("target")).unwrap();
+        write(dir.path().join("target/CACHEDIR.TAG"), "").unwrap();
+        write(dir.path().join("target/output"), "").unwrap();
+        create_dir_all(dir.path().join(".git")).unwrap();
+        write(dir.path().join(".git/HEAD"), "").unwrap();
+        assert_eq!(fingerprint, super::fingerprint(dir.path()).unwrap());
+
+        write(dir.path().join("src/lib.rs"), "fn foo() {}").unwrap();
+        assert_ne!(fingerprint, super::fingerprint(dir.path()).unwrap());
+    }
+}
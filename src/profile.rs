@@ -0,0 +1,88 @@
+//! Built-in sandbox profiles, i.e., named values of `BUILD_WRAP_CMD`
+
+use crate::sandbox::Sandbox;
+
+/// Names of the built-in profiles
+pub const BUILTIN_NAMES: &[&str] = &["default", "native-build", "network", "strict"];
+
+/// Name of the profile under which the build scripts of packages in `[deny]` are executed
+pub const STRICT: &str = "strict";
+
+/// Returns the command of the built-in profile `name`, or `None` if there is no such profile. The
+/// command is generated from the profile's [`Sandbox`].
+#[must_use]
+pub fn builtin_cmd(name: &str) -> Option<String> {
+    Sandbox::builtin(name).map(|sandbox| sandbox.cmd())
+}
+
+/// Double quotes `s` so that `BUILD_WRAP_CMD` splitting treats it as a single argument, in which
+/// variables are still expanded.
+#[must_use]
+pub fn quote(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        if matches!(c, '$' | '`' | '"' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod test {
+    use super::BUILTIN_NAMES;
+    use crate::{
+        sandbox::Sandbox,
+        util::{assert_readme_contains_code_block, split_and_expand_with},
+    };
+    use regex::Regex;
+    use std::{ffi::OsString, path::Path};
+
+    #[test]
+    fn builtin_profiles_exist() {
+        for name in BUILTIN_NAMES {
+            assert!(Sandbox::builtin(name).is_some(), "{name}");
+        }
+        assert!(Sandbox::builtin("unknown").is_none());
+    }
+
+    #[test]
+    fn readme_contains_linux_cmds() {
+        let re = Regex::new("\\s+").unwrap();
+        let lines = ["native-build", "network", "strict"].map(|name| {
+            let cmd = Sandbox::builtin(name).unwrap().linux_cmd();
+            format!("{name}: {}", re.replace_all(&cmd, " "))
+        });
+        assert_readme_contains_code_block(lines.iter().map(String::as_str), Some("sh"));
+    }
+
+    #[test]
+    fn builtin_profiles_expand() {
+        let build_script_path = Path::new("/build_script_build");
+        for name in BUILTIN_NAMES {
+            let sandbox = Sandbox::builtin(name).unwrap();
+            for cmd in [sandbox.linux_cmd(), sandbox.macos_cmd()] {
+                let args =
+                    split_and_expand_with(&cmd, build_script_path, |_| Ok(OsString::from("/x")))
+                        .unwrap();
+                assert_eq!(
+                    Some("/build_script_build"),
+                    args.last().and_then(|arg| arg.to_str())
+                );
+            }
+        }
+
+        // smoelius: A macOS profile passed with `-p` is a single argument.
+        let args = split_and_expand_with(
+            &Sandbox::builtin("network").unwrap().macos_cmd(),
+            build_script_path,
+            |_| Ok(OsString::from("/x")),
+        )
+        .unwrap();
+        let profile = args[args.len() - 2].to_str().unwrap();
+        assert!(profile.starts_with("(version 1)\n"), "{profile}");
+        assert!(profile.contains("(allow network*)"), "{profile}");
+    }
+}
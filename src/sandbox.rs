@@ -0,0 +1,635 @@
+//! A typed model of the default sandbox, from which both the Linux command (a `bwrap` invocation)
+//! and the macOS profile (SBPL passed to `sandbox-exec`) are generated
+
+use std::fmt::Write;
+
+/// Rules that extend the default sandbox, i.e., the contents of a `[sandbox]` section or of one of
+/// its `[sandbox.packages.PATTERN]` subsections
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Rules {
+    /// Additional paths the build script may write to. Each may contain `{VAR}` groups, which are
+    /// expanded when the build script is run.
+    pub writable: Vec<String>,
+    /// Whether the build script may access the network
+    pub network: bool,
+    /// Whether the target directory is hidden from the build script, except for its own `OUT_DIR`,
+    /// the directory containing the build script, and the directories that `DEP_*` variables name
+    pub hide_target_dir: bool,
+}
+
+impl Rules {
+    /// Returns whether the rules do not extend the default sandbox.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.writable.is_empty() && !self.network && !self.hide_target_dir
+    }
+
+    /// Adds `other`'s rules to `self`'s.
+    pub fn extend(&mut self, other: &Self) {
+        for path in &other.writable {
+            if !self.writable.contains(path) {
+                self.writable.push(path.clone());
+            }
+        }
+        self.network |= other.network;
+        self.hide_target_dir |= other.hide_target_dir;
+    }
+}
+
+/// A location the build script may write to
+#[derive(Debug, PartialEq)]
+enum Writable {
+    /// `/dev`
+    Dev,
+    /// The build script's `OUT_DIR`
+    OutDir,
+    /// The temporary directory, i.e., `/tmp` on Linux, and `TMPDIR` on macOS
+    TempDir,
+    /// A path from a `writable` list
+    Path(String),
+}
+
+/// The sandbox a build script is executed in. The default command and the built-in profiles'
+/// commands are generated from this model too.
+#[derive(Debug, PartialEq)]
+pub struct Sandbox {
+    writable: Vec<Writable>,
+    network: bool,
+    hide_target_dir: bool,
+    /// Whether the build script gets only basic devices, a private temporary directory and, on
+    /// Linux, new namespaces of every kind `bwrap` supports
+    isolated: bool,
+}
+
+impl Default for Sandbox {
+    fn default() -> Self {
+        Self::new(&Rules::default())
+    }
+}
+
+impl Sandbox {
+    /// Returns the default sandbox extended with `rules`.
+    pub fn new(rules: &Rules) -> Self {
+        let mut writable = vec![Writable::Dev, Writable::OutDir, Writable::TempDir];
+        writable.extend(rules.writable.iter().cloned().map(Writable::Path));
+        Self {
+            writable,
+            network: rules.network,
+            hide_target_dir: rules.hide_target_dir,
+            isolated: false,
+        }
+    }
+
+    /// Returns the sandbox of the built-in profile `name`, or `None` if there is no such profile.
+    #[must_use]
+    pub fn builtin(name: &str) -> Option<Self> {
+        let mut sandbox = Self::default();
+        match name {
+            "default" => {}
+            "native-build" => sandbox
+                .writable
+                .push(Writable::Path(String::from("{CARGO_MANIFEST_DIR}"))),
+            "network" => sandbox.network = true,
+            "strict" => {
+                sandbox.hide_target_dir = true;
+                sandbox.isolated = true;
+            }
+            _ => return None,
+        }
+        Some(sandbox)
+    }
+
+    /// Returns the value of `BUILD_WRAP_CMD` that executes a build script in the sandbox on the
+    /// current platform.
+    #[must_use]
+    pub fn cmd(&self) -> String {
+        if cfg!(target_os = "linux") {
+            self.linux_cmd()
+        } else {
+            self.macos_cmd()
+        }
+    }
+
+    /// Returns the value of `BUILD_WRAP_CMD` that executes a build script in the sandbox on Linux.
+    #[must_use]
+    pub fn linux_cmd(&self) -> String {
+        let mut cmd = String::from("bwrap\n    --ro-bind / /\n");
+        for writable in &self.writable {
+            match writable {
+                Writable::Dev if self.isolated => cmd.push_str("    --dev /dev\n"),
+                Writable::Dev => cmd.push_str("    --dev-bind /dev /dev\n"),
+                // smoelius: If the target directory is hidden, `OUT_DIR` is bound below, after the
+                // target directory is replaced with an empty tmpfs.
+                Writable::OutDir if self.hide_target_dir => {}
+                Writable::OutDir => cmd.push_str("    --bind {OUT_DIR} {OUT_DIR}\n"),
+                Writable::TempDir if self.isolated => cmd.push_str("    --tmpfs /tmp\n"),
+                Writable::TempDir => cmd.push_str("    --bind /tmp /tmp\n"),
+                Writable::Path(path) => {
+                    let path = quote_if_needed(path);
+                    writeln!(cmd, "    --bind {path} {path}").unwrap();
+                }
+            }
+        }
+        // smoelius: Later mounts are made on top of earlier ones. So the tmpfs hides everything in
+        // the target directory that was bound above (e.g., if the target directory is in `/tmp`),
+        // and the directories bound after it are visible.
+        if self.hide_target_dir {
+            cmd.push_str(
+                "    --tmpfs {CARGO_TARGET_DIR_RESOLVED}
+    --ro-bind {BUILD_SCRIPT_DIR} {BUILD_SCRIPT_DIR}
+    {DEP_DIRS:%--ro-bind {DEP_DIRS} {DEP_DIRS}}
+    --bind {OUT_DIR} {OUT_DIR}\n",
+            );
+        }
+        if self.isolated {
+            cmd.push_str("    --unshare-all\n");
+            if self.network {
+                cmd.push_str("    --share-net\n");
+            }
+        } else if !self.network {
+            cmd.push_str("    --unshare-net\n");
+        }
+        cmd.push_str("    {}");
+        cmd
+    }
+
+    /// Returns the value of `BUILD_WRAP_CMD` that executes a build script in the sandbox on macOS.
+    #[must_use]
+    pub fn macos_cmd(&self) -> String {
+        // smoelius: Paths are passed to `sandbox-exec` as parameters (`-D KEY=VALUE`) rather than
+        // written into the profile, so that a path containing, e.g., a `"` cannot alter the profile.
+        // smoelius: A profile cannot refer to a list of directories that is known only when the
+        // build script is run, such as the directories that `DEP_*` variables name. So
+        // `hide_target_dir` is ignored here.
+        let mut cmd = String::from("sandbox-exec\n");
+        for (key, value) in self.params() {
+            writeln!(cmd, "    -D {}", quote_if_needed(&format!("{key}={value}"))).unwrap();
+        }
+        writeln!(cmd, "    -p {}", crate::profile::quote(&self.sbpl())).unwrap();
+        cmd.push_str("    {}");
+        cmd
+    }
+
+    /// Returns the paths the build script may write to on macOS, each with the name of the
+    /// `sandbox-exec` parameter that holds it (`None` for a literal path) and a description.
+    fn macos_writable(&self) -> Vec<(Option<String>, String, String)> {
+        let mut writable = Vec::new();
+        let mut i = 0;
+        for path in &self.writable {
+            match path {
+                Writable::Dev => {
+                    writable.push((None, String::from("/dev"), String::from("/dev")));
+                }
+                Writable::OutDir => writable.push((
+                    Some(String::from("OUT_DIR")),
+                    String::from("{OUT_DIR}"),
+                    String::from("OUT_DIR"),
+                )),
+                Writable::TempDir if self.isolated => {}
+                Writable::TempDir => {
+                    writable.push((
+                        Some(String::from("TMPDIR")),
+                        String::from("{TMPDIR}"),
+                        String::from("TMPDIR"),
+                    ));
+                    writable.push((
+                        Some(String::from("PRIVATE_TMPDIR")),
+                        String::from("{PRIVATE_TMPDIR:-{TMPDIR}}"),
+                        String::from("PRIVATE_TMPDIR"),
+                    ));
+                }
+                // smoelius: The description is the parameter's name rather than the path, since a
+                // path could contain a newline, which would end the comment.
+                Writable::Path(path) => {
+                    let key = format!("WRITABLE_{i}");
+                    writable.push((Some(key.clone()), path.clone(), key));
+                    i += 1;
+                }
+            }
+        }
+        writable
+    }
+
+    /// Returns the `sandbox-exec` parameters that [`Self::sbpl`] refers to, and their values
+    /// (before expansion).
+    fn params(&self) -> Vec<(String, String)> {
+        self.macos_writable()
+            .into_iter()
+            .filter_map(|(key, path, _)| Some((key?, path)))
+            .collect()
+    }
+
+    /// Returns the macOS profile, with a comment describing each rule.
+    fn sbpl(&self) -> String {
+        let mut rules = vec![
+            (String::from("(version 1)"), None),
+            (String::from("(deny default)"), None),
+            (
+                String::from("(allow file-read*)"),
+                Some(String::from("Allow read-only access everywhere")),
+            ),
+        ];
+        for (key, path, description) in self.macos_writable() {
+            let filter =
+                key.map_or_else(|| format!("\"{path}\""), |key| format!("(param \"{key}\")"));
+            rules.push((
+                format!("(allow file-write* (subpath {filter}))"),
+                Some(format!("Allow write access to {description}")),
+            ));
+        }
+        rules.extend([
+            (
+                String::from("(allow process-exec)"),
+                Some(String::from("Allow exec")),
+            ),
+            (
+                String::from("(allow process-fork)"),
+                Some(String::from("Allow fork")),
+            ),
+            (
+                String::from("(allow sysctl-read)"),
+                Some(String::from("Allow reading kernel state")),
+            ),
+        ]);
+        if self.network {
+            rules.push((
+                String::from("(allow network*)"),
+                Some(String::from("Allow network access")),
+            ));
+        } else {
+            rules.push((
+                String::from("(deny network*)"),
+                Some(String::from("Deny network access")),
+            ));
+        }
+        let width = rules
+            .iter()
+            .map(|(rule, _)| rule.len())
+            .max()
+            .unwrap_or_default();
+        let mut sbpl = String::new();
+        for (rule, comment) in rules {
+            if let Some(comment) = comment {
+                writeln!(sbpl, "{rule:width$} ;; {comment}").unwrap();
+            } else {
+                writeln!(sbpl, "{rule}").unwrap();
+            }
+        }
+        sbpl
+    }
+}
+
+/// Double quotes `s` (see [`crate::profile::quote`]) if splitting `BUILD_WRAP_CMD` would otherwise
+/// alter it.
+fn quote_if_needed(s: &str) -> String {
+    if s.chars()
+        .all(|c| c.is_ascii_alphanumeric() || "{}/.:=_-+@%,~".contains(c))
+    {
+        s.to_owned()
+    } else {
+        crate::profile::quote(s)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Rules, Sandbox};
+    use crate::{profile::BUILTIN_NAMES, util::split_and_expand_with};
+    use anyhow::{Result, bail, ensure};
+    use std::{collections::BTreeSet, ffi::OsString, path::Path};
+
+    const OPERATIONS: &[&str] = &[
+        "default",
+        "file-read*",
+        "file-write*",
+        "network*",
+        "process-exec",
+        "process-fork",
+        "sysctl-read",
+    ];
+
+    fn rules() -> Rules {
+        Rules {
+            writable: vec![
+                String::from("{CARGO_HOME_RESOLVED}/registry/cache"),
+                String::from("/a \"b\" \\c"),
+            ],
+            network: true,
+            hide_target_dir: false,
+        }
+    }
+
+    #[test]
+    fn default_linux_cmd() {
+        assert_eq!(
+            "bwrap
+    --ro-bind / /
+    --dev-bind /dev /dev
+    --bind {OUT_DIR} {OUT_DIR}
+    --bind /tmp /tmp
+    --unshare-net
+    {}",
+            Sandbox::default().linux_cmd()
+        );
+    }
+
+    #[test]
+    fn strict_linux_cmd() {
+        assert_eq!(
+            "bwrap
+    --ro-bind / /
+    --dev /dev
+    --tmpfs /tmp
+    --tmpfs {CARGO_TARGET_DIR_RESOLVED}
+    --ro-bind {BUILD_SCRIPT_DIR} {BUILD_SCRIPT_DIR}
+    {DEP_DIRS:%--ro-bind {DEP_DIRS} {DEP_DIRS}}
+    --bind {OUT_DIR} {OUT_DIR}
+    --unshare-all
+    {}",
+            Sandbox::builtin("strict").unwrap().linux_cmd()
+        );
+    }
+
+    #[test]
+    fn linux_cmd_with_rules() {
+        let args = split_and_expand_with(
+            &Sandbox::new(&rules()).linux_cmd(),
+            Path::new("/build_script_build"),
+            |key| Ok(OsString::from(format!("/{key}"))),
+        )
+        .unwrap();
+        assert_eq!(
+            [
+                "bwrap",
+                "--ro-bind",
+                "/",
+                "/",
+                "--dev-bind",
+                "/dev",
+                "/dev",
+                "--bind",
+                "/OUT_DIR",
+                "/OUT_DIR",
+                "--bind",
+                "/tmp",
+                "/tmp",
+                "--bind",
+                "/CARGO_HOME_RESOLVED/registry/cache",
+                "/CARGO_HOME_RESOLVED/registry/cache",
+                "--bind",
+                "/a \"b\" \\c",
+                "/a \"b\" \\c",
+                "/build_script_build",
+            ]
+            .as_slice(),
+            args
+        );
+    }
+
+    #[test]
+    fn linux_cmd_hiding_target_dir() {
+        let rules = Rules {
+            hide_target_dir: true,
+            ..Rules::default()
+        };
+        let args = split_and_expand_with(
+            &Sandbox::new(&rules).linux_cmd(),
+            Path::new("/build_script_build"),
+            |key| match key {
+                "DEP_DIRS" => Ok(OsString::from("/DEP_A:/DEP_B")),
+                _ => Ok(OsString::from(format!("/{key}"))),
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            [
+                "bwrap",
+                "--ro-bind",
+                "/",
+                "/",
+                "--dev-bind",
+                "/dev",
+                "/dev",
+                "--bind",
+                "/tmp",
+                "/tmp",
+                "--tmpfs",
+                "/CARGO_TARGET_DIR_RESOLVED",
+                "--ro-bind",
+                "/BUILD_SCRIPT_DIR",
+                "/BUILD_SCRIPT_DIR",
+                "--ro-bind",
+                "/DEP_A",
+                "/DEP_A",
+                "--ro-bind",
+                "/DEP_B",
+                "/DEP_B",
+                "--bind",
+                "/OUT_DIR",
+                "/OUT_DIR",
+                "--unshare-net",
+                "/build_script_build",
+            ]
+            .as_slice(),
+            args
+        );
+    }
+
+    #[test]
+    fn macos_cmd_is_valid() {
+        for rules in [Rules::default(), rules()] {
+            let args = split_and_expand_with(
+                &Sandbox::new(&rules).macos_cmd(),
+                Path::new("/build_script_build"),
+                |key| match key {
+                    "PRIVATE_TMPDIR" => Err(std::env::VarError::NotPresent),
+                    _ => Ok(OsString::from(format!("/{key}"))),
+                },
+            )
+            .unwrap();
+            let args = args
+                .iter()
+                .map(|arg| arg.to_str().unwrap())
+                .collect::<Vec<_>>();
+            let (profile, params) = parse_sandbox_exec_args(&args).unwrap();
+            validate_sbpl(profile, &params).unwrap();
+            assert!(params.contains("OUT_DIR=/OUT_DIR"));
+            assert!(params.contains("PRIVATE_TMPDIR=/TMPDIR"));
+            assert_eq!(Some(&"/build_script_build"), args.last());
+        }
+    }
+
+    #[test]
+    fn macos_network() {
+        let sbpl = Sandbox::new(&Rules::default()).sbpl();
+        assert!(sbpl.contains("(deny network*)"));
+        let sbpl = Sandbox::new(&rules()).sbpl();
+        assert!(sbpl.contains("(allow network*)"));
+        assert!(sbpl.contains("(allow file-write* (subpath (param \"WRITABLE_1\")))"));
+    }
+
+    #[test]
+    fn builtin_profiles_are_valid() {
+        for name in BUILTIN_NAMES {
+            let sandbox = Sandbox::builtin(name).unwrap();
+            let params = sandbox
+                .params()
+                .into_iter()
+                .map(|(key, _)| format!("{key}=/{key}"))
+                .collect::<Vec<_>>();
+            validate_sbpl(
+                &sandbox.sbpl(),
+                &params.iter().map(String::as_str).collect(),
+            )
+            .unwrap_or_else(|error| panic!("{name}: {error}"));
+        }
+        // smoelius: The `strict` profile does not allow writing to the temporary directory.
+        let sbpl = Sandbox::builtin("strict").unwrap().sbpl();
+        assert!(!sbpl.contains("TMPDIR"), "{sbpl}");
+    }
+
+    #[test]
+    fn invalid_sbpl() {
+        let params = BTreeSet::new();
+        for sbpl in [
+            "(allow file-read*)",
+            "(version 1)\n(allow file-read*",
+            "(version 1)\n(allow file-read*))",
+            "(version 1)\n(allow file-rread*)",
+            "(version 1)\n(allow file-write* (subpath \"/a))",
+            "(version 1)\n(allow file-write* (subpath (param \"UNDEFINED\")))",
+            "(version 1)\n(permit file-read*)",
+        ] {
+            assert!(validate_sbpl(sbpl, &params).is_err(), "{sbpl:?}");
+        }
+    }
+
+    /// Returns the profile passed with `-p` and the parameters passed with `-D`.
+    fn parse_sandbox_exec_args<'a>(args: &[&'a str]) -> Result<(&'a str, BTreeSet<&'a str>)> {
+        ensure!(args.first() == Some(&"sandbox-exec"), "not `sandbox-exec`");
+        let mut profile = None;
+        let mut params = BTreeSet::new();
+        let mut iter = args[1..args.len() - 1].iter();
+        while let Some(&arg) = iter.next() {
+            match (arg, iter.next()) {
+                ("-D", Some(&param)) => {
+                    params.insert(param);
+                }
+                ("-p", Some(&value)) => profile = Some(value),
+                _ => bail!("unexpected argument `{arg}`"),
+            }
+        }
+        let Some(profile) = profile else {
+            bail!("missing `-p`");
+        };
+        Ok((profile, params))
+    }
+
+    #[derive(Debug)]
+    enum Sexp<'a> {
+        Atom(&'a str),
+        String(String),
+        List(Vec<Sexp<'a>>),
+    }
+
+    /// Checks that `sbpl` is syntactically valid, uses only the operations in `OPERATIONS`, and
+    /// refers only to parameters defined in `params`. This allows the generated profiles to be
+    /// checked without running `sandbox-exec`.
+    fn validate_sbpl(sbpl: &str, params: &BTreeSet<&str>) -> Result<()> {
+        let forms = parse_sexps(sbpl)?;
+        let mut forms = forms.iter();
+        match forms.next() {
+            Some(Sexp::List(list))
+                if matches!(list.as_slice(), [Sexp::Atom("version"), Sexp::Atom("1")]) => {}
+            form => bail!("expected `(version 1)`, found {form:?}"),
+        }
+        for form in forms {
+            let Sexp::List(list) = form else {
+                bail!("expected a list, found {form:?}");
+            };
+            let [
+                Sexp::Atom("allow" | "deny"),
+                Sexp::Atom(operation),
+                filters @ ..,
+            ] = list.as_slice()
+            else {
+                bail!("expected `allow` or `deny` and an operation, found {list:?}");
+            };
+            ensure!(
+                OPERATIONS.contains(operation),
+                "unknown operation `{operation}`"
+            );
+            for filter in filters {
+                validate_filter(filter, params)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_filter(filter: &Sexp, params: &BTreeSet<&str>) -> Result<()> {
+        let Sexp::List(list) = filter else {
+            bail!("expected a filter, found {filter:?}");
+        };
+        match list.as_slice() {
+            [Sexp::Atom("subpath" | "literal"), Sexp::String(_)] => Ok(()),
+            [Sexp::Atom("subpath" | "literal"), Sexp::List(param)] => match param.as_slice() {
+                [Sexp::Atom("param"), Sexp::String(key)] => {
+                    ensure!(
+                        params
+                            .iter()
+                            .any(|param| param.split_once('=').map(|(k, _)| k) == Some(key)),
+                        "undefined parameter `{key}`"
+                    );
+                    Ok(())
+                }
+                _ => bail!("expected `(param ...)`, found {param:?}"),
+            },
+            _ => bail!("unknown filter {list:?}"),
+        }
+    }
+
+    fn parse_sexps(s: &str) -> Result<Vec<Sexp<'_>>> {
+        let mut stack = vec![Vec::new()];
+        let mut chars = s.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '(' => stack.push(Vec::new()),
+                ')' => {
+                    ensure!(stack.len() > 1, "unbalanced ')'");
+                    let list = stack.pop().unwrap();
+                    stack.last_mut().unwrap().push(Sexp::List(list));
+                }
+                ';' => while chars.next_if(|&(_, c)| c != '\n').is_some() {},
+                '"' => {
+                    let mut string = String::new();
+                    loop {
+                        match chars.next() {
+                            Some((_, '"')) => break,
+                            Some((_, '\\')) => {
+                                let Some((_, c)) = chars.next() else {
+                                    bail!("unterminated string");
+                                };
+                                string.push(c);
+                            }
+                            Some((_, c)) => string.push(c),
+                            None => bail!("unterminated string"),
+                        }
+                    }
+                    stack.last_mut().unwrap().push(Sexp::String(string));
+                }
+                _ if c.is_whitespace() => {}
+                _ => {
+                    let mut j = i + c.len_utf8();
+                    while let Some((k, c)) =
+                        chars.next_if(|&(_, c)| !c.is_whitespace() && !"()\";".contains(c))
+                    {
+                        j = k + c.len_utf8();
+                    }
+                    stack.last_mut().unwrap().push(Sexp::Atom(&s[i..j]));
+                }
+            }
+        }
+        ensure!(stack.len() == 1, "unbalanced '('");
+        Ok(stack.pop().unwrap())
+    }
+}
@@ -0,0 +1,341 @@
+//! Metadata that `build-wrap` records next to each build script and proc macro it links.
+
+use crate::{util::sha256_hex, wrapper::Sibling};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    ffi::OsString,
+    fs::{read_to_string, write},
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+};
+
+/// Name of the sidecar file. A sidecar is written to the directory containing the build script,
+/// i.e., a `build/<pkg>-<hash>` directory.
+pub const FILE_NAME: &str = "build-wrap-sidecar.toml";
+
+/// Metadata that `build-wrap` records next to each build script it links
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Sidecar {
+    /// Name of the package the build script belongs to
+    pub package: String,
+
+    /// Version of the package the build script belongs to
+    pub version: String,
+
+    /// Path of the build script, as passed to the linker
+    #[serde(with = "path")]
+    pub original: PathBuf,
+
+    /// Path of the renamed original build script. `None` if the build script was not wrapped.
+    #[serde(default, with = "option_path")]
+    pub sibling: Option<PathBuf>,
+
+    /// SHA-256 hash of the renamed original build script. `None` if the build script was not
+    /// wrapped.
+    pub sibling_sha256: Option<String>,
+
+    /// Command the build script is executed under. `None` if the build script was not wrapped.
+    pub cmd: Option<String>,
+
+    /// SHA-256 hash of `cmd`. `None` if the build script was not wrapped.
+    pub policy_hash: Option<String>,
+
+    /// Why the build script was not wrapped. `None` if the build script was wrapped.
+    pub exemption: Option<String>,
+}
+
+impl Sidecar {
+    /// Returns a sidecar for the build script at `original`, belonging to the package that is
+    /// currently being linked.
+    #[must_use]
+    pub fn new(original: &Path) -> Self {
+        Self {
+            package: std::env::var("CARGO_PKG_NAME").unwrap_or_default(),
+            version: std::env::var("CARGO_PKG_VERSION").unwrap_or_default(),
+            original: original.to_path_buf(),
+            sibling: None,
+            sibling_sha256: None,
+            cmd: None,
+            policy_hash: None,
+            exemption: None,
+        }
+    }
+
+    /// Marks the build script as wrapped so that it is executed under `cmd`.
+    #[must_use]
+    pub fn wrapped(self, sibling: Sibling, cmd: String) -> Self {
+        Self {
+            policy_hash: Some(sha256_hex(cmd.as_bytes())),
+            sibling: Some(sibling.path),
+            sibling_sha256: Some(sibling.sha256),
+            cmd: Some(cmd),
+            exemption: None,
+            ..self
+        }
+    }
+
+    /// Marks the build script as not wrapped for the reason `exemption`.
+    #[must_use]
+    pub fn exempt(self, exemption: String) -> Self {
+        Self {
+            sibling: None,
+            sibling_sha256: None,
+            cmd: None,
+            policy_hash: None,
+            exemption: Some(exemption),
+            ..self
+        }
+    }
+
+    /// Reads the sidecar in `dir`, if any.
+    pub fn read(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(FILE_NAME);
+        if !path.try_exists()? {
+            return Ok(None);
+        }
+        let contents = read_to_string(&path)
+            .with_context(|| format!("failed to read `{}`", path.display()))?;
+        let sidecar = toml::from_str(&contents)
+            .with_context(|| format!("failed to parse `{}`", path.display()))?;
+        Ok(Some(sidecar))
+    }
+
+    /// Writes the sidecar to the directory containing the build script.
+    pub fn write(&self) -> Result<()> {
+        let path = path_for(&self.original);
+        let contents = toml::to_string(self)?;
+        write(&path, contents).with_context(|| format!("failed to write `{}`", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Returns the path of the sidecar for the build script at `build_script_path`.
+#[must_use]
+pub fn path_for(build_script_path: &Path) -> PathBuf {
+    build_script_path.with_file_name(FILE_NAME)
+}
+
+/// Metadata that `build-wrap` records next to each proc macro it links. Unlike a build script, a
+/// proc macro shares its directory (i.e., `deps`) with other crates, so the sidecar's name is the
+/// proc macro's file name followed by `.` and [`FILE_NAME`].
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ProcMacroSidecar {
+    /// Name of the proc macro's package
+    pub package: String,
+
+    /// Version of the proc macro's package
+    pub version: String,
+
+    /// Path of the proc macro, as passed to the linker
+    #[serde(with = "path")]
+    pub original: PathBuf,
+
+    /// SHA-256 fingerprint of the package's sources (see [`crate::proc_macros::fingerprint`]).
+    /// `None` if the proc macro was not checked against a fingerprint.
+    pub fingerprint: Option<String>,
+
+    /// How the `[proc-macros]` policy treated the proc macro, e.g., "trusted on first use"
+    pub approval: String,
+
+    /// Whether the proc macro was refused, i.e., removed after linking
+    pub refused: bool,
+}
+
+impl ProcMacroSidecar {
+    /// Returns a sidecar for the proc macro at `original`, belonging to the package that is
+    /// currently being linked.
+    #[must_use]
+    pub fn new(original: &Path) -> Self {
+        Self {
+            package: std::env::var("CARGO_PKG_NAME").unwrap_or_default(),
+            version: std::env::var("CARGO_PKG_VERSION").unwrap_or_default(),
+            original: original.to_path_buf(),
+            fingerprint: None,
+            approval: String::new(),
+            refused: false,
+        }
+    }
+
+    /// Reads the proc-macro sidecar at `path`.
+    pub fn read(path: &Path) -> Result<Self> {
+        let contents =
+            read_to_string(path).with_context(|| format!("failed to read `{}`", path.display()))?;
+        toml::from_str(&contents).with_context(|| format!("failed to parse `{}`", path.display()))
+    }
+
+    /// Writes the sidecar next to the proc macro.
+    pub fn write(&self) -> Result<()> {
+        let path = proc_macro_path_for(&self.original);
+        let contents = toml::to_string(self)?;
+        write(&path, contents).with_context(|| format!("failed to write `{}`", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Returns the path of the sidecar for the proc macro at `proc_macro_path`.
+#[must_use]
+pub fn proc_macro_path_for(proc_macro_path: &Path) -> PathBuf {
+    let mut file_name = proc_macro_path.file_name().unwrap_or_default().to_owned();
+    file_name.push(".");
+    file_name.push(FILE_NAME);
+    proc_macro_path.with_file_name(file_name)
+}
+
+/// Returns whether `path` names a proc-macro sidecar (see [`proc_macro_path_for`]).
+#[must_use]
+pub fn is_proc_macro_sidecar(path: &Path) -> bool {
+    path.file_name().is_some_and(|file_name| {
+        let bytes = file_name.as_bytes();
+        bytes.len() > FILE_NAME.len() + 1
+            && bytes.ends_with(FILE_NAME.as_bytes())
+            && bytes[bytes.len() - FILE_NAME.len() - 1] == b'.'
+    })
+}
+
+/// How a path is stored in a sidecar: as a string if the path is valid UTF-8, and as an array of
+/// bytes otherwise
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum PathRepr {
+    String(String),
+    Bytes(Vec<u8>),
+}
+
+impl From<&Path> for PathRepr {
+    fn from(path: &Path) -> Self {
+        path.to_str().map_or_else(
+            || Self::Bytes(path.as_os_str().as_bytes().to_vec()),
+            |s| Self::String(s.to_owned()),
+        )
+    }
+}
+
+impl From<PathRepr> for PathBuf {
+    fn from(repr: PathRepr) -> Self {
+        match repr {
+            PathRepr::String(s) => Self::from(s),
+            PathRepr::Bytes(bytes) => Self::from(OsString::from_vec(bytes)),
+        }
+    }
+}
+
+mod path {
+    use super::{Deserialize, Deserializer, Path, PathBuf, PathRepr, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        PathRepr::from(path).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+        PathRepr::deserialize(deserializer).map(Into::into)
+    }
+}
+
+mod option_path {
+    use super::{Deserialize, Deserializer, PathBuf, PathRepr, Serialize, Serializer};
+
+    #[allow(clippy::ref_option)]
+    pub fn serialize<S: Serializer>(
+        path: &Option<PathBuf>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        path.as_deref().map(PathRepr::from).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<PathBuf>, D::Error> {
+        Option::<PathRepr>::deserialize(deserializer).map(|repr| repr.map(Into::into))
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod test {
+    use super::*;
+    use std::ffi::OsStr;
+
+    #[test]
+    fn roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = dir.path().join("build_script_build-0123456789abcdef");
+        let sibling = dir.path().join(".tmpAbCdEf");
+
+        let sidecar = Sidecar::new(&original).wrapped(
+            Sibling {
+                path: sibling.clone(),
+                sha256: sha256_hex(b""),
+            },
+            String::from("time -p {}"),
+        );
+        sidecar.write().unwrap();
+
+        let sidecar = Sidecar::read(dir.path()).unwrap().unwrap();
+        assert_eq!(original, sidecar.original);
+        assert_eq!(Some(sibling), sidecar.sibling);
+        assert_eq!(Some(sha256_hex(b"")), sidecar.sibling_sha256);
+        assert_eq!(Some("time -p {}"), sidecar.cmd.as_deref());
+        assert_eq!(Some(sha256_hex(b"time -p {}")), sidecar.policy_hash);
+        assert!(sidecar.exemption.is_none());
+
+        let sidecar = sidecar.exempt(String::from("reason"));
+        assert!(sidecar.sibling.is_none());
+        assert!(sidecar.sibling_sha256.is_none());
+        assert!(sidecar.cmd.is_none());
+        assert!(sidecar.policy_hash.is_none());
+        assert_eq!(Some("reason"), sidecar.exemption.as_deref());
+    }
+
+    #[test]
+    fn proc_macro_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = dir.path().join("libfoo_derive-0123456789abcdef.so");
+
+        let mut sidecar = ProcMacroSidecar::new(&original);
+        sidecar.fingerprint = Some(sha256_hex(b""));
+        sidecar.approval = String::from("trusted on first use");
+        sidecar.write().unwrap();
+
+        let path = proc_macro_path_for(&original);
+        assert_eq!(
+            dir.path()
+                .join("libfoo_derive-0123456789abcdef.so.build-wrap-sidecar.toml"),
+            path
+        );
+        assert!(is_proc_macro_sidecar(&path));
+        assert!(!is_proc_macro_sidecar(&path_for(&original)));
+
+        let sidecar = ProcMacroSidecar::read(&path).unwrap();
+        assert_eq!(original, sidecar.original);
+        assert_eq!(Some(sha256_hex(b"")), sidecar.fingerprint);
+        assert_eq!("trusted on first use", sidecar.approval);
+        assert!(!sidecar.refused);
+    }
+
+    #[test]
+    fn roundtrip_non_utf8() {
+        let dir = tempfile::tempdir().unwrap();
+        let build_dir = dir.path().join(OsStr::from_bytes(b"\"\\\xff"));
+        std::fs::create_dir(&build_dir).unwrap();
+        let original = build_dir.join("build_script_build-0123456789abcdef");
+        let sibling = build_dir.join(".tmpAbCdEf");
+
+        Sidecar::new(&original)
+            .wrapped(
+                Sibling {
+                    path: sibling.clone(),
+                    sha256: sha256_hex(b""),
+                },
+                String::from("{}"),
+            )
+            .write()
+            .unwrap();
+
+        let sidecar = Sidecar::read(&build_dir).unwrap().unwrap();
+        assert_eq!(original, sidecar.original);
+        assert_eq!(Some(sibling), sidecar.sibling);
+    }
+}
@@ -1,79 +1,176 @@
 //! This file is included verbatim in the wrapper build script's src/main.rs file.
 
 use anyhow::{Context, Result, anyhow, bail, ensure};
+use sha2::{Digest, Sha256};
 use std::{
+    collections::BTreeMap,
     env,
-    fs::canonicalize,
-    io::Write,
-    os::unix::ffi::OsStrExt,
-    path::Path,
-    process::{Command, Output, Stdio},
-    str::Utf8Error,
+    ffi::{OsStr, OsString},
+    fmt::Write as _,
+    fs::{OpenOptions, canonicalize, read, read_dir, remove_file, symlink_metadata, write},
+    io::{BufRead, BufReader, Read, Write},
+    iter::Peekable,
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        fs::{MetadataExt, OpenOptionsExt},
+    },
+    path::{Component, Path, PathBuf},
+    process::{Command, ExitStatus, Output, Stdio},
+    str::Chars,
     sync::LazyLock,
 };
 
-#[allow(dead_code)]
-const DEFAULT_PROFILE: &str = r#"(version 1)
-(deny default)
-(allow file-read*)                               ;; Allow read-only access everywhere
-(allow file-write* (subpath "/dev"))             ;; Allow write access to /dev
-(allow file-write* (subpath "{OUT_DIR}"))        ;; Allow write access to `OUT_DIR`
-(allow file-write* (subpath "{TMPDIR}"))         ;; Allow write access to `TMPDIR`
-(allow file-write* (subpath "{PRIVATE_TMPDIR}")) ;; Allow write access to `PRIVATE_TMPDIR` (see below)
-(allow process-exec)                             ;; Allow `exec`
-(allow process-fork)                             ;; Allow `fork`
-(allow sysctl-read)                              ;; Allow reading kernel state
-(deny network*)                                  ;; Deny network access
-"#;
-
 /// Executes `command`, forwards its output to stdout and stderr, and optionally checks whether
 /// `command` succeeded.
 ///
-/// Called by [`exec_sibling`]. Since this file is included in the wrapper build script's
-/// src/main.rs file, `exec_forwarding_output` should appear here, alongside [`exec_sibling`].
+/// Output is forwarded line by line as `command` produces it, so that the progress of long-running
+/// build scripts is visible (e.g., under `cargo -vv`). The output is also collected and returned.
+///
+/// Called by `exec_sibling`. Since this file is included in the wrapper build script's
+/// src/main.rs file, `exec_forwarding_output` should appear here, alongside `exec_sibling`.
 ///
 /// # Errors
 ///
 /// If `command` cannot be executed, or if `failure_is_error` is true and `command` failed.
-pub fn exec_forwarding_output(mut command: Command, failure_is_error: bool) -> Result<Output> {
+pub fn exec_forwarding_output(command: Command, failure_is_error: bool) -> Result<Output> {
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let status = exec_filtering_output(
+        command,
+        failure_is_error,
+        |line| {
+            stdout.extend_from_slice(line);
+            line.to_owned()
+        },
+        |line| stderr.extend_from_slice(line),
+    )?;
+    Ok(Output {
+        status,
+        stdout,
+        stderr,
+    })
+}
+
+/// Like [`exec_forwarding_output`], but forwards `filter(line)` rather than each line of
+/// `command`'s stdout, and passes each line of `command`'s stderr to `inspect_stderr` after
+/// forwarding it. Nothing is collected; callers keep whatever they need.
+///
+/// If forwarding fails, `command` is killed. In every case, `command` is waited on before this
+/// function returns.
+fn exec_filtering_output(
+    mut command: Command,
+    failure_is_error: bool,
+    mut filter: impl FnMut(&[u8]) -> Vec<u8>,
+    mut inspect_stderr: impl FnMut(&[u8]) + Send,
+) -> Result<ExitStatus> {
     command.stdout(Stdio::piped());
     command.stderr(Stdio::piped());
 
-    let output = command.output()?;
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("failed to execute {command:?}"))?;
+    let child_stdout = child.stdout.take().unwrap();
+    let child_stderr = child.stderr.take().unwrap();
 
     // smoelius: Stdout *must* be forwarded.
     // See: https://doc.rust-lang.org/cargo/reference/build-scripts.html#life-cycle-of-a-build-script
-    // `print!` and `eprint!` are used so that `libtest` will capture them.
-    print!("{}", String::from_utf8_lossy(&output.stdout));
-    eprint!("{}", String::from_utf8_lossy(&output.stderr));
+    // `print!` and `eprint!` are used so that `libtest` will capture them. Stderr is forwarded from
+    // a separate thread so that neither pipe can fill while the other is being read.
+    let result = std::thread::scope(|scope| -> Result<()> {
+        let stderr_handle = scope.spawn(|| {
+            for_each_line(child_stderr, |line| {
+                forward(line, true)?;
+                inspect_stderr(line);
+                Ok(())
+            })
+        });
+        let stdout_result = for_each_line(child_stdout, |line| forward(&filter(line), false));
+        // smoelius: If stdout cannot be forwarded, kill `command` so that the thread forwarding
+        // stderr sees end-of-file rather than waiting on a process that may never exit.
+        if stdout_result.is_err() {
+            let _: std::io::Result<()> = child.kill();
+        }
+        let stderr_result = stderr_handle
+            .join()
+            .map_err(|_| anyhow!("thread forwarding stderr panicked"));
+        stdout_result?;
+        stderr_result?
+    });
 
-    std::io::stdout().flush()?;
-    std::io::stderr().flush()?;
+    if result.is_err() {
+        let _: std::io::Result<()> = child.kill();
+    }
+    let status = child.wait()?;
+    result?;
 
-    if !output.status.success() {
+    if !status.success() {
         if failure_is_error {
             bail!("command failed: {command:?}");
         }
         eprintln!("command failed: {command:?}");
     }
 
-    Ok(output)
+    Ok(status)
+}
+
+/// Writes `bytes` to stderr (if `to_stderr` is true) or stdout, and flushes it. Valid UTF-8 is
+/// written with `eprint!` or `print!` so that `libtest` will capture it; anything else is written
+/// unchanged.
+fn forward(bytes: &[u8], to_stderr: bool) -> std::io::Result<()> {
+    match (std::str::from_utf8(bytes), to_stderr) {
+        (Ok(s), true) => eprint!("{s}"),
+        (Ok(s), false) => print!("{s}"),
+        (Err(_), true) => std::io::stderr().write_all(bytes)?,
+        (Err(_), false) => std::io::stdout().write_all(bytes)?,
+    }
+    if to_stderr {
+        std::io::stderr().flush()
+    } else {
+        std::io::stdout().flush()
+    }
+}
+
+/// Reads `reader` to completion, calling `f` on each line (including its terminating newline, if
+/// any) as soon as it is read. Only the current line is held in memory.
+fn for_each_line(reader: impl Read, mut f: impl FnMut(&[u8]) -> std::io::Result<()>) -> Result<()> {
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    while reader.read_until(b'\n', &mut line)? != 0 {
+        f(&line)?;
+        line.clear();
+    }
+    Ok(())
 }
 
 /// Essentially the body of the wrapper build script's `main` function. Not called by `build-wrap`
 /// itself.
+///
+/// `sibling_sha256` is the SHA-256 hash of the renamed original build script, computed when the
+/// wrapper was linked.
 #[allow(dead_code)]
-fn exec_sibling(sibling_path_as_str: &str) -> Result<()> {
+fn exec_sibling(sibling_path: &Path, sibling_sha256: &str) -> Result<()> {
     let current_exe = env::current_exe()?;
 
     let parent = current_exe
         .parent()
         .ok_or_else(|| anyhow!("failed to get `current_exe` parent"))?;
 
-    let sibling_path = Path::new(sibling_path_as_str);
-
     assert!(sibling_path.starts_with(parent));
 
+    let policy = Policy::parse(option_env!("BUILD_WRAP_POLICY").unwrap_or_default())?;
+    let out_dir = env::var_os("OUT_DIR").map(PathBuf::from);
+    let filter = |line: &[u8]| filter_directives(line, &policy, out_dir.as_deref());
+
+    // smoelius: Another build script or proc macro could have overwritten the sibling between when
+    // it was linked and now. Note that there remains a window between this check and when the
+    // sibling is executed.
+    verify_sibling(sibling_path, sibling_sha256)?;
+
+    // smoelius: Failing to record the invocation should not cause the build to fail.
+    if let Err(error) = record_invocation(sibling_path) {
+        eprintln!("warning: failed to record invocation: {error}");
+    }
+
     // smoelius: The `BUILD_WRAP_CMD` used is the one set when set when the wrapper build script is
     // compiled, not when it is run. So if the wrapped build script prints the following and the
     // environment variable changes, those facts alone will not cause the wrapper build script
@@ -84,41 +181,1581 @@ fn exec_sibling(sibling_path_as_str: &str) -> Result<()> {
     // They will cause the wrapped build script to be rerun, however.
     let expanded_args = split_and_expand(sibling_path)?;
 
-    let allow_enabled = enabled("BUILD_WRAP_ALLOW");
+    // smoelius: `BUILD_WRAP_DENIED` is set when the wrapper build script is compiled for a package
+    // denied by config.toml. Such a package's build script is never rerun unsandboxed.
+    let denied = option_env!("BUILD_WRAP_DENIED").is_some();
+    let allow_enabled = !denied && enabled("BUILD_WRAP_ALLOW");
 
-    let mut command = Command::new(&expanded_args[0]);
-    command.args(&expanded_args[1..]);
-    let output = exec_forwarding_output(command, !allow_enabled)?;
+    // smoelius: The filter applies regardless of what `BUILD_WRAP_CMD` is (see `seccomp`). It is
+    // not installed when the build script is rerun unsandboxed below. `_seccomp_file` must outlive
+    // the spawning of `command`.
+    #[cfg(target_os = "linux")]
+    let (command, _seccomp_file) = seccomp::command(
+        &expanded_args,
+        option_env!("BUILD_WRAP_DENIED_SYSCALLS")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|name| !name.is_empty()),
+    )?;
+    #[cfg(not(target_os = "linux"))]
+    let command = {
+        let mut command = Command::new(&expanded_args[0]);
+        command.args(&expanded_args[1..]);
+        command
+    };
+    let scan = IntegrityScan::new(protected_dirs(&expanded_args))?;
+    let mut diagnosis = Diagnosis::default();
+    let status = exec_filtering_output(command, false, filter, |line| diagnosis.observe(line))?;
 
-    // smoelius: We should arrive at this `if` with `!output.status.success()` only when
-    // `BUILD_WRAP_ALLOW` is enabled.
-    if !output.status.success() {
-        debug_assert!(allow_enabled);
+    // smoelius: The scan is what protects sources when `BUILD_WRAP_CMD` does not, e.g., when it is
+    // a custom command. A build script that modified sources is never rerun.
+    let modified = scan.modified()?;
+    ensure!(
+        modified.is_empty(),
+        "wrapped build script modified files outside of `OUT_DIR`:\n{}",
+        modified
+            .iter()
+            .map(|path| format!("    {}", path.display()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    );
+
+    if !status.success() {
+        let denial = diagnosis.denial();
+        if let Some(denial) = &denial {
+            eprintln!("{denial}");
+        }
+        let allowed = allow_enabled
+            || (!denied && enabled("BUILD_WRAP_INTERACTIVE") && prompt(denial.as_ref())?);
+        ensure!(allowed, "wrapped build script failed");
+        // smoelius: The sandboxed run may have modified the sibling, e.g., if `BUILD_WRAP_CMD`
+        // made its directory writable. So verify it again before running it unsandboxed.
+        verify_sibling(sibling_path, sibling_sha256)?;
         let command = Command::new(sibling_path);
-        let _: Output = exec_forwarding_output(command, true)?;
+        let _: ExitStatus = exec_filtering_output(command, true, filter, |_| {})?;
+    }
+
+    // smoelius: The audit follows whichever run produced `OUT_DIR`'s final contents.
+    if let Some(out_dir) = &out_dir {
+        audit(out_dir, &policy)?;
+    }
+
+    Ok(())
+}
+
+/// Syscalls that wrapped build scripts may not make, in addition to any named in config.toml.
+/// `ioctl`s that inject terminal input (`TIOCSTI` and `TIOCLINUX`) are also denied.
+#[allow(dead_code)]
+pub const DEFAULT_DENIED_SYSCALLS: &[&str] = &[
+    "add_key",
+    "bpf",
+    "keyctl",
+    "perf_event_open",
+    "process_vm_readv",
+    "process_vm_writev",
+    "ptrace",
+    "request_key",
+    "userfaultfd",
+];
+
+/// A seccomp-bpf filter that makes denied syscalls fail with `EPERM`.
+///
+/// If `BUILD_WRAP_CMD`'s command is `bwrap`, the filter is passed to it with `--seccomp`, and
+/// `bwrap` installs it in the sandboxed process. Otherwise, the filter is installed with
+/// `PR_SET_NO_NEW_PRIVS` in the process that executes the command, and is inherited across `fork`
+/// and `exec`. Either way, it applies to the build script and everything it executes.
+#[cfg(target_os = "linux")]
+#[allow(dead_code)]
+pub mod seccomp {
+    use anyhow::{Result, anyhow, ensure};
+    use libc::{
+        BPF_ABS, BPF_JEQ, BPF_JGE, BPF_JMP, BPF_K, BPF_LD, BPF_RET, BPF_W, SECCOMP_RET_ALLOW,
+        SECCOMP_RET_ERRNO, c_long, sock_filter, sock_fprog,
+    };
+    use std::{
+        ffi::{OsStr, OsString},
+        fs::File,
+        io::{Seek, SeekFrom, Write},
+        os::{fd::AsRawFd, unix::process::CommandExt},
+        path::Path,
+        process::Command,
+    };
+
+    // smoelius: From linux/audit.h. `libc` does not define these.
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: Option<u32> = Some(0xC000_00B7);
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: Option<u32> = Some(0xC000_003E);
+    #[cfg(not(any(target_arch = "aarch64", target_arch = "x86_64")))]
+    const AUDIT_ARCH: Option<u32> = None;
+
+    // smoelius: Offsets of `seccomp_data`'s `nr`, `arch`, and the low 32 bits of `args[1]`. The
+    // kernel truncates an `ioctl`'s request to 32 bits, so comparing the low 32 bits suffices.
+    const NR_OFFSET: u32 = 0;
+    const ARCH_OFFSET: u32 = 4;
+    const ARG1_OFFSET: u32 = 24;
+
+    // smoelius: x32 syscalls have this bit set. They are denied wholesale rather than allowing
+    // them to bypass the filter.
+    #[cfg(target_arch = "x86_64")]
+    const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+    const DENY: u32 = SECCOMP_RET_ERRNO | libc::EPERM as u32;
+
+    /// Syscalls that may be named in config.toml's `[seccomp]` section
+    const SYSCALLS: &[(&str, c_long)] = &[
+        ("acct", libc::SYS_acct),
+        ("add_key", libc::SYS_add_key),
+        ("bpf", libc::SYS_bpf),
+        ("chroot", libc::SYS_chroot),
+        ("clock_adjtime", libc::SYS_clock_adjtime),
+        ("clock_settime", libc::SYS_clock_settime),
+        ("delete_module", libc::SYS_delete_module),
+        ("fanotify_init", libc::SYS_fanotify_init),
+        ("finit_module", libc::SYS_finit_module),
+        ("init_module", libc::SYS_init_module),
+        ("io_uring_enter", libc::SYS_io_uring_enter),
+        ("io_uring_register", libc::SYS_io_uring_register),
+        ("io_uring_setup", libc::SYS_io_uring_setup),
+        ("kcmp", libc::SYS_kcmp),
+        ("kexec_file_load", libc::SYS_kexec_file_load),
+        ("kexec_load", libc::SYS_kexec_load),
+        ("keyctl", libc::SYS_keyctl),
+        ("mount", libc::SYS_mount),
+        ("name_to_handle_at", libc::SYS_name_to_handle_at),
+        ("open_by_handle_at", libc::SYS_open_by_handle_at),
+        ("perf_event_open", libc::SYS_perf_event_open),
+        ("personality", libc::SYS_personality),
+        ("pidfd_getfd", libc::SYS_pidfd_getfd),
+        ("pivot_root", libc::SYS_pivot_root),
+        ("process_vm_readv", libc::SYS_process_vm_readv),
+        ("process_vm_writev", libc::SYS_process_vm_writev),
+        ("ptrace", libc::SYS_ptrace),
+        ("quotactl", libc::SYS_quotactl),
+        ("reboot", libc::SYS_reboot),
+        ("request_key", libc::SYS_request_key),
+        ("setns", libc::SYS_setns),
+        ("settimeofday", libc::SYS_settimeofday),
+        ("swapoff", libc::SYS_swapoff),
+        ("swapon", libc::SYS_swapon),
+        ("syslog", libc::SYS_syslog),
+        ("umount2", libc::SYS_umount2),
+        ("unshare", libc::SYS_unshare),
+        ("userfaultfd", libc::SYS_userfaultfd),
+        ("vhangup", libc::SYS_vhangup),
+    ];
+
+    /// Returns the number of the syscall named `name`, if `name` can be named in config.toml.
+    #[must_use]
+    pub fn syscall_number(name: &str) -> Option<c_long> {
+        SYSCALLS
+            .iter()
+            .find_map(|&(other, number)| (other == name).then_some(number))
+    }
+
+    /// Returns a command that executes `args` under a filter denying
+    /// [`super::DEFAULT_DENIED_SYSCALLS`] and `denied`, and the file, if any, from which the command
+    /// reads the filter. The file must be kept open until the command is spawned. No filter is
+    /// installed on architectures for which none is defined.
+    pub fn command<'a>(
+        args: &[OsString],
+        denied: impl IntoIterator<Item = &'a str>,
+    ) -> Result<(Command, Option<File>)> {
+        let (program, args) = args
+            .split_first()
+            .ok_or_else(|| anyhow!("command is empty"))?;
+        let mut command = Command::new(program);
+        let Some(filter) = filter(denied)? else {
+            command.args(args);
+            return Ok((command, None));
+        };
+        // smoelius: Installing the filter sets `PR_SET_NO_NEW_PRIVS`, which would prevent a setuid
+        // `bwrap` from working. So `bwrap` is asked to install the filter in the sandboxed process.
+        if Path::new(program).file_name() == Some(OsStr::new("bwrap")) {
+            let file = bpf_file(&filter)?;
+            let fd = file.as_raw_fd();
+            command.arg("--seccomp").arg(fd.to_string()).args(args);
+            // smoelius: Clearing `FD_CLOEXEC` in the child only keeps other processes spawned in
+            // the meantime from inheriting the file.
+            unsafe {
+                command.pre_exec(move || {
+                    if libc::fcntl(fd, libc::F_SETFD, 0) == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+            return Ok((command, Some(file)));
+        }
+        command.args(args);
+        // smoelius: `install` only makes syscalls and does not allocate, so it is safe to call
+        // between `fork` and `exec`.
+        unsafe {
+            command.pre_exec(move || install(&filter));
+        }
+        Ok((command, None))
+    }
+
+    /// Returns an anonymous file containing `program` in the form `bwrap --seccomp` reads, i.e., an
+    /// array of `struct sock_filter`. The file's offset is 0.
+    fn bpf_file(program: &[sock_filter]) -> Result<File> {
+        let mut bytes = Vec::with_capacity(size_of_val(program));
+        for instruction in program {
+            bytes.extend(instruction.code.to_ne_bytes());
+            bytes.extend([instruction.jt, instruction.jf]);
+            bytes.extend(instruction.k.to_ne_bytes());
+        }
+        let mut file = tempfile::tempfile()?;
+        file.write_all(&bytes)?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(file)
+    }
+
+    /// Returns the filter's instructions, or `None` if no filter is defined for the current
+    /// architecture.
+    pub fn filter<'a>(
+        denied: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Option<Vec<sock_filter>>> {
+        let Some(audit_arch) = AUDIT_ARCH else {
+            return Ok(None);
+        };
+
+        let mut numbers = Vec::new();
+        for name in super::DEFAULT_DENIED_SYSCALLS.iter().copied().chain(denied) {
+            let number = syscall_number(name).ok_or_else(|| anyhow!("unknown syscall `{name}`"))?;
+            if !numbers.contains(&number) {
+                numbers.push(number);
+            }
+        }
+
+        // smoelius: Each check is a conditional jump over a `DENY`, so no jump offsets need to be
+        // computed.
+        let mut program = vec![
+            stmt(BPF_LD | BPF_W | BPF_ABS, ARCH_OFFSET),
+            jump(BPF_JEQ, audit_arch, 1, 0),
+            stmt(BPF_RET | BPF_K, DENY),
+            stmt(BPF_LD | BPF_W | BPF_ABS, NR_OFFSET),
+        ];
+        #[cfg(target_arch = "x86_64")]
+        program.extend([
+            jump(BPF_JGE, X32_SYSCALL_BIT, 0, 1),
+            stmt(BPF_RET | BPF_K, DENY),
+        ]);
+        for number in numbers {
+            program.extend([
+                jump(BPF_JEQ, u32::try_from(number)?, 0, 1),
+                stmt(BPF_RET | BPF_K, DENY),
+            ]);
+        }
+        program.extend([
+            jump(BPF_JEQ, u32::try_from(libc::SYS_ioctl)?, 1, 0),
+            stmt(BPF_RET | BPF_K, SECCOMP_RET_ALLOW),
+            stmt(BPF_LD | BPF_W | BPF_ABS, ARG1_OFFSET),
+        ]);
+        for request in [libc::TIOCSTI, libc::TIOCLINUX] {
+            let request = u32::try_from(request)?;
+            program.extend([jump(BPF_JEQ, request, 0, 1), stmt(BPF_RET | BPF_K, DENY)]);
+        }
+        program.push(stmt(BPF_RET | BPF_K, SECCOMP_RET_ALLOW));
+
+        // smoelius: `install` relies on this check, since it cannot construct an error message.
+        ensure!(
+            u16::try_from(program.len()).is_ok(),
+            "seccomp filter has too many instructions"
+        );
+
+        Ok(Some(program))
+    }
+
+    /// Installs `program` in the current process. Does not allocate, so it may be called between
+    /// `fork` and `exec`.
+    pub fn install(program: &[sock_filter]) -> std::io::Result<()> {
+        let Ok(len) = u16::try_from(program.len()) else {
+            return Err(std::io::Error::from_raw_os_error(libc::EINVAL));
+        };
+        let prog = sock_fprog {
+            len,
+            filter: program.as_ptr().cast_mut(),
+        };
+        unsafe {
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+                || libc::prctl(
+                    libc::PR_SET_SECCOMP,
+                    libc::SECCOMP_MODE_FILTER,
+                    &raw const prog,
+                ) != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    fn stmt(code: u32, k: u32) -> sock_filter {
+        sock_filter {
+            code: u16::try_from(code).unwrap(),
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+
+    /// Returns a conditional jump that compares the accumulator to `k` using `op` (e.g.,
+    /// `BPF_JEQ`), and skips `jt` instructions if the comparison succeeds and `jf` otherwise.
+    fn jump(op: u32, k: u32, jt: u8, jf: u8) -> sock_filter {
+        sock_filter {
+            code: u16::try_from(BPF_JMP | op | BPF_K).unwrap(),
+            jt,
+            jf,
+            k,
+        }
+    }
+}
+
+/// Returns the directories whose contents the wrapped build script, executed under `args`, must not
+/// modify, but that `args` makes writable: the package's manifest directory, and the packages that
+/// Cargo has finished extracting into `$CARGO_HOME/registry/src` and `$CARGO_HOME/git/checkouts`.
+///
+/// Directories the sandbox makes read-only need no scan. What a command other than `bwrap` makes
+/// writable is unknown, and scanning every extracted package after every build script would be too
+/// slow. So for such a command, only the manifest directory is returned.
+#[allow(dead_code)]
+fn protected_dirs(args: &[OsString]) -> Vec<PathBuf> {
+    let manifest_dir = env::var_os("CARGO_MANIFEST_DIR").map(|dir| resolve(Path::new(&dir)));
+    let Some(writable) = bwrap_writable(args) else {
+        return manifest_dir.into_iter().collect();
+    };
+    let writable = writable.iter().map(|dir| resolve(dir)).collect::<Vec<_>>();
+
+    let mut dirs = Vec::new();
+    // smoelius: The manifest directory is writable only if `args` binds it or one of its ancestors
+    // writable (e.g., `--bind / /`). A command that binds the manifest directory itself (e.g., the
+    // `native-build` profile's) makes it writable deliberately.
+    dirs.extend(manifest_dir.filter(|manifest_dir| {
+        !writable.contains(manifest_dir) && writable.iter().any(|dir| manifest_dir.starts_with(dir))
+    }));
+    if let Ok(cargo_home) = var_for(None, "CARGO_HOME_RESOLVED") {
+        for parent in ["registry/src", "git/checkouts"] {
+            let root = Path::new(&cargo_home).join(parent);
+            for dir in &writable {
+                if root.starts_with(dir) {
+                    dirs.extend(extracted_packages(&root));
+                } else if dir.starts_with(&root) {
+                    dirs.push(dir.clone());
+                }
+            }
+        }
+    }
+    dirs.sort();
+    dirs.dedup();
+    dirs
+}
+
+/// Returns the packages under `root` (i.e., `$CARGO_HOME/registry/src` or
+/// `$CARGO_HOME/git/checkouts`) that Cargo has finished extracting.
+#[allow(dead_code)]
+fn extracted_packages(root: &Path) -> Vec<PathBuf> {
+    let mut packages = Vec::new();
+    // smoelius: Cargo writes `.cargo-ok` once it has finished extracting a package. Packages that
+    // Cargo is still extracting are not protected, since Cargo is writing to them.
+    for index_dir in read_dir(root).into_iter().flatten().flatten() {
+        let package_dirs = read_dir(index_dir.path()).into_iter().flatten();
+        for package_dir in package_dirs.flatten() {
+            if package_dir.path().join(".cargo-ok").exists() {
+                packages.push(package_dir.path());
+            }
+        }
+    }
+    packages
+}
+
+/// `bwrap` options that take two arguments, the second of which is a destination made writable
+const BWRAP_WRITABLE_OPTIONS: &[&str] = &["--bind", "--bind-try", "--dev-bind", "--dev-bind-try"];
+
+/// `bwrap` options that take arguments, and how many, other than [`BWRAP_WRITABLE_OPTIONS`]
+const BWRAP_OPTIONS: &[(&str, usize)] = &[
+    ("--add-seccomp-fd", 1),
+    ("--args", 1),
+    ("--argv0", 1),
+    ("--bind-data", 2),
+    ("--block-fd", 1),
+    ("--cap-add", 1),
+    ("--cap-drop", 1),
+    ("--chdir", 1),
+    ("--chmod", 2),
+    ("--dev", 1),
+    ("--dir", 1),
+    ("--exec-label", 1),
+    ("--file", 2),
+    ("--file-label", 1),
+    ("--gid", 1),
+    ("--hostname", 1),
+    ("--info-fd", 1),
+    ("--json-status-fd", 1),
+    ("--lock-file", 1),
+    ("--mqueue", 1),
+    ("--overlay", 3),
+    ("--overlay-src", 1),
+    ("--perms", 1),
+    ("--pidns", 1),
+    ("--proc", 1),
+    ("--remount-ro", 1),
+    ("--ro-bind", 2),
+    ("--ro-bind-data", 2),
+    ("--ro-bind-try", 2),
+    ("--ro-overlay", 1),
+    ("--seccomp", 1),
+    ("--setenv", 2),
+    ("--size", 1),
+    ("--symlink", 2),
+    ("--sync-fd", 1),
+    ("--tmp-overlay", 1),
+    ("--tmpfs", 1),
+    ("--uid", 1),
+    ("--unsetenv", 1),
+    ("--userns", 1),
+    ("--userns-block-fd", 1),
+    ("--userns2", 1),
+];
+
+/// Returns the destinations that `args`, a `bwrap` command, binds writable, or `None` if `args` is
+/// not a `bwrap` command.
+#[allow(dead_code)]
+fn bwrap_writable(args: &[OsString]) -> Option<Vec<PathBuf>> {
+    let (program, args) = args.split_first()?;
+    if Path::new(program).file_name() != Some(OsStr::new("bwrap")) {
+        return None;
+    }
+    let mut writable = Vec::new();
+    let mut iter = args.iter();
+    // smoelius: `bwrap`'s options end at the first argument that is not an option, i.e., the
+    // command to execute.
+    while let Some(arg) = iter.next().and_then(|arg| arg.to_str()) {
+        if BWRAP_WRITABLE_OPTIONS.contains(&arg) {
+            let _: Option<&OsString> = iter.next();
+            writable.extend(iter.next().map(PathBuf::from));
+        } else if let Some(&(_, n)) = BWRAP_OPTIONS.iter().find(|&&(option, _)| option == arg) {
+            for _ in 0..n {
+                let _: Option<&OsString> = iter.next();
+            }
+        } else if !arg.starts_with("--") {
+            break;
+        }
+    }
+    Some(writable)
+}
+
+/// Detects modifications to a set of directories made after the scan was created.
+///
+/// A file's change time (ctime) is updated whenever the file is written, and unlike its
+/// modification time, it cannot be set by the process that wrote the file. Creating, removing, or
+/// renaming a file updates its directory's change time. So a file or directory can have been
+/// modified only if its change time is not earlier than that of a marker file created when the scan
+/// was. Such a file or directory is then compared to what the scan recorded when it was created
+/// (see [`Snapshot`]), so that one that was merely rewritten with the same contents (e.g., by
+/// `git checkout` or an editor) is not reported.
+#[allow(dead_code)]
+struct IntegrityScan {
+    roots: Vec<PathBuf>,
+    baseline: (i64, i64),
+    snapshots: BTreeMap<PathBuf, Snapshot>,
+}
+
+/// What [`IntegrityScan`] records about each file and directory
+#[allow(dead_code)]
+#[derive(Debug, PartialEq)]
+enum Snapshot {
+    /// The names of the directory's entries, sorted
+    Dir(Vec<OsString>),
+    /// The file's permissions and the SHA-256 hash of its contents, if it could be read
+    File(u32, Option<String>),
+    /// The symlink's target
+    Symlink(PathBuf),
+    /// Anything else, e.g., a socket
+    Other,
+}
+
+#[allow(dead_code)]
+impl IntegrityScan {
+    fn new(roots: Vec<PathBuf>) -> Result<Self> {
+        let marker = tempfile::NamedTempFile::new()?;
+        let metadata = marker.as_file().metadata()?;
+        let mut snapshots = BTreeMap::new();
+        walk(&roots, |path, metadata| {
+            snapshots.insert(path.to_path_buf(), Snapshot::new(path, metadata)?);
+            Ok(())
+        })?;
+        Ok(Self {
+            roots,
+            baseline: (metadata.ctime(), metadata.ctime_nsec()),
+            snapshots,
+        })
+    }
+
+    /// Returns the files and directories under `roots` that were modified, skipping target
+    /// directories (i.e., those containing a `CACHEDIR.TAG` file).
+    fn modified(&self) -> Result<Vec<PathBuf>> {
+        let mut modified = Vec::new();
+        walk(&self.roots, |path, metadata| {
+            if (metadata.ctime(), metadata.ctime_nsec()) >= self.baseline
+                && self.snapshots.get(path) != Some(&Snapshot::new(path, metadata)?)
+            {
+                modified.push(path.to_path_buf());
+            }
+            Ok(())
+        })?;
+        modified.sort();
+        Ok(modified)
+    }
+}
+
+#[allow(dead_code)]
+impl Snapshot {
+    fn new(path: &Path, metadata: &std::fs::Metadata) -> Result<Self> {
+        let file_type = metadata.file_type();
+        if file_type.is_dir() {
+            let mut names = read_dir(path)?
+                .map(|entry| entry.map(|entry| entry.file_name()))
+                .collect::<std::io::Result<Vec<_>>>()?;
+            names.sort();
+            Ok(Self::Dir(names))
+        } else if file_type.is_file() {
+            // smoelius: A file that cannot be read (e.g., because it is owned by another user)
+            // cannot have been written by the build script either, unless its permissions change.
+            let sha256 = read(path).ok().map(|contents| sha256_hex(&contents));
+            Ok(Self::File(metadata.mode(), sha256))
+        } else if file_type.is_symlink() {
+            Ok(Self::Symlink(std::fs::read_link(path)?))
+        } else {
+            Ok(Self::Other)
+        }
+    }
+}
+
+/// Calls `f` on each file and directory under `roots`, including `roots` themselves, but skipping
+/// target directories (i.e., those containing a `CACHEDIR.TAG` file). A root need not exist.
+#[allow(dead_code)]
+fn walk(
+    roots: &[PathBuf],
+    mut f: impl FnMut(&Path, &std::fs::Metadata) -> Result<()>,
+) -> Result<()> {
+    let mut stack = roots.to_vec();
+    while let Some(path) = stack.pop() {
+        let metadata = match symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            // smoelius: A file may also be removed while it is being walked.
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(error) => {
+                return Err(error).with_context(|| format!("failed to stat `{}`", path.display()));
+            }
+        };
+        if metadata.is_dir() {
+            if path.join("CACHEDIR.TAG").exists() {
+                continue;
+            }
+            for entry in read_dir(&path)? {
+                stack.push(entry?.path());
+            }
+        }
+        f(&path, &metadata)?;
+    }
+    Ok(())
+}
+
+/// A failure of a wrapped build script that appears to have been caused by the sandbox.
+#[derive(Debug, PartialEq)]
+struct Denial {
+    /// What the build script was likely trying to do, e.g., "accessing the network"
+    operation: String,
+    /// The line of stderr from which the denial was inferred
+    evidence: String,
+    /// The narrowest configuration change that would let the build script do what it was denied
+    remedy: Remedy,
+}
+
+/// A configuration change suggested for a [`Denial`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Remedy {
+    /// `network = true` in a `[sandbox.packages.PATTERN]` section
+    Network,
+    /// A `writable` entry in a `[sandbox.packages.PATTERN]` section, or the `native-build` profile
+    /// if the build script wrote to its own package's source directory
+    Writable,
+    /// An `[allow]` entry, i.e., no sandboxing at all
+    Unsandboxed,
+}
+
+/// Patterns that indicate a denial, most specific first. A line matches a pattern if it contains a
+/// string from the pattern's first list and, if the second list is nonempty, a string from the
+/// second list.
+const DENIAL_PATTERNS: &[(&[&str], &[&str], &str, Remedy)] = &[
+    (
+        &["Read-only file system"],
+        &[],
+        "writing outside of `OUT_DIR` and the temporary directory",
+        Remedy::Writable,
+    ),
+    (
+        &[
+            "Network is unreachable",
+            "Temporary failure in name resolution",
+        ],
+        &[],
+        "accessing the network",
+        Remedy::Network,
+    ),
+    (
+        &["socket", "connect", "sendto", "bind"],
+        &["Operation not permitted", "Permission denied"],
+        "accessing the network",
+        Remedy::Network,
+    ),
+    (
+        &["Permission denied"],
+        &[],
+        "accessing a file that the sandbox does not permit",
+        Remedy::Unsandboxed,
+    ),
+    (
+        &["Operation not permitted"],
+        &[],
+        "performing an operation that the sandbox does not permit",
+        Remedy::Unsandboxed,
+    ),
+];
+
+/// Infers, one line of stderr at a time, whether a wrapped build script failed because the sandbox
+/// denied it something. Only the most specific denial observed so far is kept, so stderr need not
+/// be collected.
+#[derive(Default)]
+struct Diagnosis {
+    /// The denial and its rank (lower is more specific)
+    best: Option<(usize, Denial)>,
+}
+
+impl Diagnosis {
+    fn observe(&mut self, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        let Some((rank, operation, remedy)) = classify(&line) else {
+            return;
+        };
+        // smoelius: Among lines of the same rank, the first is kept.
+        if self.best.as_ref().is_some_and(|&(best, _)| best <= rank) {
+            return;
+        }
+        self.best = Some((
+            rank,
+            Denial {
+                operation,
+                evidence: line.trim().to_owned(),
+                remedy,
+            },
+        ));
+    }
+
+    fn denial(self) -> Option<Denial> {
+        self.best.map(|(_, denial)| denial)
+    }
+}
+
+/// Returns the rank of the most specific denial that `line` indicates, the operation denied, and
+/// the suggested remedy. A `sandbox-exec` log line has rank 0; a line matching `DENIAL_PATTERNS[i]`
+/// has rank `i + 1`.
+fn classify(line: &str) -> Option<(usize, String, Remedy)> {
+    // smoelius: `sandbox-exec` logs lines like `Sandbox: ping(123) deny(1) network-outbound ...`.
+    if let Some(operation) = (|| {
+        let (_, rest) = line.split_once("deny(")?;
+        let (_, rest) = rest.split_once(')')?;
+        rest.split_whitespace().next()
+    })() {
+        let (operation, remedy) = if operation.starts_with("network") {
+            (String::from("accessing the network"), Remedy::Network)
+        } else if operation.starts_with("file-write") {
+            (
+                String::from("writing outside of `OUT_DIR` and the temporary directory"),
+                Remedy::Writable,
+            )
+        } else {
+            (format!("performing `{operation}`"), Remedy::Unsandboxed)
+        };
+        return Some((0, operation, remedy));
+    }
+
+    DENIAL_PATTERNS
+        .iter()
+        .position(|&(alternatives, requirements, _, _)| {
+            alternatives.iter().any(|s| line.contains(s))
+                && (requirements.is_empty() || requirements.iter().any(|s| line.contains(s)))
+        })
+        .map(|i| {
+            let (_, _, operation, remedy) = DENIAL_PATTERNS[i];
+            (i + 1, operation.to_owned(), remedy)
+        })
+}
+
+/// Returns the directory containing the first absolute path in `evidence`, if any. E.g., for
+/// `Sandbox: touch(4242) deny(1) file-write-create /Users/user/file`, returns `/Users/user`.
+fn written_dir(evidence: &str) -> Option<&str> {
+    let path = evidence
+        .split_whitespace()
+        .map(|word| word.trim_matches(|c: char| "\"'`,:;()".contains(c)))
+        .find(|word| word.starts_with('/'))?;
+    Path::new(path).parent()?.to_str()
+}
+
+impl std::fmt::Display for Denial {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = env::var("CARGO_PKG_NAME").unwrap_or_default();
+        let version = env::var("CARGO_PKG_VERSION").unwrap_or_default();
+        writeln!(
+            f,
+            "build-wrap: the build script of `{name}@{version}` appears to have been blocked by the \
+             sandbox while {}:",
+            self.operation
+        )?;
+        writeln!(f, "    {}", self.evidence)?;
+        if option_env!("BUILD_WRAP_DENIED").is_some() {
+            return write!(
+                f,
+                "build-wrap: `{name}` is denied by `$HOME/.config/build-wrap/config.toml` or a \
+                 project configuration file, so its build script is always sandboxed"
+            );
+        }
+        let (purpose, snippet) = match self.remedy {
+            Remedy::Network => (
+                format!("to allow `{name}`'s build script to access the network"),
+                format!("[sandbox.packages.\"{name}\"]\nnetwork = true"),
+            ),
+            Remedy::Writable => {
+                let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+                match written_dir(&self.evidence) {
+                    Some(dir)
+                        if !manifest_dir.is_empty()
+                            && Path::new(dir).starts_with(&manifest_dir) =>
+                    {
+                        (
+                            format!(
+                                "to allow `{name}`'s build script to write to its source directory"
+                            ),
+                            format!("[profiles.native-build]\npackages = [\"{name}\"]"),
+                        )
+                    }
+                    dir => (
+                        format!("to allow `{name}`'s build script to write outside of `OUT_DIR`"),
+                        format!(
+                            "[sandbox.packages.\"{name}\"]\nwritable = [\"{}\"]",
+                            dir.unwrap_or("/path/to/directory")
+                        ),
+                    ),
+                }
+            }
+            Remedy::Unsandboxed => (
+                format!("to build `{name}` without sandboxing"),
+                format!("[allow]\npackages = [\"{name}\"]"),
+            ),
+        };
+        writeln!(
+            f,
+            "build-wrap: {purpose}, add the following to `$HOME/.config/build-wrap/config.toml`:"
+        )?;
+        writeln!(f)?;
+        write!(f, "{snippet}")
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Answer {
+    Deny,
+    AllowOnce,
+    /// Allow always, by adding the contained entry to config.toml's `[allow]` section
+    AllowAlways(String),
+}
+
+const PROMPT: &str =
+    "[d]eny, allow [o]nce, allow this [v]ersion always, or allow this [p]ackage always? [d] ";
+
+/// Asks the user whether to rerun the failed build script without sandboxing. The question is
+/// asked on `/dev/tty` because Cargo captures the build script's stdin, stdout, and stderr. If no
+/// TTY is available (e.g., in CI), the answer is "deny".
+fn prompt(denial: Option<&Denial>) -> Result<bool> {
+    let Ok(mut tty) = OpenOptions::new().read(true).write(true).open("/dev/tty") else {
+        eprintln!(
+            "build-wrap: `BUILD_WRAP_INTERACTIVE` is enabled, but no TTY is available; denying"
+        );
+        return Ok(false);
+    };
+
+    // smoelius: Cargo runs build scripts in parallel. Locking the TTY keeps their prompts from
+    // interleaving.
+    tty.lock()?;
+
+    let name = env::var("CARGO_PKG_NAME").unwrap_or_default();
+    let version = env::var("CARGO_PKG_VERSION").unwrap_or_default();
+
+    writeln!(
+        tty,
+        "build-wrap: the build script of `{name}@{version}` failed under the sandbox"
+    )?;
+    if let Some(denial) = denial {
+        writeln!(
+            tty,
+            "build-wrap: it appears to have been blocked while {}:\n    {}",
+            denial.operation, denial.evidence
+        )?;
+    }
+
+    let mut reader = BufReader::new(tty.try_clone()?);
+    let answer = loop {
+        write!(tty, "{PROMPT}")?;
+        tty.flush()?;
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            break Answer::Deny;
+        }
+        if let Some(answer) = parse_answer(&line, &name, &version) {
+            break answer;
+        }
+    };
+
+    match answer {
+        Answer::Deny => Ok(false),
+        Answer::AllowOnce => Ok(true),
+        Answer::AllowAlways(entry) => {
+            // smoelius: Failing to persist the answer should not prevent the build script from
+            // running this time.
+            if let Err(error) = persist_allow(&entry) {
+                writeln!(
+                    tty,
+                    "build-wrap: warning: failed to allow `{entry}`: {error}"
+                )?;
+            } else {
+                writeln!(tty, "build-wrap: allowed `{entry}` in config.toml")?;
+            }
+            Ok(true)
+        }
+    }
+}
+
+fn parse_answer(line: &str, name: &str, version: &str) -> Option<Answer> {
+    match line.trim().to_ascii_lowercase().as_str() {
+        "" | "d" | "deny" => Some(Answer::Deny),
+        "o" | "once" => Some(Answer::AllowOnce),
+        "v" | "version" => Some(Answer::AllowAlways(format!("{name}@{version}"))),
+        "p" | "package" => Some(Answer::AllowAlways(name.to_owned())),
+        _ => None,
+    }
+}
+
+/// Runs `build-wrap allow entry` using the `build-wrap` that linked the wrapper build script.
+fn persist_allow(entry: &str) -> Result<()> {
+    let build_wrap =
+        option_env!("BUILD_WRAP_EXE").ok_or_else(|| anyhow!("`BUILD_WRAP_EXE` is undefined"))?;
+    // smoelius: The wrapper build script's stdout is interpreted by Cargo, so `build-wrap`'s stdout
+    // must not be inherited.
+    let status = Command::new(build_wrap)
+        .args(["allow", entry])
+        .stdout(Stdio::null())
+        .status()
+        .with_context(|| format!("failed to execute `{build_wrap}`"))?;
+    ensure!(status.success(), "`{build_wrap} allow {entry}` failed");
+    Ok(())
+}
+
+/// Verifies that the file at `sibling_path` has SHA-256 hash `expected_sha256`.
+///
+/// # Errors
+///
+/// If the file cannot be read, or if its hash is not `expected_sha256`.
+pub fn verify_sibling(sibling_path: &Path, expected_sha256: &str) -> Result<()> {
+    let contents = read(sibling_path)
+        .with_context(|| format!("failed to read `{}`", sibling_path.display()))?;
+    let actual_sha256 = sha256_hex(&contents);
+    ensure!(
+        actual_sha256 == expected_sha256,
+        "WARNING: `{}` was modified after it was linked and may have been tampered with (expected \
+         SHA-256 {expected_sha256}, found {actual_sha256}); refusing to execute it",
+        sibling_path.display()
+    );
+    Ok(())
+}
+
+/// Returns the SHA-256 hash of `bytes` as lowercase hexadecimal.
+#[must_use]
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .fold(String::new(), |mut s, byte| {
+            write!(s, "{byte:02x}").unwrap();
+            s
+        })
+}
+
+/// Name of the file in which the wrapper build script records how it was invoked. The file is
+/// written to `OUT_DIR`'s parent, alongside Cargo's `output` and `invoked.timestamp` files.
+pub const INVOKED_FILE_NAME: &str = "build-wrap-invoked";
+
+/// Variables that `record_invocation` records, in addition to those whose names begin with one of
+/// `RECORDED_VAR_PREFIXES`. These are the variables Cargo sets for build scripts, plus the few that
+/// `BUILD_WRAP_CMD` expansion and the build script's tools commonly need.
+const RECORDED_VARS: &[&str] = &[
+    "CARGO",
+    "CARGO_ENCODED_RUSTFLAGS",
+    "CARGO_HOME",
+    "CARGO_MAKEFLAGS",
+    "CARGO_MANIFEST_DIR",
+    "CARGO_MANIFEST_LINKS",
+    "CARGO_MANIFEST_PATH",
+    "CARGO_PRIMARY_PACKAGE",
+    "CARGO_TARGET_DIR",
+    "DEBUG",
+    "HOME",
+    "HOST",
+    "NUM_JOBS",
+    "OPT_LEVEL",
+    "OUT_DIR",
+    "PATH",
+    "PROFILE",
+    "RUSTC",
+    "RUSTC_LINKER",
+    "RUSTC_WORKSPACE_WRAPPER",
+    "RUSTC_WRAPPER",
+    "RUSTDOC",
+    "TARGET",
+    "TMPDIR",
+];
+
+/// Prefixes of the names of variables that `record_invocation` records.
+const RECORDED_VAR_PREFIXES: &[&str] = &["CARGO_CFG_", "CARGO_FEATURE_", "CARGO_PKG_", "DEP_"];
+
+/// Records the path of the renamed original build script and the parts of the environment the
+/// wrapper build script was invoked with that `build-wrap run` needs to reproduce the invocation.
+///
+/// The file consists of NUL-terminated fields: the sibling path, followed by one `KEY=VALUE` field
+/// per recorded environment variable. Only the variables in `RECORDED_VARS`, or whose names begin
+/// with one of `RECORDED_VAR_PREFIXES`, are recorded, so that secrets in the environment (e.g.,
+/// `CARGO_REGISTRY_TOKEN`) are not written to disk. The file is readable only by its owner.
+#[allow(dead_code)]
+fn record_invocation(sibling_path: &Path) -> Result<()> {
+    let Some(out_dir) = env::var_os("OUT_DIR") else {
+        return Ok(());
+    };
+    let Some(parent) = Path::new(&out_dir).parent() else {
+        return Ok(());
+    };
+
+    let mut buf = Vec::new();
+    buf.extend_from_slice(sibling_path.as_os_str().as_bytes());
+    buf.push(0);
+    for (key, value) in env::vars_os() {
+        if !is_recorded_var(&key) {
+            continue;
+        }
+        buf.extend_from_slice(key.as_bytes());
+        buf.push(b'=');
+        buf.extend_from_slice(value.as_bytes());
+        buf.push(0);
+    }
+
+    // smoelius: Remove any existing file first. `mode` applies only when a file is created, and a
+    // file written by an earlier version of `build-wrap` could be world-readable.
+    let path = parent.join(INVOKED_FILE_NAME);
+    match remove_file(&path) {
+        Err(error) if error.kind() != std::io::ErrorKind::NotFound => return Err(error.into()),
+        _ => {}
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)?;
+    file.write_all(&buf)?;
+
+    Ok(())
+}
+
+fn is_recorded_var(key: &OsStr) -> bool {
+    let Some(key) = key.to_str() else {
+        return false;
+    };
+    RECORDED_VARS.contains(&key)
+        || RECORDED_VAR_PREFIXES
+            .iter()
+            .any(|prefix| key.starts_with(prefix))
+}
+
+/// Policy for the `cargo:` directives that a wrapped build script prints. `build-wrap` determines
+/// the policy from config.toml when the wrapper build script is linked, and passes it to the
+/// wrapper build script's compilation in `BUILD_WRAP_POLICY`, one `key=value` pair per line.
+#[derive(Debug, Default, PartialEq)]
+pub struct Policy {
+    /// Action for `rustc-link-arg` and its variants
+    pub rustc_link_arg: Action,
+    /// Action for `rustc-link-search` and the `-L` flags of `rustc-flags`
+    pub rustc_link_search: Action,
+    /// Action for `rustc-env`
+    pub rustc_env: Action,
+    /// Action for `rustc-cfg`
+    pub rustc_cfg: Action,
+    /// Directories, in addition to `OUT_DIR` and `SYSTEM_LIBRARY_DIRECTORIES`, that
+    /// `rustc-link-search` may name when it is restricted
+    pub link_search_directories: Vec<String>,
+    /// Names (e.g., `foo`) or name-value pairs (e.g., `foo="bar"`) that `rustc-cfg` may set when it
+    /// is restricted
+    pub allowed_cfgs: Vec<String>,
+    /// Actions for the rules of the `OUT_DIR` audit (see `audit_out_dir`). A rule that is absent
+    /// has its default action.
+    pub audit: BTreeMap<AuditRule, AuditAction>,
+    /// Size in bytes above which the `large-files` rule flags a file
+    pub max_file_size: Option<u64>,
+}
+
+/// A rule of the audit of `OUT_DIR` that follows a wrapped build script's run
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum AuditRule {
+    /// Flags symbolic links that point outside of `OUT_DIR`. A later compilation step, which is not
+    /// sandboxed, could read an arbitrary file through such a link.
+    Symlinks,
+    /// Flags executable files
+    Executables,
+    /// Flags `.rs` files that `include!`, `include_bytes!`, or `include_str!` an absolute path
+    /// outside of `OUT_DIR`
+    AbsoluteIncludes,
+    /// Flags files larger than `max-file-size`
+    LargeFiles,
+}
+
+impl AuditRule {
+    /// Every rule, in order
+    pub const ALL: [Self; 4] = [
+        Self::Symlinks,
+        Self::Executables,
+        Self::AbsoluteIncludes,
+        Self::LargeFiles,
+    ];
+
+    /// Returns the rule's key in the `[audit]` section, e.g., `absolute-includes`.
+    #[must_use]
+    pub fn key(self) -> &'static str {
+        match self {
+            Self::Symlinks => "symlinks",
+            Self::Executables => "executables",
+            Self::AbsoluteIncludes => "absolute-includes",
+            Self::LargeFiles => "large-files",
+        }
+    }
+
+    /// Returns the action for the rule when configuration does not set one.
+    #[must_use]
+    pub fn default_action(self) -> AuditAction {
+        match self {
+            Self::Executables => AuditAction::Allow,
+            Self::Symlinks | Self::AbsoluteIncludes | Self::LargeFiles => AuditAction::Warn,
+        }
+    }
+}
+
+/// What the `OUT_DIR` audit does about an entry that a rule flags. Ordered from least to most
+/// strict.
+#[derive(Clone, Copy, Debug, Default, Eq, Ord, PartialEq, PartialOrd)]
+pub enum AuditAction {
+    /// Do not check the rule
+    #[default]
+    Allow,
+    /// Report the entry, but do not fail the build
+    Warn,
+    /// Report the entry and fail the build
+    Deny,
+}
+
+impl AuditAction {
+    /// The actions' names, as written in a configuration file
+    const NAMES: &[(&str, Self)] = &[
+        ("allow", Self::Allow),
+        ("warn", Self::Warn),
+        ("deny", Self::Deny),
+    ];
+
+    /// # Errors
+    ///
+    /// If `s` is not `allow`, `warn`, or `deny`.
+    pub fn parse(s: &str) -> Result<Self> {
+        parse_name(Self::NAMES, s, "audit action")
+    }
+
+    /// Returns the action's name, as written in a configuration file.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        name_of(Self::NAMES, self)
+    }
+}
+
+/// Actions are ordered from least to most strict.
+#[derive(Clone, Copy, Debug, Default, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Action {
+    /// Forward the directive to Cargo
+    #[default]
+    Allow,
+    /// Meaningful only for `rustc-link-search` and `rustc-cfg`. For other directives, treated as
+    /// `Deny`.
+    Restrict,
+    /// Reject the directive
+    Deny,
+}
+
+impl Action {
+    /// The actions' names, as written in a configuration file
+    const NAMES: &[(&str, Self)] = &[
+        ("allow", Self::Allow),
+        ("restrict", Self::Restrict),
+        ("deny", Self::Deny),
+    ];
+
+    /// # Errors
+    ///
+    /// If `s` is not `allow`, `deny`, or `restrict`.
+    pub fn parse(s: &str) -> Result<Self> {
+        parse_name(Self::NAMES, s, "action")
+    }
+
+    /// Returns the action's name, as written in a configuration file.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        name_of(Self::NAMES, self)
+    }
+}
+
+/// Returns the value that `s` names in `names`. `what` describes the values in error messages.
+fn parse_name<T: Copy>(names: &[(&str, T)], s: &str, what: &str) -> Result<T> {
+    names
+        .iter()
+        .find(|&&(name, _)| name == s)
+        .map(|&(_, value)| value)
+        .ok_or_else(|| anyhow!("unrecognized {what} `{s}`"))
+}
+
+/// Returns the name of `value` in `names`.
+fn name_of<T: Copy + PartialEq>(names: &[(&'static str, T)], value: T) -> &'static str {
+    names
+        .iter()
+        .find(|(_, other)| *other == value)
+        .map(|&(name, _)| name)
+        .unwrap()
+}
+
+/// Directories that `rustc-link-search` may name when it is restricted, regardless of
+/// `link-search-directories`.
+pub const SYSTEM_LIBRARY_DIRECTORIES: &[&str] = &[
+    "/lib",
+    "/lib64",
+    "/opt/homebrew/lib",
+    "/usr/lib",
+    "/usr/lib64",
+    "/usr/local/lib",
+];
+
+const LINK_ARG_KEYS: &[&str] = &[
+    "rustc-cdylib-link-arg",
+    "rustc-link-arg",
+    "rustc-link-arg-benches",
+    "rustc-link-arg-bin",
+    "rustc-link-arg-bins",
+    "rustc-link-arg-cdylib",
+    "rustc-link-arg-examples",
+    "rustc-link-arg-tests",
+];
+
+const LINK_SEARCH_KINDS: &[&str] = &["all", "crate", "dependency", "framework", "native"];
+
+impl Policy {
+    /// Parses the format produced by [`Policy::to_env_string`].
+    ///
+    /// # Errors
+    ///
+    /// If a line is not of the form `key=value`, or if a key or action is unrecognized.
+    #[allow(dead_code)]
+    pub fn parse(s: &str) -> Result<Self> {
+        let mut policy = Self::default();
+        for line in s.lines().filter(|line| !line.is_empty()) {
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow!("malformed policy line `{line}`"))?;
+            match key {
+                "rustc-link-arg" => policy.rustc_link_arg = Action::parse(value)?,
+                "rustc-link-search" => policy.rustc_link_search = Action::parse(value)?,
+                "rustc-env" => policy.rustc_env = Action::parse(value)?,
+                "rustc-cfg" => policy.rustc_cfg = Action::parse(value)?,
+                "link-search-directory" => policy.link_search_directories.push(value.to_owned()),
+                "allowed-cfg" => policy.allowed_cfgs.push(value.to_owned()),
+                "max-file-size" => policy.max_file_size = Some(value.parse()?),
+                _ => {
+                    let rule = key
+                        .strip_prefix("audit-")
+                        .and_then(|key| AuditRule::ALL.into_iter().find(|rule| rule.key() == key))
+                        .ok_or_else(|| anyhow!("unrecognized policy key `{key}`"))?;
+                    policy.audit.insert(rule, AuditAction::parse(value)?);
+                }
+            }
+        }
+        Ok(policy)
     }
 
+    /// Returns the policy in the form that [`Policy::parse`] accepts, i.e., that of
+    /// `BUILD_WRAP_POLICY`.
+    #[must_use]
+    pub fn to_env_string(&self) -> String {
+        let mut s = String::new();
+        for (key, action) in [
+            ("rustc-link-arg", self.rustc_link_arg),
+            ("rustc-link-search", self.rustc_link_search),
+            ("rustc-env", self.rustc_env),
+            ("rustc-cfg", self.rustc_cfg),
+        ] {
+            writeln!(s, "{key}={}", action.as_str()).unwrap();
+        }
+        for dir in &self.link_search_directories {
+            writeln!(s, "link-search-directory={dir}").unwrap();
+        }
+        for cfg in &self.allowed_cfgs {
+            writeln!(s, "allowed-cfg={cfg}").unwrap();
+        }
+        for (rule, action) in &self.audit {
+            writeln!(s, "audit-{}={}", rule.key(), action.as_str()).unwrap();
+        }
+        if let Some(max_file_size) = self.max_file_size {
+            writeln!(s, "max-file-size={max_file_size}").unwrap();
+        }
+        s
+    }
+
+    /// Returns the action for audit rule `rule`.
+    #[must_use]
+    pub fn audit_action(&self, rule: AuditRule) -> AuditAction {
+        self.audit
+            .get(&rule)
+            .copied()
+            .unwrap_or_else(|| rule.default_action())
+    }
+
+    /// Returns why `line` is rejected, if it is a directive that the policy rejects.
+    fn rejection(&self, line: &str, out_dir: Option<&Path>) -> Option<String> {
+        let directive = line
+            .strip_prefix("cargo::")
+            .or_else(|| line.strip_prefix("cargo:"))?;
+        let (key, value) = directive.split_once('=')?;
+        match key {
+            _ if LINK_ARG_KEYS.contains(&key) => (self.rustc_link_arg != Action::Allow)
+                .then(|| format!("`{key}` directives are denied")),
+            "rustc-link-search" => self.link_search_rejection(value, out_dir),
+            // smoelius: `rustc-flags` may contain only `-l` and `-L` flags. The latter are treated
+            // like `rustc-link-search` directives.
+            "rustc-flags" => {
+                let mut tokens = value.split_whitespace();
+                while let Some(token) = tokens.next() {
+                    let path = if token == "-L" {
+                        tokens.next().unwrap_or_default()
+                    } else if let Some(path) = token.strip_prefix("-L") {
+                        path
+                    } else {
+                        continue;
+                    };
+                    if let Some(reason) = self.link_search_rejection(path, out_dir) {
+                        return Some(reason);
+                    }
+                }
+                None
+            }
+            "rustc-env" => (self.rustc_env != Action::Allow)
+                .then(|| String::from("`rustc-env` directives are denied")),
+            "rustc-cfg" => match self.rustc_cfg {
+                Action::Allow => None,
+                Action::Deny => Some(String::from("`rustc-cfg` directives are denied")),
+                Action::Restrict => {
+                    let name = value.split_once('=').map_or(value, |(name, _)| name);
+                    (!self
+                        .allowed_cfgs
+                        .iter()
+                        .any(|cfg| cfg == value || cfg == name))
+                    .then(|| format!("`{value}` is not in `allowed-cfgs`"))
+                }
+            },
+            _ => None,
+        }
+    }
+
+    fn link_search_rejection(&self, value: &str, out_dir: Option<&Path>) -> Option<String> {
+        match self.rustc_link_search {
+            Action::Allow => None,
+            Action::Deny => Some(String::from("`rustc-link-search` directives are denied")),
+            Action::Restrict => {
+                let path = value
+                    .split_once('=')
+                    .filter(|(kind, _)| LINK_SEARCH_KINDS.contains(kind))
+                    .map_or(value, |(_, path)| path);
+                let resolved = resolve(Path::new(path));
+                let allowed = out_dir
+                    .into_iter()
+                    .chain(SYSTEM_LIBRARY_DIRECTORIES.iter().map(Path::new))
+                    .chain(self.link_search_directories.iter().map(Path::new))
+                    .any(|dir| resolved.starts_with(resolve(dir)));
+                (!allowed).then(|| {
+                    format!(
+                        "`{path}` is not in `OUT_DIR`, a system library directory, or \
+                         `link-search-directories`"
+                    )
+                })
+            }
+        }
+    }
+}
+
+/// Forwards each line of `stdout` that `policy` does not reject. Each rejected line is replaced
+/// with a `cargo:warning=` directive explaining the rejection, so that Cargo reports it.
+///
+/// Lines are forwarded byte for byte. Cargo ignores lines that are not valid UTF-8, so such lines
+/// cannot be directives and are never rejected.
+#[allow(dead_code)]
+fn filter_directives(stdout: &[u8], policy: &Policy, out_dir: Option<&Path>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for line in stdout.split_inclusive(|&c| c == b'\n') {
+        let rejection = std::str::from_utf8(line).ok().and_then(|line| {
+            let directive = line.trim_end_matches(['\n', '\r']);
+            let reason = policy.rejection(directive, out_dir)?;
+            Some(format!("build-wrap: rejected `{directive}`: {reason}"))
+        });
+        if let Some(message) = rejection {
+            eprintln!("{message}");
+            buf.extend_from_slice(format!("cargo:warning={message}\n").as_bytes());
+        } else {
+            buf.extend_from_slice(line);
+        }
+    }
+    buf
+}
+
+/// Name of the file, next to `OUT_DIR`, to which the inventory of `OUT_DIR` is written. Each line
+/// describes one entry: its kind (`dir`, `file`, `executable`, `symlink`, or `other`), its size in
+/// bytes, its path relative to `OUT_DIR`, and, for a symbolic link, its target, separated by tabs.
+pub const INVENTORY_FILE_NAME: &str = "build-wrap-out-dir";
+
+/// An entry of `OUT_DIR` that an audit rule flagged
+#[derive(Debug, PartialEq)]
+struct Finding {
+    rule: AuditRule,
+    path: PathBuf,
+    /// Why the entry was flagged, e.g., "points to `/etc/passwd`"
+    reason: String,
+}
+
+/// Inventories `out_dir`, writes the inventory next to it (see [`INVENTORY_FILE_NAME`]), and
+/// reports the entries that `policy`'s audit rules flag. Fails if any rule whose action is `deny`
+/// flagged an entry.
+#[allow(dead_code)]
+fn audit(out_dir: &Path, policy: &Policy) -> Result<()> {
+    let (inventory, findings) = audit_out_dir(out_dir, policy)?;
+
+    if let Some(parent) = out_dir.parent() {
+        write(parent.join(INVENTORY_FILE_NAME), inventory)?;
+    }
+
+    let mut denied = Vec::new();
+    for finding in findings {
+        let message = format!(
+            "build-wrap: `{}` {} (`{}`)",
+            finding.path.display(),
+            finding.reason,
+            finding.rule.key()
+        );
+        if policy.audit_action(finding.rule) == AuditAction::Deny {
+            denied.push(message);
+        } else {
+            eprintln!("{message}");
+            println!("cargo:warning={message}");
+        }
+    }
+    ensure!(
+        denied.is_empty(),
+        "`OUT_DIR` audit failed:\n{}",
+        denied
+            .iter()
+            .map(|message| format!("    {message}"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    );
+
     Ok(())
 }
 
-pub trait ToUtf8 {
-    fn to_utf8(&self) -> std::result::Result<&str, Utf8Error>;
+/// Returns the inventory of `out_dir` and the entries that `policy`'s audit rules flag. Rules whose
+/// action is `allow` are not checked.
+#[allow(dead_code)]
+fn audit_out_dir(out_dir: &Path, policy: &Policy) -> Result<(String, Vec<Finding>)> {
+    let checked = |rule| policy.audit_action(rule) != AuditAction::Allow;
+    let resolved_out_dir = resolve(out_dir);
+
+    let mut lines = Vec::new();
+    let mut findings = Vec::new();
+    let mut stack = vec![out_dir.to_path_buf()];
+    while let Some(path) = stack.pop() {
+        let metadata = symlink_metadata(&path)
+            .with_context(|| format!("failed to stat `{}`", path.display()))?;
+        let file_type = metadata.file_type();
+        let executable = file_type.is_file() && metadata.mode() & 0o111 != 0;
+        let kind = if file_type.is_dir() {
+            "dir"
+        } else if file_type.is_symlink() {
+            "symlink"
+        } else if executable {
+            "executable"
+        } else if file_type.is_file() {
+            "file"
+        } else {
+            "other"
+        };
+        let relative = path.strip_prefix(out_dir).unwrap_or(&path);
+        let mut line = format!("{kind}\t{}\t{}", metadata.len(), relative.display());
+        let mut flag = |rule, reason| {
+            findings.push(Finding {
+                rule,
+                path: path.clone(),
+                reason,
+            });
+        };
+
+        if file_type.is_dir() {
+            for entry in read_dir(&path)? {
+                stack.push(entry?.path());
+            }
+        } else if file_type.is_symlink() {
+            let target = std::fs::read_link(&path)?;
+            write!(line, "\t{}", target.display()).unwrap();
+            let parent = path.parent().unwrap_or(out_dir);
+            if checked(AuditRule::Symlinks)
+                && !resolve(&parent.join(&target)).starts_with(&resolved_out_dir)
+            {
+                flag(
+                    AuditRule::Symlinks,
+                    format!("points outside of `OUT_DIR`, to `{}`", target.display()),
+                );
+            }
+        } else if file_type.is_file() {
+            if executable && checked(AuditRule::Executables) {
+                flag(AuditRule::Executables, String::from("is executable"));
+            }
+            let max_file_size = policy.max_file_size.unwrap_or(u64::MAX);
+            if metadata.len() > max_file_size && checked(AuditRule::LargeFiles) {
+                flag(
+                    AuditRule::LargeFiles,
+                    format!("is {} bytes, more than {max_file_size}", metadata.len()),
+                );
+            }
+            if path.extension() == Some(OsStr::new("rs")) && checked(AuditRule::AbsoluteIncludes) {
+                let contents = read(&path)?;
+                for included in absolute_includes(&String::from_utf8_lossy(&contents)) {
+                    if !resolve(Path::new(&included)).starts_with(&resolved_out_dir) {
+                        flag(
+                            AuditRule::AbsoluteIncludes,
+                            format!("includes `{included}`, which is outside of `OUT_DIR`"),
+                        );
+                    }
+                }
+            }
+        }
+
+        lines.push(line);
+    }
+
+    lines.sort();
+    findings.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut inventory = lines.join("\n");
+    inventory.push('\n');
+    Ok((inventory, findings))
+}
+
+/// Returns the absolute paths that `contents` passes as string literals to `include!`,
+/// `include_bytes!`, or `include_str!`. Paths built with, e.g., `concat!` are not returned.
+#[allow(dead_code)]
+fn absolute_includes(contents: &str) -> Vec<String> {
+    let mut paths = Vec::new();
+    for name in ["include!", "include_bytes!", "include_str!"] {
+        for (i, _) in contents.match_indices(name) {
+            let rest = contents[i + name.len()..].trim_start();
+            let Some(rest) = rest.strip_prefix(['(', '[', '{']) else {
+                continue;
+            };
+            paths.extend(string_literal(rest.trim_start()).filter(|path| path.starts_with('/')));
+        }
+    }
+    paths
+}
+
+/// Returns the value of the (possibly raw) string literal at the start of `s`, if any.
+#[allow(dead_code)]
+fn string_literal(s: &str) -> Option<String> {
+    if let Some(rest) = s.strip_prefix('r') {
+        let hashes = rest.len() - rest.trim_start_matches('#').len();
+        let rest = rest[hashes..].strip_prefix('"')?;
+        let terminator = format!("\"{}", "#".repeat(hashes));
+        return rest.find(&terminator).map(|end| rest[..end].to_owned());
+    }
+    let mut value = String::new();
+    let mut chars = s.strip_prefix('"')?.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some(value),
+            '\\' => value.push(chars.next()?),
+            _ => value.push(c),
+        }
+    }
+    None
 }
 
-impl<T: AsRef<Path>> ToUtf8 for T {
-    fn to_utf8(&self) -> std::result::Result<&str, Utf8Error> {
-        std::str::from_utf8(self.as_ref().as_os_str().as_bytes())
-    }
+/// Canonicalizes `path` if possible. Otherwise, removes `.` and `..` components lexically, so that,
+/// e.g., `{OUT_DIR}/../..` is not considered to be within `OUT_DIR`.
+fn resolve(path: &Path) -> PathBuf {
+    canonicalize(path).unwrap_or_else(|_| {
+        let mut resolved = PathBuf::new();
+        for component in path.components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    resolved.pop();
+                }
+                _ => resolved.push(component),
+            }
+        }
+        resolved
+    })
 }
 
-pub fn split_and_expand(build_script_path: &Path) -> Result<Vec<String>> {
+/// Splits the `BUILD_WRAP_CMD` that the current crate was compiled with into words and expands
+/// each (see [`expand`]). In a wrapper build script, that is the command chosen when the build
+/// script was linked. Other users should call [`split_and_expand_with`].
+pub fn split_and_expand(build_script_path: &Path) -> Result<Vec<OsString>> {
     let cmd =
         option_env!("BUILD_WRAP_CMD").ok_or_else(|| anyhow!("`BUILD_WRAP_CMD` is undefined"))?;
+    split_and_expand_with(cmd, build_script_path, |key| {
+        var_for(Some(build_script_path), key)
+    })
+}
+
+/// Splits `cmd` (e.g., a `BUILD_WRAP_CMD`) into words as a shell would, and expands each word as
+/// a wrapper build script does (see [`expand_with`]). Variables are looked up with `var`. A word
+/// that uses the list form of expansion (e.g., `{DEP_DIRS:%...}`) may expand to several words.
+///
+/// # Errors
+///
+/// If `cmd` is malformed, if a variable it refers to cannot be found, or if `cmd` expands to no
+/// words.
+pub fn split_and_expand_with(
+    cmd: &str,
+    build_script_path: &Path,
+    var: impl Fn(&str) -> Result<OsString, env::VarError>,
+) -> Result<Vec<OsString>> {
     let args = split_escaped(cmd)?;
-    let expanded_args = args
-        .into_iter()
-        .map(|arg| expand(&arg, Some(build_script_path)))
-        .collect::<Result<Vec<_>>>()?;
+    let mut expanded_args = Vec::new();
+    for arg in args {
+        expanded_args.extend(expand_arg(&arg, Some(build_script_path), &var)?);
+    }
     eprintln!("expanded `BUILD_WRAP_CMD`: {expanded_args:#?}");
     ensure!(
         !expanded_args.is_empty(),
@@ -128,152 +1765,539 @@ pub fn split_and_expand(build_script_path: &Path) -> Result<Vec<String>> {
     Ok(expanded_args)
 }
 
-fn split_escaped(mut s: &str) -> Result<Vec<String>> {
-    let mut v = vec![String::new()];
-
-    while let Some(i) = s.find(|c: char| c.is_ascii_whitespace() || c == '\\') {
-        debug_assert!(!v.is_empty());
-        // smoelius: Only the last string in `v` can be empty.
-        debug_assert!(
-            v.iter()
-                .position(String::is_empty)
-                .is_none_or(|i| i == v.len() - 1)
-        );
-
-        let c = s.as_bytes()[i];
-
-        v.last_mut().unwrap().push_str(&s[..i]);
+/// Splits `s` into words as a POSIX shell would, but without performing any expansions. That is,
+/// words are separated by unquoted spaces, tabs, and newlines; single quotes preserve everything
+/// they enclose; double quotes preserve everything they enclose except `\` before `$`, `` ` ``,
+/// `"`, `\`, or a newline; an unquoted `\` preserves the next character; and a `#` at the start of
+/// a word begins a comment that extends to the end of the line.
+///
+/// A brace that is single quoted or backslash escaped is doubled, so that [`expand_with`] treats it
+/// literally. Other braces are left as-is, so that, e.g., `{VAR}` and `"{VAR}"` are expanded. A
+/// `{...}` group is copied verbatim, even if it contains whitespace or quotes.
+// smoelius: Each arm is one state transition. Merging arms with identical bodies would obscure that.
+#[allow(clippy::match_same_arms)]
+fn split_escaped(s: &str) -> Result<Vec<String>> {
+    #[derive(Clone, Copy)]
+    enum State {
+        Delimiter,
+        Backslash,
+        Unquoted,
+        UnquotedBackslash,
+        SingleQuoted,
+        DoubleQuoted,
+        DoubleQuotedBackslash,
+        Comment,
+    }
 
-        s = &s[i + 1..];
+    fn push_literal(word: &mut String, c: char) {
+        if c == '{' || c == '}' {
+            word.push(c);
+        }
+        word.push(c);
+    }
 
-        // smoelius: `i` shouldn't be needed anymore.
-        #[allow(unused_variables, clippy::let_unit_value)]
-        let i = ();
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut state = State::Delimiter;
+    let mut chars = s.chars().peekable();
 
-        if c.is_ascii_whitespace() {
-            if !v.last().unwrap().is_empty() {
-                v.push(String::new());
+    while let Some(c) = chars.next() {
+        state = match (state, c) {
+            // smoelius: A group is copied verbatim, so that whitespace and quotes within it are
+            // interpreted when the group is expanded.
+            (State::Delimiter | State::Unquoted, '{') => {
+                push_group(&mut word, &mut chars)?;
+                State::Unquoted
             }
-            continue;
-        }
+            (State::DoubleQuoted, '{') => {
+                push_group(&mut word, &mut chars)?;
+                State::DoubleQuoted
+            }
+            (State::Delimiter, ' ' | '\t' | '\n') => State::Delimiter,
+            (State::Delimiter, '#') => State::Comment,
+            (State::Delimiter | State::Unquoted, '\'') => State::SingleQuoted,
+            (State::Delimiter | State::Unquoted, '"') => State::DoubleQuoted,
+            (State::Delimiter, '\\') => State::Backslash,
+            (State::Unquoted, '\\') => State::UnquotedBackslash,
+            (State::Unquoted, ' ' | '\t' | '\n') => {
+                words.push(std::mem::take(&mut word));
+                State::Delimiter
+            }
+            (State::Delimiter | State::Unquoted, c) => {
+                word.push(c);
+                State::Unquoted
+            }
+            // smoelius: A backslash followed by a newline is a line continuation.
+            (State::Backslash, '\n') => State::Delimiter,
+            (State::UnquotedBackslash, '\n') => State::Unquoted,
+            (State::Backslash | State::UnquotedBackslash, c) => {
+                push_literal(&mut word, c);
+                State::Unquoted
+            }
+            (State::SingleQuoted, '\'') => State::Unquoted,
+            (State::SingleQuoted, c) => {
+                push_literal(&mut word, c);
+                State::SingleQuoted
+            }
+            (State::DoubleQuoted, '"') => State::Unquoted,
+            (State::DoubleQuoted, '\\') => State::DoubleQuotedBackslash,
+            (State::DoubleQuoted, c) => {
+                word.push(c);
+                State::DoubleQuoted
+            }
+            (State::DoubleQuotedBackslash, '\n') => State::DoubleQuoted,
+            (State::DoubleQuotedBackslash, c @ ('$' | '`' | '"' | '\\')) => {
+                word.push(c);
+                State::DoubleQuoted
+            }
+            (State::DoubleQuotedBackslash, c) => {
+                word.push('\\');
+                word.push(c);
+                State::DoubleQuoted
+            }
+            (State::Comment, '\n') => State::Delimiter,
+            (State::Comment, _) => State::Comment,
+        };
+    }
 
-        // smoelius: If the previous `if` fails, then `c` must be a backslash.
-        if !s.is_empty() {
-            let c = s.as_bytes()[0];
-            // smoelius: Verify that `c` is a legally escapable character before subslicing `s`.
-            ensure!(
-                c.is_ascii_whitespace() || c == b'\\',
-                "illegally escaped character"
-            );
-            s = &s[1..];
-            v.last_mut().unwrap().push(c as char);
-            continue;
+    match state {
+        State::Delimiter | State::Comment => {}
+        State::Unquoted => words.push(word),
+        // smoelius: As in a POSIX shell, a trailing backslash is preserved.
+        State::Backslash | State::UnquotedBackslash => {
+            word.push('\\');
+            words.push(word);
+        }
+        State::SingleQuoted | State::DoubleQuoted | State::DoubleQuotedBackslash => {
+            bail!("missing closing quote");
         }
-
-        bail!("trailing backslash");
     }
 
-    // smoelius: Push whatever is left.
-    v.last_mut().unwrap().push_str(s);
+    Ok(words)
+}
 
-    if v.last().unwrap().is_empty() {
-        v.pop();
+/// Pushes the `{...}` group whose `{` was just consumed from `chars` onto `word`, verbatim. If the
+/// `{` begins the escape `{{`, pushes just the escape.
+fn push_group(word: &mut String, chars: &mut Peekable<Chars>) -> Result<()> {
+    word.push('{');
+    if chars.next_if_eq(&'{').is_some() {
+        word.push('{');
+        return Ok(());
+    }
+    let mut depth = 1;
+    while depth > 0 {
+        let Some(c) = chars.next() else {
+            bail!("unbalanced '{{'");
+        };
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ => {}
+        }
+        word.push(c);
     }
+    Ok(())
+}
 
-    debug_assert!(!v.iter().any(String::is_empty));
+/// Like [`expand_with`], but looks up variables in the environment.
+#[allow(dead_code)]
+pub fn expand(cmd: &str, build_script_path: Option<&Path>) -> Result<OsString> {
+    expand_with(cmd, build_script_path, |key| {
+        var_for(build_script_path, key)
+    })
+}
 
-    Ok(v)
+/// Expands the `{}` and `{VAR}` occurrences in `cmd`, as described in the README's "How
+/// `BUILD_WRAP_CMD` is expanded". `{}` expands to `build_script_path`, and variables are looked
+/// up with `var`, with the synthesized variables (e.g., `BUILD_SCRIPT_DIR`) computed from
+/// `build_script_path`.
+///
+/// # Errors
+///
+/// If `cmd` is malformed, or if a variable it refers to cannot be found.
+#[allow(dead_code)]
+pub fn expand_with(
+    cmd: &str,
+    build_script_path: Option<&Path>,
+    var: impl Fn(&str) -> Result<OsString, env::VarError>,
+) -> Result<OsString> {
+    expand_text(cmd, build_script_path, &var)
 }
 
-fn expand(mut cmd: &str, build_script_path: Option<&Path>) -> Result<String> {
-    let build_script_path_as_str = build_script_path
-        .map(|path| path.to_utf8().map(ToOwned::to_owned))
-        .transpose()?;
+/// A `{...}` group within a `BUILD_WRAP_CMD` argument
+#[derive(Debug, PartialEq)]
+enum Group<'a> {
+    /// `{}`
+    Path,
+    /// `{VAR}`
+    Var(&'a str),
+    /// `{VAR:-default}`: `VAR`'s value if set and nonempty, and `default` otherwise
+    Default(&'a str, &'a str),
+    /// `{VAR:+words}`: `words` if `VAR` is set and nonempty, and nothing otherwise
+    Optional(&'a str, &'a str),
+    /// `{VAR:%words}`: `words` once for each nonempty entry of the colon-separated list in `VAR`,
+    /// with `{VAR}` referring to the entry
+    Each(&'a str, &'a str),
+}
 
-    let mut buf = String::new();
+impl<'a> Group<'a> {
+    fn parse(content: &'a str) -> Self {
+        if content.is_empty() {
+            return Self::Path;
+        }
+        if let Some((key, rest)) = content.split_once(':') {
+            if let Some(default) = rest.strip_prefix('-') {
+                return Self::Default(key, default);
+            }
+            if let Some(words) = rest.strip_prefix('+') {
+                return Self::Optional(key, words);
+            }
+            if let Some(words) = rest.strip_prefix('%') {
+                return Self::Each(key, words);
+            }
+        }
+        Self::Var(content)
+    }
+}
 
-    while let Some(i) = cmd.find(['{', '}']) {
-        let c = cmd.as_bytes()[i];
+/// A piece of a `BUILD_WRAP_CMD` argument
+#[derive(Debug, PartialEq)]
+enum Part<'a> {
+    Literal(String),
+    /// The contents of a `{...}` group, without the outer braces
+    Group(&'a str),
+}
 
-        buf.push_str(&cmd[..i]);
+/// Splits `s` into literal text and `{...}` groups. Outside of a group, `{{` and `}}` are escapes
+/// for `{` and `}`. Within a group, braces must be balanced.
+fn parse_parts(s: &str) -> Result<Vec<Part<'_>>> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = s.char_indices().peekable();
 
-        cmd = &cmd[i + 1..];
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '{' | '}' if chars.next_if(|&(_, next)| next == c).is_some() => literal.push(c),
+            '{' => {
+                let mut depth = 1;
+                let Some((j, _)) = chars.find(|&(_, c)| {
+                    match c {
+                        '{' => depth += 1,
+                        '}' => depth -= 1,
+                        _ => {}
+                    }
+                    depth == 0
+                }) else {
+                    bail!("unbalanced '{{'");
+                };
+                if !literal.is_empty() {
+                    parts.push(Part::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(Part::Group(&s[i + 1..j]));
+            }
+            '}' => bail!("unbalanced '}}'"),
+            _ => literal.push(c),
+        }
+    }
 
-        // smoelius: `i` shouldn't be needed anymore.
-        #[allow(unused_variables, clippy::let_unit_value)]
-        let i = ();
+    if !literal.is_empty() {
+        parts.push(Part::Literal(literal));
+    }
 
-        // smoelius: Escaped `{` or `}`?
-        if !cmd.is_empty() && cmd.as_bytes()[0] == c {
-            buf.push(c as char);
-            cmd = &cmd[1..];
-            continue;
-        }
+    Ok(parts)
+}
 
-        if c == b'{' {
-            let Some(j) = cmd.find('}') else {
-                bail!("unbalanced '{{'");
-            };
-            if j == 0 {
-                let s = build_script_path_as_str
-                    .as_ref()
-                    .ok_or_else(|| anyhow!("build script path is unavailable"))?;
-                buf.push_str(s);
-            } else {
-                let key = &cmd[..j];
+fn is_set(value: Result<&OsString, &env::VarError>) -> bool {
+    value.is_ok_and(|value| !value.is_empty())
+}
+
+/// Expands `s` to a single `OsString`. Where `{VAR:+words}` or `{VAR:%words}` is expanded, `words`
+/// is treated as text, and the expansions for each entry of `VAR` are separated by spaces.
+fn expand_text(
+    s: &str,
+    build_script_path: Option<&Path>,
+    var: &dyn Fn(&str) -> Result<OsString, env::VarError>,
+) -> Result<OsString> {
+    let mut buf = OsString::new();
+
+    for part in parse_parts(s)? {
+        let content = match part {
+            Part::Literal(literal) => {
+                buf.push(&literal);
+                continue;
+            }
+            Part::Group(content) => content,
+        };
+        match Group::parse(content) {
+            Group::Path => {
+                let path =
+                    build_script_path.ok_or_else(|| anyhow!("build script path is unavailable"))?;
+                buf.push(path);
+            }
+            Group::Var(key) => {
                 let value =
                     var(key).with_context(|| format!("environment variable `{key}` not found"))?;
-                buf.push_str(&value);
+                buf.push(&value);
             }
-            cmd = &cmd[j + 1..];
-            continue;
+            Group::Default(key, default) => match var(key) {
+                Ok(value) if !value.is_empty() => buf.push(&value),
+                _ => buf.push(&expand_text(default, build_script_path, var)?),
+            },
+            Group::Optional(key, words) => {
+                if is_set(var(key).as_ref()) {
+                    buf.push(&expand_text(words, build_script_path, var)?);
+                }
+            }
+            Group::Each(key, words) => {
+                let expansions = for_each_entry(key, var, |var| {
+                    expand_text(words, build_script_path, var).map(|s| vec![s])
+                })?;
+                buf.push(expansions.join(OsStr::new(" ")));
+            }
+        }
+    }
+
+    Ok(buf)
+}
+
+/// Expands `arg` to zero or more arguments. If `arg` consists entirely of `{VAR:+words}` or
+/// `{VAR:%words}`, then `words` is split into arguments and each is expanded. Otherwise, `arg` is
+/// expanded to a single argument with [`expand_text`].
+fn expand_arg(
+    arg: &str,
+    build_script_path: Option<&Path>,
+    var: &dyn Fn(&str) -> Result<OsString, env::VarError>,
+) -> Result<Vec<OsString>> {
+    let expand_words = |words: &str, var: &dyn Fn(&str) -> Result<OsString, env::VarError>| {
+        let mut args = Vec::new();
+        for word in split_escaped(words)? {
+            args.extend(expand_arg(&word, build_script_path, var)?);
         }
+        Ok(args)
+    };
 
-        bail!("unbalanced '{}'", c as char);
+    if let [Part::Group(content)] = parse_parts(arg)?.as_slice() {
+        match Group::parse(content) {
+            Group::Optional(key, words) => {
+                if !is_set(var(key).as_ref()) {
+                    return Ok(Vec::new());
+                }
+                return expand_words(words, var);
+            }
+            Group::Each(key, words) => {
+                return for_each_entry(key, var, |var| expand_words(words, var));
+            }
+            _ => {}
+        }
     }
 
-    // smoelius: Push whatever is left.
-    buf.push_str(cmd);
+    expand_text(arg, build_script_path, var).map(|arg| vec![arg])
+}
 
-    Ok(buf)
+/// Calls `f` once for each nonempty entry of the colon-separated list in `key`, with a `var` that
+/// maps `key` to the entry. Returns the concatenation of `f`'s results.
+fn for_each_entry(
+    key: &str,
+    var: &dyn Fn(&str) -> Result<OsString, env::VarError>,
+    mut f: impl FnMut(&dyn Fn(&str) -> Result<OsString, env::VarError>) -> Result<Vec<OsString>>,
+) -> Result<Vec<OsString>> {
+    let value = var(key).unwrap_or_default();
+    let mut results = Vec::new();
+    for entry in value
+        .as_bytes()
+        .split(|&c| c == b':')
+        .filter(|entry| !entry.is_empty())
+    {
+        let var = |k: &str| {
+            if k == key {
+                Ok(OsStr::from_bytes(entry).to_owned())
+            } else {
+                var(k)
+            }
+        };
+        results.extend(f(&var)?);
+    }
+    Ok(results)
 }
 
+// smoelius: `BUILD_WRAP_PROFILE` has no default. The default command passes a profile generated
+// from the default sandbox to `sandbox-exec` with `-p` instead.
 #[cfg(target_os = "macos")]
-static BUILD_WRAP_PROFILE_PATH: LazyLock<String> = LazyLock::new(|| {
+static BUILD_WRAP_PROFILE_PATH: LazyLock<Option<OsString>> = LazyLock::new(|| {
+    let profile = env::var("BUILD_WRAP_PROFILE").ok()?;
     let tempfile = tempfile::NamedTempFile::new().unwrap();
     let (mut file, temp_path) = tempfile.into_parts();
-    let profile = var("BUILD_WRAP_PROFILE").unwrap_or(DEFAULT_PROFILE.to_owned());
     let expanded_profile = expand(&profile, None).unwrap();
     file.write_all(expanded_profile.as_bytes()).unwrap();
     let path = temp_path.keep().unwrap();
-    path.to_utf8().map(ToOwned::to_owned).unwrap()
+    Some(path.into_os_string())
 });
 
-static PRIVATE_TMPDIR: LazyLock<Option<String>> = LazyLock::new(|| {
-    var("TMPDIR").ok().and_then(|value| {
+static PRIVATE_TMPDIR: LazyLock<Option<OsString>> = LazyLock::new(|| {
+    env::var_os("TMPDIR").and_then(|value| {
         let path = canonicalize(value).ok()?;
         if path.starts_with("/private") {
-            path.to_utf8().map(ToOwned::to_owned).ok()
+            Some(path.into_os_string())
         } else {
             None
         }
     })
 });
 
-fn var(key: &str) -> Result<String, env::VarError> {
-    #[cfg(target_os = "macos")]
-    if key == "BUILD_WRAP_PROFILE_PATH" {
-        return Ok(BUILD_WRAP_PROFILE_PATH.clone());
+fn env_var(key: &str) -> Result<OsString, env::VarError> {
+    env::var_os(key).ok_or(env::VarError::NotPresent)
+}
+
+/// Looks up `key` in the environment, taking into account the variables that `build-wrap` treats
+/// as set. Used when no build script path is known, e.g., when expanding config.toml entries.
+///
+/// # Errors
+///
+/// If `key` is not set.
+#[allow(dead_code)]
+pub fn var(key: &str) -> Result<OsString, env::VarError> {
+    var_for(None, key)
+}
+
+/// Looks up `key` in the current environment, taking into account the variables that `build-wrap`
+/// treats as set. `build_script_path` is the path of the renamed original build script, if known.
+fn var_for(build_script_path: Option<&Path>, key: &str) -> Result<OsString, env::VarError> {
+    synthesized_var(key, build_script_path, &env_var, &|| {
+        env::vars_os().collect()
+    })
+    .unwrap_or_else(|| env_var(key))
+}
+
+/// Returns the value of `key` if it is one of the variables that `build-wrap` treats as set, and
+/// `None` otherwise. `build_script_path` is the path of the renamed original build script, if
+/// known. `var` looks up variables in the build script's environment, and `vars` lists them.
+pub fn synthesized_var(
+    key: &str,
+    build_script_path: Option<&Path>,
+    var: &dyn Fn(&str) -> Result<OsString, env::VarError>,
+    vars: &dyn Fn() -> Vec<(OsString, OsString)>,
+) -> Option<Result<OsString, env::VarError>> {
+    let value = match key {
+        #[cfg(target_os = "macos")]
+        "BUILD_WRAP_PROFILE_PATH" => BUILD_WRAP_PROFILE_PATH
+            .clone()
+            .ok_or(env::VarError::NotPresent),
+        "PRIVATE_TMPDIR" => PRIVATE_TMPDIR.clone().ok_or(env::VarError::NotPresent),
+        "CARGO_TARGET_DIR_RESOLVED" => target_dir_resolved(var).map(PathBuf::into_os_string),
+        "DEP_DIRS" => Ok(dep_dirs(var, vars)),
+        "CARGO_HOME_RESOLVED" => match var("CARGO_HOME") {
+            Ok(cargo_home) if !cargo_home.is_empty() => {
+                Ok(resolve(Path::new(&cargo_home)).into_os_string())
+            }
+            _ => var("HOME").map(|home| resolve(&Path::new(&home).join(".cargo")).into_os_string()),
+        },
+        "MANIFEST_DIR" => var("CARGO_MANIFEST_DIR")
+            .map(|manifest_dir| resolve(Path::new(&manifest_dir)).into_os_string()),
+        "RUSTC_SYSROOT" => rustc_sysroot(var),
+        "BUILD_SCRIPT_DIR" => build_script_path
+            .and_then(Path::parent)
+            .ok_or(env::VarError::NotPresent)
+            .map(path_value),
+        "SIBLING" => build_script_path
+            .ok_or(env::VarError::NotPresent)
+            .map(path_value),
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// Returns the target directory containing `OUT_DIR`, with symbolic links resolved.
+fn target_dir_resolved(
+    var: &dyn Fn(&str) -> Result<OsString, env::VarError>,
+) -> Result<PathBuf, env::VarError> {
+    let out_dir = var("OUT_DIR")?;
+    // smoelius: Cargo writes a `CACHEDIR.TAG` file to the root of the target directory.
+    let target_dir = Path::new(&out_dir)
+        .ancestors()
+        .find(|dir| dir.join("CACHEDIR.TAG").is_file())
+        .ok_or(env::VarError::NotPresent)?;
+    Ok(resolve(target_dir))
+}
+
+/// Returns a colon-separated list of the directories that `DEP_*` variables name within
+/// dependencies' `OUT_DIR`s, e.g., an include directory that a dependency exposed through `links`
+/// metadata. Values that are themselves colon-separated lists are split.
+///
+/// Dependencies control `DEP_*` values. So a path is accepted only if it lies inside an `OUT_DIR`
+/// in the same `build` directory as the package's own `OUT_DIR`. In particular, the target
+/// directory, its ancestors, and `build` directories are rejected, as they would re-expose what
+/// `hide-target-dir` hides.
+fn dep_dirs(
+    var: &dyn Fn(&str) -> Result<OsString, env::VarError>,
+    vars: &dyn Fn() -> Vec<(OsString, OsString)>,
+) -> OsString {
+    let Ok(out_dir) = var("OUT_DIR") else {
+        return OsString::new();
+    };
+    let out_dir = resolve(Path::new(&out_dir));
+    let Some(build_dir) = out_dir.parent().and_then(Path::parent) else {
+        return OsString::new();
+    };
+    let mut dirs = Vec::new();
+    for (key, value) in vars() {
+        if !key.as_bytes().starts_with(b"DEP_") {
+            continue;
+        }
+        for path in env::split_paths(&value) {
+            if !path.is_absolute() || !path.is_dir() {
+                continue;
+            }
+            let path = resolve(&path);
+            if within_out_dir(&path, build_dir) && !dirs.contains(&path) {
+                dirs.push(path);
+            }
+        }
     }
+    dirs.sort();
+    OsString::from_vec(
+        dirs.iter()
+            .map(|dir| dir.as_os_str().as_bytes())
+            .collect::<Vec<_>>()
+            .join(&b':'),
+    )
+}
+
+/// Returns whether `path` is at or below `<build_dir>/<package>-<hash>/out`.
+fn within_out_dir(path: &Path, build_dir: &Path) -> bool {
+    let Ok(relative) = path.strip_prefix(build_dir) else {
+        return false;
+    };
+    let mut components = relative.components();
+    matches!(components.next(), Some(Component::Normal(_)))
+        && components.next() == Some(Component::Normal(OsStr::new("out")))
+}
 
-    if key == "PRIVATE_TMPDIR" {
-        return PRIVATE_TMPDIR.clone().ok_or(env::VarError::NotPresent);
+/// Returns the output of `rustc --print sysroot`, where `rustc` is the compiler that Cargo passes
+/// to build scripts in `RUSTC`.
+fn rustc_sysroot(
+    var: &dyn Fn(&str) -> Result<OsString, env::VarError>,
+) -> Result<OsString, env::VarError> {
+    let rustc = var("RUSTC").unwrap_or_else(|_| OsString::from("rustc"));
+    let output = Command::new(rustc)
+        .args(["--print", "sysroot"])
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|_| env::VarError::NotPresent)?;
+    if !output.status.success() {
+        return Err(env::VarError::NotPresent);
     }
+    let mut stdout = output.stdout;
+    stdout.truncate(stdout.trim_ascii_end().len());
+    Ok(OsString::from_vec(stdout))
+}
 
-    env::var(key)
+fn path_value(path: &Path) -> OsString {
+    path.as_os_str().to_owned()
 }
 
-fn enabled(name: &str) -> bool {
+/// Returns whether environment variable `name` is set to a value other than `0`.
+#[must_use]
+pub fn enabled(name: &str) -> bool {
     env::var(name).is_ok_and(|value| value != "0")
 }
 
@@ -282,15 +2306,25 @@ pub use test::assert_readme_contains_code_block;
 
 #[cfg(test)]
 mod test {
+    use super::{Action, AuditAction, AuditRule, Policy};
     use anyhow::Result;
-    use std::{env::set_var, fs::read_to_string, path::Path};
+    use std::{
+        env::VarError,
+        ffi::{OsStr, OsString},
+        fs::{create_dir_all, read_to_string, write},
+        path::{Path, PathBuf},
+        process::Command,
+    };
 
-    #[test]
-    fn expand_cmd() {
-        unsafe {
-            set_var("KEY", "VALUE");
+    fn test_var(key: &str) -> Result<OsString, VarError> {
+        match key {
+            "KEY" => Ok(OsString::from("VALUE")),
+            _ => Err(VarError::NotPresent),
         }
+    }
 
+    #[test]
+    fn expand_cmd() {
         let successes = [
             ("left path right", "{}"),
             ("left VALUE right", "{KEY}"),
@@ -313,16 +2347,437 @@ mod test {
         }
     }
 
-    fn surround_and_expand(s: &str) -> Result<String> {
+    #[test]
+    fn split_escaped_matches_shell_words() {
+        const ALPHABET: &[char] = &[' ', '\t', '\n', 'a', '\'', '"', '\\', '#', '$'];
+        const MAX_LEN: u32 = 6;
+
+        for len in 0..=MAX_LEN {
+            for mut n in 0..ALPHABET.len().pow(len) {
+                let mut s = String::new();
+                for _ in 0..len {
+                    s.push(ALPHABET[n % ALPHABET.len()]);
+                    n /= ALPHABET.len();
+                }
+                assert_eq!(
+                    shell_words::split(&s).ok(),
+                    super::split_escaped(&s).ok(),
+                    "{s:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn split_and_expand_quoted() {
+        let cases = [
+            (
+                r#"--setenv FOO "a b" {}"#,
+                vec!["--setenv", "FOO", "a b", "path"],
+            ),
+            (r#""{KEY} {}" '{KEY} {}'"#, vec!["VALUE path", "{KEY} {}"]),
+            (r"\{KEY\} {{KEY}}", vec!["{KEY}", "{KEY}"]),
+            ("bwrap  # comment {UNKNOWN}\n  {}", vec!["bwrap", "path"]),
+        ];
+
+        for (cmd, expected) in cases {
+            assert_eq!(
+                expected,
+                super::split_and_expand_with(cmd, Path::new("path"), test_var).unwrap(),
+                "{cmd:?}"
+            );
+        }
+
+        assert_eq!(
+            "missing closing quote",
+            super::split_and_expand_with("'{}", Path::new("path"), test_var)
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn expand_groups() {
+        let var = |key: &str| match key {
+            "SET" => Ok(OsString::from("/set")),
+            "EMPTY" => Ok(OsString::new()),
+            "LIST" => Ok(OsString::from("/a::/b")),
+            _ => Err(std::env::VarError::NotPresent),
+        };
+
+        let cases: &[(&str, &[&str])] = &[
+            ("{UNSET:-/default} {SET:-/default}", &["/default", "/set"]),
+            ("{EMPTY:-{}}", &["path"]),
+            ("{UNSET:+--bind {UNSET} {UNSET}} {}", &["path"]),
+            (
+                "{SET:+--bind {SET} {SET}} {}",
+                &["--bind", "/set", "/set", "path"],
+            ),
+            ("{EMPTY:+--bind {EMPTY} {EMPTY}} {}", &["path"]),
+            (
+                "{LIST:%--ro-bind {LIST} {LIST}} {}",
+                &["--ro-bind", "/a", "/a", "--ro-bind", "/b", "/b", "path"],
+            ),
+            ("{UNSET:%--ro-bind {UNSET} {UNSET}} {}", &["path"]),
+            (
+                "{LIST:%'{LIST}' \"{LIST}\"}",
+                &["{LIST}", "/a", "{LIST}", "/b"],
+            ),
+            // smoelius: A group that does not make up an entire argument is expanded as text.
+            (
+                "--opt={SET:+a b} -L{LIST:%({LIST})}",
+                &["--opt=a b", "-L(/a) (/b)"],
+            ),
+        ];
+
+        for &(cmd, expected) in cases {
+            assert_eq!(
+                expected,
+                super::split_and_expand_with(cmd, Path::new("path"), var).unwrap(),
+                "{cmd:?}"
+            );
+        }
+
+        for cmd in ["{SET:+--bind {SET}", "{SET:+--bind}}"] {
+            let error = super::split_and_expand_with(cmd, Path::new("path"), var).unwrap_err();
+            assert!(
+                error.to_string().starts_with("unbalanced"),
+                "{cmd:?}: {error}"
+            );
+        }
+    }
+
+    #[allow(clippy::disallowed_methods)]
+    #[test]
+    fn synthesized_vars() {
+        let dir = tempfile::tempdir().unwrap();
+        let target_dir = dir.path().canonicalize().unwrap().join("target");
+        let build_dir = target_dir.join("debug/build/foo-0123456789abcdef");
+        let out_dir = build_dir.join("out");
+        create_dir_all(&out_dir).unwrap();
+        write(target_dir.join("CACHEDIR.TAG"), "").unwrap();
+
+        let sibling_path = build_dir.join("build_script_build-0123456789abcdef");
+        let var = |key: &str| match key {
+            "OUT_DIR" => Ok(out_dir.clone().into_os_string()),
+            "CARGO_MANIFEST_DIR" => Ok(OsString::from("/src/foo/./")),
+            "HOME" => Ok(OsString::from("/home/user")),
+            _ => Err(std::env::VarError::NotPresent),
+        };
+        let include_dir = target_dir.join("debug/build/bar-0123456789abcdef/out/include");
+        create_dir_all(&include_dir).unwrap();
+        let vars = || {
+            vec![
+                (
+                    OsString::from("DEP_BAR_INCLUDE"),
+                    include_dir.clone().into_os_string(),
+                ),
+                (
+                    OsString::from("DEP_BAR_ROOT"),
+                    OsString::from("/usr:relative"),
+                ),
+                (OsString::from("OTHER"), out_dir.clone().into_os_string()),
+                // smoelius: A dependency must not be able to re-expose the target directory.
+                (
+                    OsString::from("DEP_BAR_TARGET"),
+                    target_dir.clone().into_os_string(),
+                ),
+                (
+                    OsString::from("DEP_BAR_BUILD"),
+                    std::env::join_paths([
+                        target_dir.join("debug"),
+                        target_dir.join("debug/build"),
+                        target_dir.join("debug/build/bar-0123456789abcdef"),
+                        include_dir.join("../../.."),
+                    ])
+                    .unwrap(),
+                ),
+            ]
+        };
+        let synthesized =
+            |key| super::synthesized_var(key, Some(&sibling_path), &var, &vars).unwrap();
+
+        assert_eq!(
+            Ok(target_dir.as_os_str()),
+            synthesized("CARGO_TARGET_DIR_RESOLVED").as_deref()
+        );
+        assert_eq!(
+            Ok(OsStr::new("/home/user/.cargo")),
+            synthesized("CARGO_HOME_RESOLVED").as_deref()
+        );
+        assert_eq!(
+            Ok(OsStr::new("/src/foo")),
+            synthesized("MANIFEST_DIR").as_deref()
+        );
+        assert_eq!(
+            Ok(build_dir.as_os_str()),
+            synthesized("BUILD_SCRIPT_DIR").as_deref()
+        );
+        assert_eq!(
+            Ok(sibling_path.as_os_str()),
+            synthesized("SIBLING").as_deref()
+        );
+        assert_eq!(
+            Ok(include_dir.as_os_str()),
+            synthesized("DEP_DIRS").as_deref()
+        );
+        assert!(synthesized("RUSTC_SYSROOT").is_ok_and(|sysroot| Path::new(&sysroot).is_dir()));
+
+        assert!(
+            super::synthesized_var("SIBLING", None, &var, &vars)
+                .unwrap()
+                .is_err()
+        );
+        assert!(super::synthesized_var("OUT_DIR", None, &var, &vars).is_none());
+    }
+
+    fn surround_and_expand(s: &str) -> Result<OsString> {
         let cmd = String::from("left ") + s + " right";
-        super::expand(&cmd, Some(Path::new("path")))
+        super::expand_with(&cmd, Some(Path::new("path")), test_var)
+    }
+
+    #[test]
+    fn sha256_hex() {
+        assert_eq!(
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            super::sha256_hex(b"")
+        );
+    }
+
+    #[test]
+    fn exec_forwarding_large_output() {
+        // smoelius: Each stream is larger than a typical pipe buffer, so that forwarding would
+        // deadlock if the streams were not read concurrently.
+        let mut command = Command::new("sh");
+        command.args([
+            "-c",
+            "i=0; while [ $i -lt 20000 ]; do echo out $i; echo err $i >&2; i=$((i+1)); done; echo \
+             -n partial",
+        ]);
+        let output = super::exec_forwarding_output(command, true).unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert_eq!(20001, stdout.lines().count());
+        assert_eq!(20000, stderr.lines().count());
+        assert!(stdout.starts_with("out 0\n"));
+        assert!(stdout.ends_with("out 19999\npartial"));
+        assert!(stderr.ends_with("err 19999\n"));
+    }
+
+    fn diagnose_all(stderr: &[u8]) -> Option<super::Denial> {
+        let mut diagnosis = super::Diagnosis::default();
+        for line in stderr.split_inclusive(|&c| c == b'\n') {
+            diagnosis.observe(line);
+        }
+        diagnosis.denial()
+    }
+
+    #[test]
+    fn diagnose() {
+        let cases = [
+            (
+                r#"Os { code: 30, kind: ReadOnlyFilesystem, message: "Read-only file system" }"#,
+                Some("writing outside of `OUT_DIR` and the temporary directory"),
+            ),
+            (
+                "ping: socket: Operation not permitted",
+                Some("accessing the network"),
+            ),
+            (
+                "ping: sendto: Operation not permitted",
+                Some("accessing the network"),
+            ),
+            (
+                "curl: (6) Could not resolve host: Temporary failure in name resolution",
+                Some("accessing the network"),
+            ),
+            (
+                r#"Os { code: 13, kind: PermissionDenied, message: "Permission denied" }"#,
+                Some("accessing a file that the sandbox does not permit"),
+            ),
+            (
+                "Sandbox: ping(4242) deny(1) network-outbound /private/var/run/mDNSResponder",
+                Some("accessing the network"),
+            ),
+            (
+                "Sandbox: touch(4242) deny(1) file-write-create /Users/user/file",
+                Some("writing outside of `OUT_DIR` and the temporary directory"),
+            ),
+            (
+                "libc::ioctl: Operation not permitted",
+                Some("performing an operation that the sandbox does not permit"),
+            ),
+            ("error: linking with `cc` failed: exit status: 1", None),
+        ];
+        for (stderr, expected) in cases {
+            let stderr = format!("warning: something\n  {stderr}\n");
+            assert_eq!(
+                expected,
+                diagnose_all(stderr.as_bytes())
+                    .as_ref()
+                    .map(|denial| denial.operation.as_str()),
+                "{stderr:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn diagnose_prefers_specific_patterns() {
+        let stderr = "Permission denied\nsocket: Operation not permitted\n";
+        let denial = diagnose_all(stderr.as_bytes()).unwrap();
+        assert_eq!("accessing the network", denial.operation);
+        assert_eq!("socket: Operation not permitted", denial.evidence);
+    }
+
+    #[test]
+    fn denial_remedies() {
+        let manifest_dir = env!("CARGO_MANIFEST_DIR");
+        let cases = [
+            (
+                String::from("ping: socket: Operation not permitted"),
+                "[sandbox.packages.\"build-wrap\"]\nnetwork = true",
+            ),
+            (
+                String::from("Sandbox: touch(4242) deny(1) file-write-create /Users/user/file"),
+                "[sandbox.packages.\"build-wrap\"]\nwritable = [\"/Users/user\"]",
+            ),
+            (
+                format!("Sandbox: cc(4242) deny(1) file-write-create {manifest_dir}/src/x.o"),
+                "[profiles.native-build]\npackages = [\"build-wrap\"]",
+            ),
+            (
+                String::from(
+                    r#"Os { code: 30, kind: ReadOnlyFilesystem, message: "Read-only file system" }"#,
+                ),
+                "[sandbox.packages.\"build-wrap\"]\nwritable = [\"/path/to/directory\"]",
+            ),
+            (
+                String::from("libc::ioctl: Operation not permitted"),
+                "[allow]\npackages = [\"build-wrap\"]",
+            ),
+        ];
+        for (stderr, expected) in cases {
+            let denial = diagnose_all(stderr.as_bytes()).unwrap();
+            let message = denial.to_string();
+            assert!(message.ends_with(expected), "{message}");
+        }
+    }
+
+    #[test]
+    fn parse_answer() {
+        use super::Answer;
+        let cases = [
+            ("\n", Some(Answer::Deny)),
+            ("d\n", Some(Answer::Deny)),
+            ("O\n", Some(Answer::AllowOnce)),
+            (
+                " v \n",
+                Some(Answer::AllowAlways(String::from("foo@1.2.3"))),
+            ),
+            ("package\n", Some(Answer::AllowAlways(String::from("foo")))),
+            ("x\n", None),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(
+                expected,
+                super::parse_answer(line, "foo", "1.2.3"),
+                "{line:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn policy_roundtrip() {
+        let policy = Policy {
+            rustc_link_arg: Action::Deny,
+            rustc_link_search: Action::Restrict,
+            rustc_env: Action::Allow,
+            rustc_cfg: Action::Restrict,
+            link_search_directories: vec![String::from("/opt/lib")],
+            allowed_cfgs: vec![String::from("foo"), String::from(r#"bar="baz""#)],
+            audit: [
+                (AuditRule::Executables, AuditAction::Deny),
+                (AuditRule::AbsoluteIncludes, AuditAction::Allow),
+            ]
+            .into_iter()
+            .collect(),
+            max_file_size: Some(1024),
+        };
+        assert_eq!(policy, Policy::parse(&policy.to_env_string()).unwrap());
+        assert_eq!(Policy::default(), Policy::parse("").unwrap());
+        assert!(Policy::parse("rustc-link-arg").is_err());
+        assert!(Policy::parse("rustc-link-arg=forbid").is_err());
+        assert!(Policy::parse("unknown=allow").is_err());
+        assert!(Policy::parse("audit-symlinks=restrict").is_err());
+        assert!(Policy::parse("audit-unknown=deny").is_err());
     }
 
     #[test]
-    fn readme_contains_default_profile() {
-        assert_readme_contains_code_block(super::DEFAULT_PROFILE.lines(), None);
+    fn filter_directives() {
+        let policy = Policy {
+            rustc_link_arg: Action::Deny,
+            rustc_link_search: Action::Restrict,
+            rustc_env: Action::Allow,
+            rustc_cfg: Action::Restrict,
+            link_search_directories: vec![String::from("/opt/lib")],
+            allowed_cfgs: vec![String::from("foo")],
+            ..Policy::default()
+        };
+        let out_dir = Path::new("/target/debug/build/a-0123456789abcdef/out");
+
+        let accepted = [
+            "cargo:rerun-if-changed=build.rs",
+            "cargo:rustc-link-lib=z",
+            "cargo:rustc-env=KEY=VALUE",
+            "cargo:rustc-cfg=foo",
+            r#"cargo::rustc-cfg=foo="bar""#,
+            "cargo:rustc-link-search=/target/debug/build/a-0123456789abcdef/out/lib",
+            "cargo:rustc-link-search=native=/usr/lib/x86_64-linux-gnu",
+            "cargo::rustc-link-search=/opt/lib",
+            "cargo:rustc-flags=-l z -L /usr/local/lib",
+            "not a directive",
+        ];
+
+        let rejected = [
+            "cargo:rustc-link-arg=-Wl,-rpath,/tmp",
+            "cargo::rustc-link-arg-bins=-nostartfiles",
+            "cargo:rustc-cfg=bar",
+            "cargo:rustc-link-search=/home/attacker/lib",
+            "cargo:rustc-link-search=native=/target/debug/build/a-0123456789abcdef/out/../../..",
+            "cargo:rustc-flags=-l z -L/home/attacker/lib",
+        ];
+
+        for line in accepted {
+            let stdout = format!("{line}\n");
+            assert_eq!(
+                stdout.as_bytes(),
+                super::filter_directives(stdout.as_bytes(), &policy, Some(out_dir))
+            );
+        }
+
+        for line in rejected {
+            let stdout = format!("{line}\n");
+            let filtered = super::filter_directives(stdout.as_bytes(), &policy, Some(out_dir));
+            let filtered = String::from_utf8(filtered).unwrap();
+            assert!(
+                filtered.starts_with(&format!("cargo:warning=build-wrap: rejected `{line}`: ")),
+                "{line:?} was not rejected: {filtered:?}"
+            );
+            assert_eq!(1, filtered.lines().count());
+        }
+
+        // smoelius: Cargo ignores lines that are not valid UTF-8, so they are forwarded unchanged.
+        let stdout = b"cargo:rustc-link-arg=\xff\n";
+        assert_eq!(
+            stdout.as_slice(),
+            super::filter_directives(stdout, &policy, Some(out_dir))
+        );
     }
 
+    // smoelius: The present module is imported by tests/integration/util.rs, which does not use
+    // the next function.
+    #[allow(dead_code)]
+    /// Asserts that README.md contains a code block in `language` consisting of `lines`.
     pub fn assert_readme_contains_code_block(
         lines: impl Iterator<Item = impl AsRef<str>>,
         language: Option<&str>,
@@ -342,4 +2797,278 @@ mod test {
             .collect::<Vec<_>>();
         assert!(readme_lines.windows(size).any(|w| w == delimited_lines));
     }
+
+    // smoelius: The filter is installed in a child process, between `fork` and `exec`, and the
+    // child checks that denied syscalls fail with `EPERM` and that others still work.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn seccomp() {
+        use std::{
+            io::Read,
+            os::{fd::AsRawFd, unix::process::CommandExt},
+        };
+
+        let Some(program) = super::seccomp::filter(["personality"]).unwrap() else {
+            return;
+        };
+        let program_len = program.len();
+
+        let mut command = Command::new("true");
+        unsafe {
+            command.pre_exec(move || {
+                super::seccomp::install(&program)?;
+                let byte = 0u8;
+                for result in [
+                    libc::syscall(libc::SYS_keyctl, 0),
+                    libc::syscall(libc::SYS_personality, 0xffff_ffff_u32),
+                    libc::c_long::from(libc::ioctl(
+                        libc::STDIN_FILENO,
+                        libc::TIOCSTI,
+                        &raw const byte,
+                    )),
+                ] {
+                    let error = std::io::Error::last_os_error();
+                    if result != -1 || error.raw_os_error() != Some(libc::EPERM) {
+                        return Err(std::io::Error::other("syscall was not denied"));
+                    }
+                }
+                if libc::getpid() <= 0 {
+                    return Err(std::io::Error::other("`getpid` failed"));
+                }
+                Ok(())
+            });
+        }
+        assert!(command.status().unwrap().success());
+
+        assert_eq!(
+            "unknown syscall `fork`",
+            super::seccomp::filter(["fork"]).unwrap_err().to_string()
+        );
+
+        // smoelius: `bwrap` is passed the filter rather than having it installed.
+        let args = ["/usr/bin/bwrap", "--ro-bind", "/", "/", "true"].map(OsString::from);
+        let (command, file) = super::seccomp::command(&args, ["personality"]).unwrap();
+        let mut file = file.unwrap();
+        let fd = file.as_raw_fd().to_string();
+        assert_eq!(Path::new("/usr/bin/bwrap"), command.get_program());
+        assert_eq!(
+            ["--seccomp", &fd, "--ro-bind", "/", "/", "true"].as_slice(),
+            command.get_args().collect::<Vec<_>>()
+        );
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).unwrap();
+        assert_eq!(program_len * size_of::<libc::sock_filter>(), bytes.len());
+    }
+
+    #[test]
+    fn bwrap_writable() {
+        let args = [
+            "/usr/bin/bwrap",
+            "--ro-bind",
+            "/",
+            "/",
+            "--setenv",
+            "--bind",
+            "x",
+            "--dev-bind",
+            "/dev",
+            "/dev",
+            "--bind",
+            "/out",
+            "/out",
+            "--unshare-net",
+            "/build_script_build",
+            "--bind",
+            "/a",
+            "/a",
+        ]
+        .map(OsString::from);
+        assert_eq!(
+            Some(vec![PathBuf::from("/dev"), PathBuf::from("/out")]),
+            super::bwrap_writable(&args)
+        );
+        assert_eq!(
+            None,
+            super::bwrap_writable(&["sandbox-exec", "--bind", "/", "/"].map(OsString::from))
+        );
+    }
+
+    #[test]
+    fn protected_dirs() {
+        let manifest_dir = super::resolve(Path::new(env!("CARGO_MANIFEST_DIR")));
+        let manifest_dir_str = manifest_dir.to_str().unwrap();
+        for (args, expected) in [
+            (
+                &[
+                    "bwrap",
+                    "--ro-bind",
+                    "/",
+                    "/",
+                    "--bind",
+                    "/out",
+                    "/out",
+                    "a",
+                ][..],
+                false,
+            ),
+            (&["bwrap", "--bind", "/", "/", "a"], true),
+            (
+                &[
+                    "bwrap",
+                    "--bind",
+                    "/",
+                    "/",
+                    "--bind",
+                    manifest_dir_str,
+                    manifest_dir_str,
+                    "a",
+                ],
+                false,
+            ),
+            (&["sandbox-exec", "a"], true),
+        ] {
+            let args = args.iter().map(OsString::from).collect::<Vec<_>>();
+            assert_eq!(
+                expected,
+                super::protected_dirs(&args).contains(&manifest_dir),
+                "{args:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn extracted_packages() {
+        #[allow(clippy::disallowed_methods)]
+        let dir = tempfile::tempdir().unwrap();
+        let extracted = dir.path().join("index.crates.io-0/a-0.1.0");
+        let extracting = dir.path().join("index.crates.io-0/b-0.1.0");
+        create_dir_all(&extracted).unwrap();
+        create_dir_all(&extracting).unwrap();
+        write(extracted.join(".cargo-ok"), "").unwrap();
+        assert_eq!(vec![extracted], super::extracted_packages(dir.path()));
+        assert!(super::extracted_packages(&dir.path().join("nonexistent")).is_empty());
+    }
+
+    #[test]
+    fn integrity_scan() {
+        #[allow(clippy::disallowed_methods)]
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let target = dir.path().join("target");
+        create_dir_all(&src).unwrap();
+        create_dir_all(&target).unwrap();
+        write(src.join("lib.rs"), "").unwrap();
+        write(target.join("CACHEDIR.TAG"), "").unwrap();
+
+        // smoelius: File timestamps come from a coarse clock. Files written during the same tick
+        // as the scan's marker file are considered modified.
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        let scan = super::IntegrityScan::new(vec![dir.path().to_path_buf()]).unwrap();
+        assert!(scan.modified().unwrap().is_empty());
+
+        // smoelius: Writes to target directories are not modifications.
+        write(target.join("output"), "").unwrap();
+        assert!(scan.modified().unwrap().is_empty());
+
+        // smoelius: Neither is rewriting a file with the same contents.
+        write(src.join("lib.rs"), "").unwrap();
+        assert!(scan.modified().unwrap().is_empty());
+
+        write(src.join("lib.rs"), "fn foo() {}").unwrap();
+        write(src.join("main.rs"), "").unwrap();
+        assert_eq!(
+            vec![src.clone(), src.join("lib.rs"), src.join("main.rs")],
+            scan.modified().unwrap()
+        );
+    }
+
+    #[test]
+    fn absolute_includes() {
+        let contents = r##"
+include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+include!("/etc/foo.rs");
+const KEY: &[u8] = include_bytes! ( r#"/home/user/.ssh/id_rsa"# );
+const NAME: &str = include_str!["relative.txt"];
+const PATH: &str = include_str!{"/tmp/with \"quotes\""};
+"##;
+        assert_eq!(
+            vec![
+                "/etc/foo.rs",
+                "/home/user/.ssh/id_rsa",
+                r#"/tmp/with "quotes""#
+            ],
+            super::absolute_includes(contents)
+        );
+    }
+
+    #[test]
+    fn audit_out_dir() {
+        use std::os::unix::fs::{PermissionsExt, symlink};
+
+        #[allow(clippy::disallowed_methods)]
+        let dir = tempfile::tempdir().unwrap();
+        let out_dir = dir.path().join("out");
+        create_dir_all(out_dir.join("include")).unwrap();
+        write(out_dir.join("include/foo.h"), "").unwrap();
+        symlink("include/foo.h", out_dir.join("foo.h")).unwrap();
+        symlink("/etc/passwd", out_dir.join("passwd")).unwrap();
+        symlink("../out/../..", out_dir.join("up")).unwrap();
+        write(out_dir.join("tool"), "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(out_dir.join("tool"), PermissionsExt::from_mode(0o755)).unwrap();
+        write(
+            out_dir.join("lib.rs"),
+            format!(
+                "include!({:?});\ninclude_bytes!(\"/etc/passwd\");\n",
+                out_dir.join("include/foo.h").display()
+            ),
+        )
+        .unwrap();
+
+        let (inventory, findings) = super::audit_out_dir(&out_dir, &Policy::default()).unwrap();
+        assert_eq!(
+            "dir\t",
+            &inventory.lines().next().unwrap()[..4],
+            "{inventory}"
+        );
+        assert!(
+            inventory.contains("symlink\t11\tpasswd\t/etc/passwd\n"),
+            "{inventory}"
+        );
+        assert!(inventory.contains("executable\t10\ttool\n"), "{inventory}");
+        assert_eq!(
+            vec![
+                (AuditRule::AbsoluteIncludes, out_dir.join("lib.rs")),
+                (AuditRule::Symlinks, out_dir.join("passwd")),
+                (AuditRule::Symlinks, out_dir.join("up")),
+            ],
+            findings
+                .into_iter()
+                .map(|finding| (finding.rule, finding.path))
+                .collect::<Vec<_>>()
+        );
+
+        let policy = Policy {
+            audit: [
+                (AuditRule::Symlinks, AuditAction::Allow),
+                (AuditRule::Executables, AuditAction::Warn),
+                (AuditRule::AbsoluteIncludes, AuditAction::Allow),
+            ]
+            .into_iter()
+            .collect(),
+            max_file_size: Some(10),
+            ..Policy::default()
+        };
+        let (_, findings) = super::audit_out_dir(&out_dir, &policy).unwrap();
+        assert_eq!(
+            vec![
+                (AuditRule::LargeFiles, out_dir.join("lib.rs")),
+                (AuditRule::Executables, out_dir.join("tool")),
+            ],
+            findings
+                .into_iter()
+                .map(|finding| (finding.rule, finding.path))
+                .collect::<Vec<_>>()
+        );
+    }
 }
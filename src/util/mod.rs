@@ -1,15 +1,33 @@
+//! Utilities shared by `build-wrap` and the wrapper build scripts it generates. Most live in
+//! util/common.rs, which is compiled into each wrapper build script as well.
+
 use anyhow::{Result, ensure};
 use std::{env, ffi::OsString, fs::canonicalize, path::PathBuf, process::Command};
 
 mod common;
-pub use common::{ToUtf8, exec_forwarding_output};
+pub use common::exec_forwarding_output;
+
+// smoelius: The present module is imported by tests/integration/util.rs, which does not use the
+// following. The `allow` prevents "unused import" warnings in that module.
+#[allow(unused_imports)]
+pub use common::{
+    Action, AuditAction, AuditRule, DEFAULT_DENIED_SYSCALLS, INVOKED_FILE_NAME, Policy, enabled,
+    expand, expand_with, sha256_hex, split_and_expand, split_and_expand_with, synthesized_var, var,
+    verify_sibling,
+};
+
+#[cfg(target_os = "linux")]
+#[allow(unused_imports)]
+pub use common::seccomp;
 
 #[cfg(test)]
 #[allow(unused_imports)]
 pub use common::assert_readme_contains_code_block;
 
+/// The linker `build-wrap` invokes if `BUILD_WRAP_LD` is not set
 pub const DEFAULT_LD: &str = "cc";
 
+/// Returns a `cargo build -vv` command. The `CARGO` environment variable is respected, if set.
 #[must_use]
 pub fn cargo_build() -> Command {
     // smoelius: Respect `CARGO` environment variable, if set.
@@ -34,6 +52,7 @@ pub fn cargo_build() -> Command {
 // smoelius: The present module is imported by tests/integration/util.rs. The next `allow` prevents
 // a "function `which` is never used" warning in that module.
 #[allow(dead_code)]
+/// Returns the canonical path of the program that `which filename` finds.
 pub fn which(filename: &str) -> Result<PathBuf> {
     let mut command = Command::new("which");
     let output = command.arg(filename).output()?;
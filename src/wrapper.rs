@@ -1,24 +1,50 @@
-use crate::util::ToUtf8;
+//! Generation of wrapper build scripts, and management of the renamed original build scripts
+//! ("siblings") that they execute
+
+use crate::{sidecar::Sidecar, util::sha256_hex};
 use anyhow::{Result, anyhow};
 use std::{
-    fs::{create_dir, rename, write},
-    path::Path,
+    ascii,
+    ffi::OsStr,
+    fs::{create_dir, read, read_dir, remove_file, rename, write},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
 };
-use tempfile::{NamedTempFile, TempDir, tempdir};
+use tempfile::{Builder, TempDir, tempdir};
+
+/// Prefix of the name of each renamed original build script. A distinctive prefix allows stale
+/// siblings to be identified without confusing them with other temporary files.
+const SIBLING_PREFIX: &str = ".build-wrap-sibling-";
+
+/// Prefix of the name of each renamed original build script created by earlier versions of
+/// `build-wrap`, i.e., `tempfile`'s default prefix. Such a name is followed by six alphanumeric
+/// characters.
+const LEGACY_SIBLING_PREFIX: &str = ".tmp";
+
+/// A renamed original build script
+pub struct Sibling {
+    /// Path of the renamed original build script
+    pub path: PathBuf,
 
+    /// SHA-256 hash of the file at `path`
+    pub sha256: String,
+}
+
+/// Renames the build script at `build_script_path` to a fresh sibling, and creates a package that
+/// builds a wrapper around the sibling. Returns the package's directory and the sibling.
 #[allow(clippy::disallowed_methods)]
-pub fn package(build_script_path: &Path) -> Result<TempDir> {
+pub fn package(build_script_path: &Path) -> Result<(TempDir, Sibling)> {
     let parent = build_script_path
         .parent()
         .ok_or_else(|| anyhow!("failed to get `build_script_path` parent"))?;
 
-    let temp_file = NamedTempFile::new_in(parent)?;
+    let temp_file = Builder::new().prefix(SIBLING_PREFIX).tempfile_in(parent)?;
 
     let (_file, sibling_path) = temp_file.keep()?;
 
     rename(build_script_path, &sibling_path)?;
 
-    let sibling_path_as_str = sibling_path.to_utf8()?;
+    let sibling_sha256 = sha256_hex(&read(&sibling_path)?);
 
     let tempdir = tempdir()?;
 
@@ -26,10 +52,65 @@ pub fn package(build_script_path: &Path) -> Result<TempDir> {
     create_dir(tempdir.path().join("src"))?;
     write(
         tempdir.path().join("src/main.rs"),
-        main_rs(sibling_path_as_str),
+        main_rs(&sibling_path, &sibling_sha256),
     )?;
 
-    Ok(tempdir)
+    Ok((
+        tempdir,
+        Sibling {
+            path: sibling_path,
+            sha256: sibling_sha256,
+        },
+    ))
+}
+
+/// Removes the renamed original build scripts in `dir` that no wrapper refers to, and returns their
+/// paths. If `dry_run` is true, the paths are returned but nothing is removed.
+///
+/// Only files whose names look like siblings' (see [`is_sibling_name`]) are considered, and only if
+/// `dir` contains a sidecar, in which case the sibling the sidecar names is the only live one. A
+/// directory without a sidecar is left alone, since `build-wrap` cannot tell which of its files are
+/// still in use.
+pub fn remove_stale_siblings(dir: &Path, dry_run: bool) -> Result<Vec<PathBuf>> {
+    let Some(sidecar) = Sidecar::read(dir)? else {
+        return Ok(Vec::new());
+    };
+
+    // smoelius: The sidecar records an absolute path, whereas `dir` may be relative (e.g., the
+    // `target` that `build-wrap gc` uses by default). So compare file names only.
+    let live = sidecar.sibling.as_deref().and_then(Path::file_name);
+
+    let mut stale = Vec::new();
+    for result in read_dir(dir)? {
+        let entry = result?;
+        let file_name = entry.file_name();
+        if !is_sibling_name(&file_name) || live == Some(file_name.as_os_str()) {
+            continue;
+        }
+        let sibling = entry.path();
+        if !dry_run {
+            remove_file(&sibling)?;
+        }
+        stale.push(sibling);
+    }
+
+    Ok(stale)
+}
+
+/// Returns true if `file_name` begins with [`SIBLING_PREFIX`], or is [`LEGACY_SIBLING_PREFIX`]
+/// followed by six alphanumeric characters.
+///
+/// A legacy name is considered only in a directory with a sidecar. Sidecars are written only by
+/// versions of `build-wrap` that use [`SIBLING_PREFIX`], so in such a directory, a file with a
+/// legacy name was left by an earlier relink and no wrapper refers to it.
+fn is_sibling_name(file_name: &OsStr) -> bool {
+    let bytes = file_name.as_bytes();
+    if bytes.starts_with(SIBLING_PREFIX.as_bytes()) {
+        return true;
+    }
+    bytes
+        .strip_prefix(LEGACY_SIBLING_PREFIX.as_bytes())
+        .is_some_and(|suffix| suffix.len() == 6 && suffix.iter().all(u8::is_ascii_alphanumeric))
 }
 
 // smoelius: The dependencies listed here must be sufficient to compile util/common.rs.
@@ -42,6 +123,8 @@ publish = false
 
 [dependencies]
 anyhow = "1.0"
+libc = "0.2"
+sha2 = "0.10"
 tempfile = "3.20"
 "#;
 
@@ -49,18 +132,22 @@ tempfile = "3.20"
 ///
 /// - the contents of util/common.rs (included verbatim)
 /// - the path of the renamed original build script (`PATH`)
+/// - the SHA-256 hash of the renamed original build script (`SHA256`)
 /// - a `main` function
 ///
 /// See [`package`].
-fn main_rs(sibling_path_as_str: &str) -> Vec<u8> {
+fn main_rs(sibling_path: &Path, sibling_sha256: &str) -> Vec<u8> {
+    let sibling_path_literal = byte_string_literal(sibling_path.as_os_str().as_bytes());
     [
         COMMON_RS,
         format!(
             r#"
-const PATH: &str = "{sibling_path_as_str}";
+const PATH: &[u8] = {sibling_path_literal};
+
+const SHA256: &str = "{sibling_sha256}";
 
 fn main() -> Result<()> {{
-    exec_sibling(PATH)
+    exec_sibling(Path::new(OsStr::from_bytes(PATH)), SHA256)
 }}
 "#,
         )
@@ -69,4 +156,85 @@ fn main() -> Result<()> {{
     .concat()
 }
 
+/// Returns a Rust byte string literal for `bytes`. Every byte that is not printable ASCII, as well
+/// as `"` and `\`, is escaped, so the path may contain arbitrary bytes.
+fn byte_string_literal(bytes: &[u8]) -> String {
+    let escaped = bytes
+        .iter()
+        .flat_map(|&byte| ascii::escape_default(byte))
+        .map(char::from)
+        .collect::<String>();
+    format!("b\"{escaped}\"")
+}
+
 const COMMON_RS: &[u8] = include_bytes!("util/common.rs");
+
+#[cfg(test)]
+#[allow(clippy::disallowed_methods)]
+mod test {
+    use super::Sibling;
+    use crate::{sidecar::Sidecar, util::sha256_hex};
+    use std::fs::write;
+
+    #[test]
+    fn remove_stale_siblings() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = dir.path().join("build_script_build-0123456789abcdef");
+        let live = dir.path().join(".build-wrap-sibling-AbCdEf");
+        let stale = dir.path().join(".build-wrap-sibling-GhIjKl");
+        let legacy = dir.path().join(".tmpMnOpQr");
+        let unrelated = dir.path().join(".tmp-unrelated");
+        let short = dir.path().join(".tmpStUv");
+        for path in [&original, &live, &stale, &legacy, &unrelated, &short] {
+            write(path, []).unwrap();
+        }
+        Sidecar::new(&original)
+            .wrapped(
+                Sibling {
+                    path: live.clone(),
+                    sha256: sha256_hex(b""),
+                },
+                String::from("{}"),
+            )
+            .write()
+            .unwrap();
+
+        let mut removed = super::remove_stale_siblings(dir.path(), true).unwrap();
+        removed.sort();
+        assert_eq!(vec![stale.clone(), legacy.clone()], removed);
+        assert!(stale.exists());
+        assert!(legacy.exists());
+
+        let _: Vec<_> = super::remove_stale_siblings(dir.path(), false).unwrap();
+        assert!(original.exists());
+        assert!(live.exists());
+        assert!(!stale.exists());
+        assert!(!legacy.exists());
+        assert!(unrelated.exists());
+        assert!(short.exists());
+    }
+
+    #[test]
+    fn byte_string_literal() {
+        assert_eq!(
+            r#"b"/a b/\"\\\'/\xff\n""#,
+            super::byte_string_literal(b"/a b/\"\\'/\xff\n")
+        );
+    }
+
+    #[test]
+    fn remove_stale_siblings_without_sidecar() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = dir.path().join("build_script_build-0123456789abcdef");
+        let sibling = dir.path().join(".build-wrap-sibling-AbCdEf");
+        let legacy = dir.path().join(".tmpGhIjKl");
+        for path in [&original, &sibling, &legacy] {
+            write(path, []).unwrap();
+        }
+
+        let removed = super::remove_stale_siblings(dir.path(), false).unwrap();
+        assert!(removed.is_empty());
+        assert!(sibling.exists());
+        assert!(legacy.exists());
+    }
+}
@@ -0,0 +1,6 @@
+use std::process::exit;
+
+fn main() {
+    eprintln!("ping: socket: Operation not permitted");
+    exit(1);
+}
@@ -0,0 +1,6 @@
+fn main() {
+    println!("cargo:rustc-check-cfg=cfg(evil)");
+    println!("cargo:rustc-cfg=evil");
+    println!("cargo:rustc-link-arg=-Wl,-rpath,/tmp");
+    println!("cargo:rustc-link-search=/nonexistent/attacker");
+}
@@ -0,0 +1,18 @@
+use std::{
+    env::var_os,
+    fs::{create_dir_all, remove_file, write},
+    path::PathBuf,
+};
+
+fn main() {
+    let cargo_home = var_os("CARGO_HOME").map_or_else(
+        || PathBuf::from(var_os("HOME").unwrap()).join(".cargo"),
+        PathBuf::from,
+    );
+    let checkouts = cargo_home.join("git/checkouts");
+    create_dir_all(&checkouts).unwrap();
+    let path = checkouts.join("BUILD_WRAP_TEST");
+    write(&path, "x").unwrap();
+    // smoelius: Should the write succeed, do not leave the file behind.
+    remove_file(&path).unwrap();
+}
@@ -0,0 +1,18 @@
+use std::{
+    env::var_os,
+    fs::{read_dir, write},
+    path::PathBuf,
+};
+
+// smoelius: The test harness enables the optional `libc` build dependency, whose build script
+// runs before this one. So `libc`'s `OUT_DIR` is next to this one.
+fn main() {
+    let out_dir = PathBuf::from(var_os("OUT_DIR").unwrap());
+    let build_dir = out_dir.parent().unwrap().parent().unwrap();
+    let other_out_dir = read_dir(build_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path().join("out"))
+        .find(|path| path.is_dir() && *path != out_dir)
+        .unwrap();
+    write(other_out_dir.join("OTHER_OUT_DIR"), "x").unwrap();
+}
@@ -0,0 +1,16 @@
+use std::{
+    env::var_os,
+    fs::{remove_file, write},
+    path::PathBuf,
+};
+
+fn main() {
+    let cargo_home = var_os("CARGO_HOME").map_or_else(
+        || PathBuf::from(var_os("HOME").unwrap()).join(".cargo"),
+        PathBuf::from,
+    );
+    let path = cargo_home.join("registry/src/BUILD_WRAP_TEST");
+    write(&path, "x").unwrap();
+    // smoelius: Should the write succeed, do not leave the file behind.
+    remove_file(&path).unwrap();
+}
@@ -0,0 +1,32 @@
+use std::{fs::read_to_string, process::Command};
+
+#[test]
+fn allow_command() {
+    let home = crate::util::tempdir().unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_build-wrap"))
+        .args(["allow", "foo", "bar@1.2.3", "foo"])
+        .env_remove("XDG_CONFIG_HOME")
+        .env("HOME", home.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let config_toml = home.path().join(".config/build-wrap/config.toml");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        format!(
+            "allowed `foo` in `{0}`\nallowed `bar@1.2.3` in `{0}`\n`foo` is already allowed in \
+             `{0}`\n",
+            config_toml.display()
+        ),
+        stdout
+    );
+
+    let contents = read_to_string(&config_toml).unwrap();
+    let table = contents.parse::<toml::Table>().unwrap();
+    assert_eq!(
+        Some(&toml::Value::from(vec!["foo", "bar@1.2.3"])),
+        table["allow"].get("packages")
+    );
+}
@@ -0,0 +1,50 @@
+use crate::util;
+use std::fs::{create_dir_all, write};
+
+// smoelius: `BUILD_WRAP_CMD` is `{}`, so nothing stops the build script from creating the link. The
+// audit that follows the build script's run must catch it.
+#[test]
+fn symlink_outside_out_dir() {
+    let dir = util::tempdir().unwrap();
+    let build_script_path = dir.path().join("build.rs");
+    write(
+        &build_script_path,
+        r#"fn main() {
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    std::os::unix::fs::symlink("/etc/passwd", std::path::Path::new(&out_dir).join("passwd"))
+        .unwrap();
+}
+"#,
+    )
+    .unwrap();
+
+    let home = util::tempdir().unwrap();
+    let config_dir = home.path().join(".config/build-wrap");
+    create_dir_all(&config_dir).unwrap();
+    write(
+        config_dir.join("config.toml"),
+        "[audit]\nsymlinks = \"deny\"\n",
+    )
+    .unwrap();
+
+    for deny in [false, true] {
+        let temp_package = util::temp_package(Some(&build_script_path), []).unwrap();
+
+        let mut command = util::build_with_build_wrap();
+        command.env("BUILD_WRAP_CMD", "{}");
+        command.env_remove("XDG_CONFIG_HOME");
+        if deny {
+            command.env("HOME", home.path());
+        }
+        command.current_dir(&temp_package);
+        let output = util::exec_forwarding_output(command, false).unwrap();
+        assert_eq!(!deny, output.status.success());
+
+        let stderr = std::str::from_utf8(&output.stderr).unwrap();
+        assert!(
+            stderr.contains("points outside of `OUT_DIR`, to `/etc/passwd` (`symlinks`)"),
+            "{stderr}"
+        );
+        assert_eq!(deny, stderr.contains("`OUT_DIR` audit failed"), "{stderr}");
+    }
+}
@@ -0,0 +1,83 @@
+use crate::util;
+use std::{
+    fs::{create_dir_all, write},
+    path::Path,
+    process::Command,
+};
+
+#[test]
+fn config_check() {
+    let home = util::tempdir().unwrap();
+    let config_dir = home.path().join(".config/build-wrap");
+    create_dir_all(&config_dir).unwrap();
+
+    write(
+        config_dir.join("config.toml"),
+        "\
+[allow]
+packages = [\"foo\"]
+
+[directives]
+rustc-link-arg = \"deny\"
+",
+    )
+    .unwrap();
+
+    let output = build_wrap(home.path())
+        .args(["config", "check"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("packages = [\"foo\"]"), "{stdout}");
+    assert!(stdout.contains("rustc-link-arg = \"deny\""), "{stdout}");
+
+    write(
+        config_dir.join("config.toml"),
+        "\
+[allow]
+package = [\"foo\"]
+",
+    )
+    .unwrap();
+
+    let output = build_wrap(home.path())
+        .args(["config", "check"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("line 2, column 1"), "{stderr}");
+    assert!(stderr.contains("unknown field `package`"), "{stderr}");
+}
+
+#[test]
+fn strict_config() {
+    let home = util::tempdir().unwrap();
+    let config_dir = home.path().join(".config/build-wrap");
+    create_dir_all(&config_dir).unwrap();
+    write(config_dir.join("config.toml"), "[allowed]\n").unwrap();
+
+    for strict in [false, true] {
+        let temp_package = util::temp_package(Some("tests/build_scripts/dev_null.rs"), []).unwrap();
+
+        let mut command = util::build_with_build_wrap();
+        command.env("BUILD_WRAP_CMD", "{}");
+        command.env_remove("XDG_CONFIG_HOME");
+        command.env("HOME", home.path());
+        // smoelius: Errors are fatal by default. `BUILD_WRAP_STRICT_CONFIG=0` opts out.
+        if !strict {
+            command.env("BUILD_WRAP_STRICT_CONFIG", "0");
+        }
+        command.current_dir(&temp_package);
+        let output = util::exec_forwarding_output(command, false).unwrap();
+        assert_eq!(!strict, output.status.success());
+    }
+}
+
+fn build_wrap(home: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_build-wrap"));
+    command.env_remove("XDG_CONFIG_HOME");
+    command.env("HOME", home);
+    command
+}
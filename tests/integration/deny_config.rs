@@ -0,0 +1,52 @@
+use crate::util;
+use std::{
+    fs::{create_dir_all, write},
+    process::{Command, Output},
+};
+
+#[test]
+fn deny_config() {
+    let home = util::tempdir().unwrap();
+    let config_dir = home.path().join(".config/build-wrap");
+    create_dir_all(&config_dir).unwrap();
+    write(
+        config_dir.join("config.toml"),
+        "\
+[allow]
+packages = [\"temp-package\"]
+
+[deny]
+packages = [\"temp-*\"]
+",
+    )
+    .unwrap();
+
+    let temp_package = util::temp_package(Some("tests/build_scripts/dev_null.rs"), []).unwrap();
+
+    // smoelius: Neither the `[allow]` entry nor `BUILD_WRAP_ALLOW` exempts the package, and the
+    // permissive `BUILD_WRAP_CMD` is replaced by the default.
+    let mut command = util::build_with_build_wrap();
+    command.env("BUILD_WRAP_ALLOW", "1");
+    command.env("BUILD_WRAP_CMD", "{}");
+    command.env_remove("XDG_CONFIG_HOME");
+    command.env("HOME", home.path());
+    command.current_dir(&temp_package);
+    let _: Output = util::exec_forwarding_output(command, true).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_build-wrap"))
+        .args(["list", "--json"])
+        .env_remove("CARGO_TARGET_DIR")
+        .current_dir(&temp_package)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let value = serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap();
+    let entries = value.as_array().unwrap();
+    assert_eq!(1, entries.len(), "{value:#}");
+    assert_eq!("wrapped", entries[0]["state"], "{value:#}");
+    let cmd = entries[0]["cmd"].as_str().unwrap();
+    assert!(
+        cmd.starts_with("bwrap") || cmd.starts_with("sandbox-exec"),
+        "{cmd}"
+    );
+}
@@ -0,0 +1,31 @@
+use crate::util;
+
+#[test]
+fn diagnostics() {
+    let temp_package = util::temp_package(Some("tests/build_scripts/denied.rs"), []).unwrap();
+
+    let mut command = util::build_with_build_wrap();
+    command.env("BUILD_WRAP_CMD", "{}");
+    command.current_dir(&temp_package);
+    let output = util::exec_forwarding_output(command, false).unwrap();
+    assert!(!output.status.success());
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let lines = stderr.lines().map(str::trim).collect::<Vec<_>>();
+    let expected = [
+        "build-wrap: the build script of `temp-package@0.1.0` appears to have been blocked by the \
+         sandbox while accessing the network:",
+        "ping: socket: Operation not permitted",
+        "build-wrap: to allow `temp-package`'s build script to access the network, add the \
+         following to `$HOME/.config/build-wrap/config.toml`:",
+        "",
+        "[sandbox.packages.\"temp-package\"]",
+        "network = true",
+    ];
+    assert!(
+        lines
+            .windows(expected.len())
+            .any(|window| window == expected),
+        "failed to find diagnosis in:\n{stderr}"
+    );
+}
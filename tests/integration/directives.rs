@@ -0,0 +1,53 @@
+use crate::util;
+use std::fs::{create_dir_all, write};
+
+#[test]
+fn directives() {
+    let home = util::tempdir().unwrap();
+    let config_dir = home.path().join(".config/build-wrap");
+    create_dir_all(&config_dir).unwrap();
+    write(
+        config_dir.join("config.toml"),
+        "\
+[directives]
+rustc-link-arg = \"deny\"
+rustc-link-search = \"restrict\"
+rustc-cfg = \"restrict\"
+",
+    )
+    .unwrap();
+
+    let temp_package = util::temp_package(Some("tests/build_scripts/directives.rs"), []).unwrap();
+    // smoelius: If the `evil` cfg were forwarded to Cargo, the package would fail to build.
+    write(
+        temp_package.path().join("src/lib.rs"),
+        "#[cfg(evil)]\ncompile_error!(\"`rustc-cfg` directive was forwarded\");\n",
+    )
+    .unwrap();
+
+    let mut command = util::build_with_build_wrap();
+    command.env("BUILD_WRAP_CMD", "{}");
+    command.env_remove("XDG_CONFIG_HOME");
+    command.env("HOME", home.path());
+    command.current_dir(&temp_package);
+    let output = util::exec_forwarding_output(command, true).unwrap();
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    for (directive, reason) in [
+        ("cargo:rustc-cfg=evil", "`evil` is not in `allowed-cfgs`"),
+        (
+            "cargo:rustc-link-arg=-Wl,-rpath,/tmp",
+            "`rustc-link-arg` directives are denied",
+        ),
+        (
+            "cargo:rustc-link-search=/nonexistent/attacker",
+            "`/nonexistent/attacker` is not in `OUT_DIR`",
+        ),
+    ] {
+        let message = format!("build-wrap: rejected `{directive}`: {reason}");
+        assert!(
+            stderr.lines().any(|line| line.contains(&message)),
+            "failed to find `{message}`"
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use crate::util;
+use std::{
+    fs::{read_dir, write},
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+#[test]
+fn relink_removes_stale_sibling() {
+    let temp_package = util::temp_package(Some("tests/build_scripts/dev_null.rs"), []).unwrap();
+
+    for i in 0..2 {
+        if i > 0 {
+            // smoelius: Modify build.rs so that Cargo relinks the build script.
+            write(temp_package.path().join("build.rs"), "fn main() {}").unwrap();
+        }
+
+        let mut command = util::build_with_build_wrap();
+        command.env("BUILD_WRAP_CMD", "{}");
+        command.current_dir(&temp_package);
+        let _: Output = util::exec_forwarding_output(command, true).unwrap();
+
+        assert_eq!(1, siblings(&temp_package.path().join("target")).len());
+    }
+}
+
+#[test]
+fn gc() {
+    let temp_package = util::temp_package(Some("tests/build_scripts/dev_null.rs"), []).unwrap();
+    let target_dir = temp_package.path().join("target");
+
+    let mut command = util::build_with_build_wrap();
+    command.env("BUILD_WRAP_CMD", "{}");
+    command.current_dir(&temp_package);
+    let _: Output = util::exec_forwarding_output(command, true).unwrap();
+
+    let live = siblings(&target_dir);
+    assert_eq!(1, live.len());
+    let stale = live[0].with_file_name(".build-wrap-sibling-stale");
+    write(&stale, []).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_build-wrap"))
+        .args(["gc", "--target-dir"])
+        .arg(&target_dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    assert_eq!(format!("removed `{}`\n", stale.display()), stdout);
+
+    assert_eq!(live, siblings(&target_dir));
+}
+
+// smoelius: Without `--target-dir`, `build-wrap gc` uses the relative path `target`. The live
+// sibling must be kept nonetheless.
+#[test]
+fn gc_relative_target_dir() {
+    let temp_package = util::temp_package(Some("tests/build_scripts/dev_null.rs"), []).unwrap();
+    let target_dir = temp_package.path().join("target");
+
+    let mut command = util::build_with_build_wrap();
+    command.env("BUILD_WRAP_CMD", "{}");
+    command.current_dir(&temp_package);
+    let _: Output = util::exec_forwarding_output(command, true).unwrap();
+
+    let live = siblings(&target_dir);
+    assert_eq!(1, live.len());
+    let legacy = live[0].with_file_name(".tmpAbCdEf");
+    write(&legacy, []).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_build-wrap"))
+        .arg("gc")
+        .env_remove("CARGO_TARGET_DIR")
+        .current_dir(&temp_package)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = std::str::from_utf8(&output.stdout).unwrap();
+    let relative = legacy.strip_prefix(temp_package.path()).unwrap();
+    assert_eq!(format!("removed `{}`\n", relative.display()), stdout);
+
+    assert_eq!(live, siblings(&target_dir));
+}
+
+fn siblings(target_dir: &Path) -> Vec<PathBuf> {
+    let mut siblings = Vec::new();
+    for result in read_dir(target_dir.join("debug/build")).unwrap() {
+        let entry = result.unwrap();
+        for result in read_dir(entry.path()).unwrap() {
+            let entry = result.unwrap();
+            if entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.starts_with(".build-wrap-sibling-"))
+            {
+                siblings.push(entry.path());
+            }
+        }
+    }
+    siblings
+}
@@ -0,0 +1,58 @@
+use crate::util;
+use std::fs::{create_dir_all, write};
+
+#[cfg(target_os = "linux")]
+#[test]
+fn hide_target_dir() {
+    let dir = util::tempdir().unwrap();
+    let build_script_path = dir.path().join("build.rs");
+    write(
+        &build_script_path,
+        r#"use std::{env::var_os, fs::{read_dir, write}, path::PathBuf};
+
+fn main() {
+    let out_dir = PathBuf::from(var_os("OUT_DIR").unwrap());
+    write(out_dir.join("x"), "x").unwrap();
+    let build_dir = out_dir.parent().unwrap().parent().unwrap();
+    let mut visible = read_dir(build_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    visible.sort();
+    println!("cargo:warning=visible: {visible:?}");
+}
+"#,
+    )
+    .unwrap();
+
+    let home = util::tempdir().unwrap();
+    let config_dir = home.path().join(".config/build-wrap");
+    create_dir_all(&config_dir).unwrap();
+    write(
+        config_dir.join("config.toml"),
+        "[sandbox]\nhide-target-dir = true\n",
+    )
+    .unwrap();
+
+    for hidden in [false, true] {
+        let temp_package = util::temp_package(Some(&build_script_path), []).unwrap();
+
+        let mut command = util::build_with_build_wrap();
+        // smoelius: `--all-features` builds `libc`, whose build script has an `OUT_DIR`.
+        command.arg("--all-features");
+        command.env_remove("XDG_CONFIG_HOME");
+        if hidden {
+            command.env("HOME", home.path());
+        }
+        command.current_dir(&temp_package);
+        let output = util::exec_forwarding_output(command, true).unwrap();
+
+        let stderr = std::str::from_utf8(&output.stderr).unwrap();
+        let line = stderr
+            .lines()
+            .find(|line| line.contains("visible: "))
+            .unwrap_or_else(|| panic!("stderr: ```\n{stderr}\n```"));
+        assert_eq!(!hidden, line.contains("\"libc-"), "{line}");
+        assert!(line.contains("\"temp-package-"), "{line}");
+    }
+}
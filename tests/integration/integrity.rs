@@ -0,0 +1,89 @@
+use crate::util;
+use std::{
+    fs::{OpenOptions, create_dir, read_dir},
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+#[test]
+fn tampered_sibling() {
+    let temp_package = util::temp_package(Some("tests/build_scripts/dev_null.rs"), []).unwrap();
+
+    let mut command = util::build_with_build_wrap();
+    command.env("BUILD_WRAP_CMD", "{}");
+    command.current_dir(&temp_package);
+    let _: Output = util::exec_forwarding_output(command, true).unwrap();
+
+    let build_dir = build_script_dir(&temp_package.path().join("target/debug/build"));
+    // smoelius: The wrapper records its invocation in `OUT_DIR`'s parent, so `OUT_DIR` must be a
+    // subdirectory of a per-test directory.
+    let tempdir = util::tempdir().unwrap();
+    let out_dir = tempdir.path().join("out");
+    create_dir(&out_dir).unwrap();
+
+    for tamper in [false, true] {
+        if tamper {
+            let sibling = read_dir(&build_dir)
+                .unwrap()
+                .map(|result| result.unwrap().path())
+                .find(|path| {
+                    path.file_name()
+                        .and_then(|name| name.to_str())
+                        .is_some_and(|name| name.starts_with(".build-wrap-sibling-"))
+                })
+                .unwrap();
+            let mut file = OpenOptions::new().append(true).open(sibling).unwrap();
+            file.write_all(b"\0").unwrap();
+        }
+
+        // smoelius: Execute the wrapper directly, as Cargo would.
+        let output = Command::new(build_dir.join("build-script-build"))
+            .env("OUT_DIR", &out_dir)
+            .output()
+            .unwrap();
+        let stderr = std::str::from_utf8(&output.stderr).unwrap();
+        assert_eq!(
+            !tamper,
+            output.status.success(),
+            "stderr: ```\n{stderr}\n```"
+        );
+        assert_eq!(
+            tamper,
+            stderr.contains("may have been tampered with"),
+            "stderr: ```\n{stderr}\n```"
+        );
+    }
+}
+
+// smoelius: `BUILD_WRAP_CMD` does not protect the package's sources, but the post-run integrity
+// scan detects that they were modified. `BUILD_WRAP_ALLOW` does not cause the build to succeed.
+#[test]
+fn modified_manifest_dir() {
+    let temp_package = util::temp_package(Some("tests/build_scripts/manifest_dir.rs"), []).unwrap();
+
+    let mut command = util::build_with_build_wrap();
+    command.env("BUILD_WRAP_ALLOW", "1");
+    command.env("BUILD_WRAP_CMD", "{}");
+    command.current_dir(&temp_package);
+    let output = util::exec_forwarding_output(command, false).unwrap();
+    assert!(!output.status.success());
+
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert!(
+        stderr.contains("wrapped build script modified files outside of `OUT_DIR`"),
+        "stderr: ```\n{stderr}\n```"
+    );
+    assert!(
+        stderr.contains(&temp_package.path().join("src/lib.rs").display().to_string()),
+        "stderr: ```\n{stderr}\n```"
+    );
+}
+
+fn build_script_dir(build: &Path) -> PathBuf {
+    read_dir(build)
+        .unwrap()
+        .map(|result| result.unwrap().path())
+        .find(|path| path.join("build-script-build").exists())
+        .unwrap()
+}
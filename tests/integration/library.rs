@@ -0,0 +1,97 @@
+use crate::util;
+use build_wrap::{
+    Action, AuditAction, AuditRule, Config, PROJECT_FILE_NAME, Policy, linux_default_cmd,
+    split_and_expand_with,
+};
+use std::{
+    env::VarError,
+    ffi::OsString,
+    fs::{create_dir_all, write},
+    path::Path,
+};
+
+#[test]
+fn split_and_expand_linux_default_cmd() {
+    let build_script_path =
+        Path::new("/target/debug/build/foo-0123456789abcdef/.build-wrap-sibling-AbCdEf");
+    let words = split_and_expand_with(&linux_default_cmd(), build_script_path, |key| match key {
+        "OUT_DIR" => Ok(OsString::from(
+            "/target/debug/build/foo-fedcba9876543210/out",
+        )),
+        _ => Err(VarError::NotPresent),
+    })
+    .unwrap();
+    assert_eq!(
+        [
+            "bwrap",
+            "--ro-bind",
+            "/",
+            "/",
+            "--dev-bind",
+            "/dev",
+            "/dev",
+            "--bind",
+            "/target/debug/build/foo-fedcba9876543210/out",
+            "/target/debug/build/foo-fedcba9876543210/out",
+            "--bind",
+            "/tmp",
+            "/tmp",
+            "--unshare-net",
+            "/target/debug/build/foo-0123456789abcdef/.build-wrap-sibling-AbCdEf",
+        ]
+        .map(OsString::from)
+        .as_slice(),
+        words
+    );
+}
+
+#[test]
+fn policy_roundtrip() {
+    let policy = Policy {
+        rustc_link_arg: Action::Deny,
+        audit: [(AuditRule::Executables, AuditAction::Warn)]
+            .into_iter()
+            .collect(),
+        ..Policy::default()
+    };
+    assert_eq!(policy, Policy::parse(&policy.to_env_string()).unwrap());
+    assert_eq!(AuditAction::Warn, policy.audit_action(AuditRule::Symlinks));
+}
+
+#[test]
+fn load_config() {
+    let dir = util::tempdir().unwrap();
+    let home_config = dir.path().join("config.toml");
+    let project_dir = dir.path().join("project");
+    create_dir_all(project_dir.join("src")).unwrap();
+    write(
+        &home_config,
+        "[deny]\npackages = [\"foo\"]\n\n[sandbox.packages.bar]\nnetwork = true\n",
+    )
+    .unwrap();
+    write(
+        project_dir.join(PROJECT_FILE_NAME),
+        "[directives]\nrustc-link-arg = \"deny\"\n",
+    )
+    .unwrap();
+
+    let config = Config::load(&home_config, None).unwrap();
+    assert!(config.package_denied("foo", "1.0.0"));
+    assert!(!config.package_denied("bar", "1.0.0"));
+    assert!(config.sandbox_rules("bar", "1.0.0").network);
+    assert_eq!(Action::Allow, config.policy().rustc_link_arg);
+
+    // smoelius: A directory and its ancestors are searched for a project configuration file.
+    for project in [project_dir.join(PROJECT_FILE_NAME), project_dir.join("src")] {
+        let config = Config::load(&home_config, Some(&project)).unwrap();
+        assert!(config.package_denied("foo", "1.0.0"));
+        assert_eq!(Action::Deny, config.policy().rustc_link_arg);
+    }
+
+    // smoelius: A nonexistent user configuration file is treated as empty.
+    let config = Config::load(&dir.path().join("nonexistent.toml"), None).unwrap();
+    assert!(!config.package_denied("foo", "1.0.0"));
+
+    write(&home_config, "[deny]\npackages = 0\n").unwrap();
+    assert!(Config::load(&home_config, None).is_err());
+}
@@ -0,0 +1,54 @@
+use crate::util;
+use std::{
+    fs::{create_dir_all, write},
+    process::{Command, Output},
+};
+
+#[test]
+fn list() {
+    let home = util::tempdir().unwrap();
+    let config_dir = home.path().join(".config/build-wrap");
+    create_dir_all(&config_dir).unwrap();
+    write(
+        config_dir.join("config.toml"),
+        "\
+[allow]
+packages = [\"temp-package\"]
+",
+    )
+    .unwrap();
+
+    for allowed in [false, true] {
+        let temp_package = util::temp_package(Some("tests/build_scripts/dev_null.rs"), []).unwrap();
+
+        let mut command = util::build_with_build_wrap();
+        command.env("BUILD_WRAP_CMD", "{}");
+        command.env_remove("XDG_CONFIG_HOME");
+        if allowed {
+            command.env("HOME", home.path());
+        }
+        command.current_dir(&temp_package);
+        let _: Output = util::exec_forwarding_output(command, true).unwrap();
+
+        let output = Command::new(env!("CARGO_BIN_EXE_build-wrap"))
+            .args(["list", "--json", "--target-dir"])
+            .arg(temp_package.path().join("target"))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+
+        let value = serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap();
+        let entries = value.as_array().unwrap();
+        assert_eq!(1, entries.len(), "{value:#}");
+        let entry = &entries[0];
+        assert_eq!("temp-package", entry["package"]);
+        if allowed {
+            assert_eq!("exempt", entry["state"]);
+            assert_eq!("package is allowed by config.toml", entry["exemption"]);
+        } else {
+            assert_eq!("wrapped", entry["state"]);
+            assert_eq!("{}", entry["cmd"]);
+            assert!(entry["sibling"].is_string());
+        }
+    }
+}
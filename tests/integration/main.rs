@@ -1,13 +1,31 @@
 mod allow;
+mod allow_command;
+mod audit;
 mod build_scripts;
 mod build_wrap_cmd_changed;
 mod cargo_target_dir;
 mod ci;
 mod config;
+mod config_check;
 mod config_toml;
 mod custom_build_name;
+mod deny_config;
+mod diagnostics;
+mod directives;
 mod dogfood;
 mod edition_2021;
 mod enabled;
+mod gc;
+mod hide_target_dir;
+mod integrity;
+mod library;
+mod list;
+mod proc_macros;
+mod profiles;
+mod project_config;
+mod run;
+mod seccomp;
 mod third_party;
+mod unusual_paths;
+mod unwrap;
 mod util;
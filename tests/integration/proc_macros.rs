@@ -0,0 +1,114 @@
+use crate::util;
+use std::{
+    fs::{create_dir_all, read_to_string, write},
+    path::Path,
+    process::Command,
+};
+
+#[test]
+fn allowlist() {
+    let home = util::tempdir().unwrap();
+    let config_path = home.path().join(".config/build-wrap/config.toml");
+    create_dir_all(config_path.parent().unwrap()).unwrap();
+
+    for approved in [false, true] {
+        let mut config = String::from("[proc-macros]\npolicy = \"allowlist\"\n");
+        if approved {
+            config.push_str("packages = [\"thiserror-impl@2.*\"]\n");
+        }
+        write(&config_path, config).unwrap();
+
+        let temp_package =
+            util::temp_package(None::<&Path>, [("thiserror-impl", "2.0.18")]).unwrap();
+
+        let output = build(&temp_package, home.path());
+        assert_eq!(approved, output.status.success());
+
+        let stderr = std::str::from_utf8(&output.stderr).unwrap();
+        assert_eq!(
+            !approved,
+            stderr.contains("proc macro `thiserror-impl@2.0.18` is not approved"),
+            "{stderr}"
+        );
+
+        let entry = proc_macro_entry(&temp_package);
+        if approved {
+            assert_eq!("proc-macro", entry["state"]);
+            assert_eq!("approved by config.toml", entry["approval"]);
+        } else {
+            assert_eq!("refused", entry["state"]);
+        }
+    }
+}
+
+#[test]
+fn tofu() {
+    let home = util::tempdir().unwrap();
+    let config_path = home.path().join(".config/build-wrap/config.toml");
+    create_dir_all(config_path.parent().unwrap()).unwrap();
+    write(&config_path, "[proc-macros]\npolicy = \"tofu\"\n").unwrap();
+
+    // smoelius: The first link records a fingerprint, and the second checks against it.
+    for _ in 0..2 {
+        let temp_package =
+            util::temp_package(None::<&Path>, [("thiserror-impl", "2.0.18")]).unwrap();
+        let output = build(&temp_package, home.path());
+        assert!(output.status.success());
+    }
+
+    let config = read_to_string(&config_path).unwrap();
+    assert!(
+        config.contains("[proc-macros.fingerprints]\n\"thiserror-impl@2.0.18\" = \""),
+        "{config}"
+    );
+
+    // smoelius: A fingerprint that no longer matches causes the proc macro to be refused.
+    let fingerprint = config.rsplit_once(" = ").unwrap().1.trim();
+    write(
+        &config_path,
+        config.replace(fingerprint, &format!("\"{}\"", "0".repeat(64))),
+    )
+    .unwrap();
+
+    let temp_package = util::temp_package(None::<&Path>, [("thiserror-impl", "2.0.18")]).unwrap();
+    let output = build(&temp_package, home.path());
+    assert!(!output.status.success());
+
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert!(
+        stderr.contains(
+            "the sources of proc macro `thiserror-impl@2.0.18` changed since it was first linked"
+        ),
+        "{stderr}"
+    );
+}
+
+fn build(temp_package: &tempfile::TempDir, home: &Path) -> std::process::Output {
+    let mut command = util::build_with_build_wrap();
+    // smoelius: `proc-macro2`'s build script must not need a sandbox.
+    command.env("BUILD_WRAP_CMD", "{}");
+    command.env_remove("XDG_CONFIG_HOME");
+    command.env("HOME", home);
+    command.current_dir(temp_package);
+    util::exec_forwarding_output(command, false).unwrap()
+}
+
+/// Returns `build-wrap list`'s entry for the one proc macro in `temp_package`'s target directory.
+fn proc_macro_entry(temp_package: &tempfile::TempDir) -> serde_json::Value {
+    let output = Command::new(env!("CARGO_BIN_EXE_build-wrap"))
+        .args(["list", "--json", "--target-dir"])
+        .arg(temp_package.path().join("target"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let value = serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap();
+    let entries = value
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|entry| entry["package"] == "thiserror-impl")
+        .collect::<Vec<_>>();
+    assert_eq!(1, entries.len(), "{value:#}");
+    entries[0].clone()
+}
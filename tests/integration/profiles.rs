@@ -0,0 +1,58 @@
+use crate::util;
+use std::{
+    fs::{create_dir_all, write},
+    path::Path,
+    process::Command,
+};
+
+#[test]
+fn profiles() {
+    let home = util::tempdir().unwrap();
+    let config_dir = home.path().join(".config/build-wrap");
+    create_dir_all(&config_dir).unwrap();
+    write(
+        config_dir.join("config.toml"),
+        "\
+[profiles.unsandboxed]
+cmd = \"{}\"
+packages = [\"temp-package\"]
+",
+    )
+    .unwrap();
+
+    let build = |dir: &Path, profile_name: Option<&str>| {
+        let mut command = util::build_with_build_wrap();
+        command.env_remove("BUILD_WRAP_CMD");
+        command.env_remove("XDG_CONFIG_HOME");
+        command.env("HOME", home.path());
+        if let Some(profile_name) = profile_name {
+            command.env("BUILD_WRAP_PROFILE_NAME", profile_name);
+        }
+        command.current_dir(dir);
+        util::exec_forwarding_output(command, false).unwrap()
+    };
+
+    let temp_package = util::temp_package(Some("tests/build_scripts/dev_null.rs"), []).unwrap();
+
+    // smoelius: The package is executed under the profile that names it, rather than under the
+    // default command.
+    let output = build(temp_package.path(), None);
+    assert!(output.status.success());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_build-wrap"))
+        .args(["list", "--json"])
+        .env_remove("CARGO_TARGET_DIR")
+        .current_dir(&temp_package)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let value = serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap();
+    assert_eq!("{}", value[0]["cmd"], "{value:#}");
+
+    let temp_package = util::temp_package(Some("tests/build_scripts/dev_null.rs"), []).unwrap();
+
+    let output = build(temp_package.path(), Some("nonexistent"));
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("unknown profile `nonexistent`"), "{stderr}");
+}
@@ -0,0 +1,125 @@
+use crate::util;
+use std::{
+    fs::{OpenOptions, create_dir, write},
+    io::Write,
+    path::Path,
+    process::{Command, Output},
+};
+
+#[test]
+fn project_config() {
+    let home = util::tempdir().unwrap();
+
+    let temp_package = util::temp_package(Some("tests/build_scripts/dev_null.rs"), []).unwrap();
+    write(
+        temp_package.path().join("build-wrap.toml"),
+        "\
+[allow]
+packages = [\"temp-package\"]
+",
+    )
+    .unwrap();
+
+    let mut command = util::build_with_build_wrap();
+    command.env("BUILD_WRAP_CMD", "{}");
+    command.env_remove("XDG_CONFIG_HOME");
+    command.env("HOME", home.path());
+    command.current_dir(&temp_package);
+    let _: Output = util::exec_forwarding_output(command, true).unwrap();
+
+    // smoelius: The project configuration file is not trusted, so the build script is wrapped.
+    assert_eq!("wrapped", state(home.path(), temp_package.path()));
+
+    for subcommand in ["trust", "rewrap"] {
+        let status = build_wrap(home.path(), temp_package.path())
+            .arg(subcommand)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    assert_eq!("exempt", state(home.path(), temp_package.path()));
+}
+
+// smoelius: When rustc links a dependency outside of the workspace, the current directory is the
+// dependency's package root. A `build-wrap.toml` shipped with the dependency must not be loaded.
+#[test]
+fn dependency_project_config() {
+    let home = util::tempdir().unwrap();
+
+    let dep = util::tempdir().unwrap();
+    write(
+        dep.path().join("Cargo.toml"),
+        "\
+[package]
+name = \"dep\"
+version = \"0.1.0\"
+edition = \"2024\"
+publish = false
+",
+    )
+    .unwrap();
+    write(dep.path().join("build.rs"), "fn main() {}").unwrap();
+    create_dir(dep.path().join("src")).unwrap();
+    write(dep.path().join("src/lib.rs"), "").unwrap();
+    write(
+        dep.path().join("build-wrap.toml"),
+        "\
+[deny]
+packages = [\"dep\"]
+",
+    )
+    .unwrap();
+
+    let temp_package = util::temp_package(None::<&Path>, []).unwrap();
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(temp_package.path().join("Cargo.toml"))
+        .unwrap();
+    writeln!(
+        file,
+        "\n[dependencies]\ndep = {{ path = {:?} }}",
+        dep.path()
+    )
+    .unwrap();
+
+    let mut command = util::build_with_build_wrap();
+    command.env("BUILD_WRAP_CMD", "{}");
+    command.env_remove("XDG_CONFIG_HOME");
+    command.env("HOME", home.path());
+    command.current_dir(&temp_package);
+    let output = util::exec_forwarding_output(command, true).unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert!(!stderr.contains("build-wrap.toml"), "{stderr}");
+
+    let output = build_wrap(home.path(), temp_package.path())
+        .args(["list", "--json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let value = serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap();
+    let entries = value.as_array().unwrap();
+    assert_eq!(1, entries.len(), "{value:#}");
+    assert_eq!("{}", entries[0]["cmd"], "{value:#}");
+}
+
+fn state(home: &Path, dir: &Path) -> String {
+    let output = build_wrap(home, dir)
+        .args(["list", "--json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let value = serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap();
+    let entries = value.as_array().unwrap();
+    assert_eq!(1, entries.len(), "{value:#}");
+    entries[0]["state"].as_str().unwrap().to_owned()
+}
+
+fn build_wrap(home: &Path, dir: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_build-wrap"));
+    command.env_remove("XDG_CONFIG_HOME");
+    command.env_remove("CARGO_TARGET_DIR");
+    command.env("HOME", home);
+    command.current_dir(dir);
+    command
+}
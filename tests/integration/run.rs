@@ -0,0 +1,126 @@
+use crate::util;
+use std::{
+    fs::{create_dir_all, metadata, read, read_dir, write},
+    os::unix::fs::PermissionsExt,
+    process::{Command, Output},
+};
+
+#[test]
+fn run() {
+    let temp_package = util::temp_package(
+        Some("tests/build_scripts/rerun_if_build_wrap_cmd_changed.rs"),
+        [],
+    )
+    .unwrap();
+
+    let mut command = util::build_with_build_wrap();
+    command.env("BUILD_WRAP_CMD", "{}");
+    command.env("CARGO_REGISTRY_TOKEN", "build-wrap-secret");
+    command.current_dir(&temp_package);
+    let _: Output = util::exec_forwarding_output(command, true).unwrap();
+
+    let target_dir = temp_package.path().join("target");
+
+    // smoelius: The invocation record must not contain variables outside of the allowlist, and must
+    // be readable only by its owner.
+    let invoked = read_dir(target_dir.join("debug/build"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path().join("build-wrap-invoked"))
+        .find(|path| path.exists())
+        .unwrap();
+    let contents = read(&invoked).unwrap();
+    assert!(
+        !contents
+            .windows(b"build-wrap-secret".len())
+            .any(|window| window == b"build-wrap-secret")
+    );
+    assert_eq!(
+        0o600,
+        metadata(&invoked).unwrap().permissions().mode() & 0o777
+    );
+
+    for shell in [false, true] {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_build-wrap"));
+        command.arg("run");
+        command.args(["--target-dir", target_dir.to_str().unwrap()]);
+        if shell {
+            // smoelius: Use `env` as the "shell" so that the recorded environment is printed.
+            command.arg("--shell");
+            command.env("SHELL", "env");
+        }
+        command.arg("temp-package");
+        command.env("BUILD_WRAP_CMD", "{}");
+
+        let output = command.output().unwrap();
+        let stdout = std::str::from_utf8(&output.stdout).unwrap();
+        assert!(
+            output.status.success(),
+            "unexpected stdout: ```\n{stdout}\n```"
+        );
+        let needle = if shell {
+            "OUT_DIR="
+        } else {
+            "cargo:rerun-if-env-changed=BUILD_WRAP_CMD"
+        };
+        assert!(
+            stdout.lines().any(|line| line.starts_with(needle)),
+            "unexpected stdout: ```\n{stdout}\n```"
+        );
+    }
+}
+
+// smoelius: A package denied by config.toml after it was built must be rerun under the `strict`
+// profile, not under `BUILD_WRAP_CMD`.
+#[test]
+fn run_denied_package() {
+    let temp_package = util::temp_package(Some("tests/build_scripts/dev_null.rs"), []).unwrap();
+
+    let mut command = util::build_with_build_wrap();
+    command.env("BUILD_WRAP_CMD", "{}");
+    command.current_dir(&temp_package);
+    let _: Output = util::exec_forwarding_output(command, true).unwrap();
+
+    let home = util::tempdir().unwrap();
+    let config_dir = home.path().join(".config/build-wrap");
+    create_dir_all(&config_dir).unwrap();
+    write(
+        config_dir.join("config.toml"),
+        "[deny]\npackages = [\"temp-package\"]\n",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_build-wrap"))
+        .args(["run", "--target-dir"])
+        .arg(temp_package.path().join("target"))
+        .arg("temp-package")
+        .env("BUILD_WRAP_CMD", "{}")
+        .env_remove("XDG_CONFIG_HOME")
+        .env("HOME", home.path())
+        .output()
+        .unwrap();
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert!(
+        stderr.contains(
+            "warning: ignoring `BUILD_WRAP_CMD` for `temp-package@0.1.0`, which is denied by \
+             config.toml; its build script is executed under the `strict` profile"
+        ),
+        "unexpected stderr: ```\n{stderr}\n```"
+    );
+}
+
+#[test]
+fn run_unknown_package() {
+    let tempdir = util::tempdir().unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_build-wrap"))
+        .args(["run", "--target-dir", tempdir.path().to_str().unwrap()])
+        .arg("unknown-package")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert!(
+        stderr.contains("found no build script for `unknown-package`"),
+        "unexpected stderr: ```\n{stderr}\n```"
+    );
+}
@@ -0,0 +1,73 @@
+use crate::util;
+use std::fs::{create_dir_all, write};
+
+// smoelius: The filter is installed regardless of `BUILD_WRAP_CMD`, so `tiocsti.rs` fails even when
+// the build script is not otherwise sandboxed.
+#[cfg(target_os = "linux")]
+#[test]
+fn tiocsti_with_any_cmd() {
+    let temp_package = util::temp_package(Some("tests/build_scripts/tiocsti.rs"), []).unwrap();
+
+    let mut command = util::build_with_build_wrap();
+    command.arg("--all-features");
+    command.env("BUILD_WRAP_CMD", "{}");
+    command.current_dir(&temp_package);
+    let output = util::exec_forwarding_output(command, false).unwrap();
+    assert!(!output.status.success());
+
+    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    assert!(
+        stderr.contains("libc::ioctl: Operation not permitted"),
+        "{stderr}"
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn config_toml_seccomp_packages() {
+    let dir = util::tempdir().unwrap();
+    let build_script_path = dir.path().join("build.rs");
+    write(
+        &build_script_path,
+        r#"fn main() {
+    // smoelius: `0xffffffff` queries the current persona without changing it.
+    if unsafe { libc::personality(0xffff_ffff) } == -1 {
+        panic!("personality: {}", std::io::Error::last_os_error());
+    }
+}
+"#,
+    )
+    .unwrap();
+
+    let home = util::tempdir().unwrap();
+    let config_dir = home.path().join(".config/build-wrap");
+    create_dir_all(&config_dir).unwrap();
+    write(
+        config_dir.join("config.toml"),
+        "[seccomp.packages.\"temp-package\"]\ndeny = [\"personality\"]\n",
+    )
+    .unwrap();
+
+    for denied in [false, true] {
+        let temp_package = util::temp_package(Some(&build_script_path), []).unwrap();
+
+        let mut command = util::build_with_build_wrap();
+        command.arg("--all-features");
+        command.env("BUILD_WRAP_CMD", "{}");
+        command.env_remove("XDG_CONFIG_HOME");
+        if denied {
+            command.env("HOME", home.path());
+        }
+        command.current_dir(&temp_package);
+        let output = util::exec_forwarding_output(command, false).unwrap();
+        assert_eq!(!denied, output.status.success());
+
+        if denied {
+            let stderr = std::str::from_utf8(&output.stderr).unwrap();
+            assert!(
+                stderr.contains("personality: Operation not permitted"),
+                "{stderr}"
+            );
+        }
+    }
+}
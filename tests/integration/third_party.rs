@@ -1,6 +1,6 @@
 use crate::{
     config,
-    util::{TestCase, ToUtf8, test_case},
+    util::{TestCase, test_case},
 };
 use anyhow::{Result, ensure};
 use assert_cmd::Command;
@@ -24,7 +24,7 @@ fn third_party() {
         .unwrap();
 
         let file_stem = path.file_stem().unwrap();
-        let name = file_stem.to_utf8().unwrap();
+        let name = file_stem.to_str().unwrap();
 
         let version = parse_version_file(path);
 
@@ -0,0 +1,54 @@
+use crate::util;
+use std::{
+    ffi::OsStr,
+    fs::{create_dir, write},
+    os::unix::ffi::OsStrExt,
+    process::{Command, Output},
+};
+
+// smoelius: rustc rejects arguments that are not valid UTF-8, so Cargo cannot build in a target
+// directory whose path is not valid UTF-8. But a target directory's path can contain quotes and
+// backslashes.
+#[test]
+fn quotes_in_target_dir() {
+    let temp_package = util::temp_package(Some("tests/build_scripts/dev_null.rs"), []).unwrap();
+
+    let mut command = util::build_with_build_wrap();
+    command.env("CARGO_TARGET_DIR", temp_package.path().join("\"target\\'"));
+    command.current_dir(&temp_package);
+    let _: Output = util::exec_forwarding_output(command, true).unwrap();
+}
+
+// smoelius: Since Cargo cannot be used (see above), `build-wrap` is invoked as a linker directly,
+// and a C program stands in for the build script.
+#[test]
+fn non_utf8_build_script_path() {
+    let home = util::tempdir().unwrap();
+    let dir = util::tempdir().unwrap();
+    let build_dir = dir.path().join(OsStr::from_bytes(b"build-\"\\\xff"));
+    create_dir(&build_dir).unwrap();
+    let main_c = dir.path().join("main.c");
+    write(
+        &main_c,
+        "#include <stdio.h>\nint main(void) { puts(\"cargo:warning=hello\"); return 0; }\n",
+    )
+    .unwrap();
+    let build_script_path = build_dir.join("build_script_build-0123456789abcdef");
+
+    let mut command = Command::new(env!("CARGO_BIN_EXE_build-wrap"));
+    command.arg("-o");
+    command.arg(&build_script_path);
+    command.arg(&main_c);
+    command.env("BUILD_WRAP_CMD", "{}");
+    command.env("HOME", home.path());
+    command.env_remove("XDG_CONFIG_HOME");
+    command.env_remove("RUSTC_WRAPPER");
+    command.env_remove("RUSTC_WORKSPACE_WRAPPER");
+    let _: Output = util::exec_forwarding_output(command, true).unwrap();
+
+    assert!(build_dir.join("build-wrap-sidecar.toml").exists());
+
+    let output = Command::new(&build_script_path).output().unwrap();
+    assert!(output.status.success(), "{output:#?}");
+    assert_eq!(b"cargo:warning=hello\n", output.stdout.as_slice());
+}
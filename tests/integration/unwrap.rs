@@ -0,0 +1,68 @@
+use crate::util;
+use std::process::Command;
+
+// smoelius: `sh` prints `MARKER` before executing the build script, so the marker appears in
+// Cargo's output precisely when the build script is executed under `MARKER_CMD`.
+const MARKER_CMD: &str = r"sh -c echo\ MARKER>&2;exec\ {}";
+
+#[test]
+fn unwrap_and_rewrap() {
+    let temp_package = util::temp_package(Some("tests/build_scripts/dev_null.rs"), []).unwrap();
+    let target_dir = temp_package.path().join("target");
+
+    let build = |build_wrap_cmd: Option<&str>| {
+        let mut command = util::build_with_build_wrap();
+        if let Some(build_wrap_cmd) = build_wrap_cmd {
+            command.env("BUILD_WRAP_CMD", build_wrap_cmd);
+        }
+        command.current_dir(&temp_package);
+        let output = util::exec_forwarding_output(command, true).unwrap();
+        String::from_utf8(output.stderr).unwrap()
+    };
+
+    let build_wrap = |subcommand: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_build-wrap"))
+            .args([subcommand, "--target-dir"])
+            .arg(&target_dir)
+            .env("BUILD_WRAP_CMD", MARKER_CMD)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let stderr = build(Some(MARKER_CMD));
+    assert!(stderr.contains("] MARKER"), "stderr: ```\n{stderr}\n```");
+
+    let stdout = build_wrap("unwrap");
+    assert!(contains_line_starting_with(
+        &stdout,
+        "unwrapped `temp-package@0.1.0`"
+    ));
+
+    // smoelius: If Cargo relinked the build script, it would be wrapped with the default command,
+    // which may not be available. So build without `MARKER_CMD` to verify that it was not.
+    let stderr = build(None);
+    assert!(reran(&stderr), "stderr: ```\n{stderr}\n```");
+    assert!(!stderr.contains("] MARKER"), "stderr: ```\n{stderr}\n```");
+
+    let stdout = build_wrap("rewrap");
+    assert!(contains_line_starting_with(
+        &stdout,
+        "wrapped `temp-package@0.1.0`"
+    ));
+
+    let stderr = build(None);
+    assert!(reran(&stderr), "stderr: ```\n{stderr}\n```");
+    assert!(stderr.contains("] MARKER"), "stderr: ```\n{stderr}\n```");
+}
+
+fn contains_line_starting_with(s: &str, prefix: &str) -> bool {
+    s.lines().any(|line| line.starts_with(prefix))
+}
+
+fn reran(stderr: &str) -> bool {
+    stderr
+        .lines()
+        .any(|line| line.trim_start().starts_with("Running") && line.contains("build-script-build"))
+}
@@ -12,9 +12,10 @@ use snapbox::assert_data_eq;
 use std::{
     env,
     ffi::{OsStr, OsString},
-    fs::{OpenOptions, copy, create_dir, write},
+    fs::{OpenOptions, copy, create_dir, create_dir_all, read_dir, write},
     io::Write,
     net::TcpListener,
+    os::unix::fs::symlink,
     path::{Path, PathBuf},
     process::Command,
     sync::LazyLock,
@@ -121,6 +122,7 @@ pub fn test_case(build_wrap_cmd: Option<&str>, test_case: &TestCase, stderr_expe
         command.env("BUILD_WRAP_CMD", build_wrap_cmd);
     }
     let _tcp_listener = create_tcp_listener(&mut command, test_case).unwrap();
+    let _cargo_home = create_cargo_home(&mut command, test_case).unwrap();
     command.current_dir(&temp_package);
 
     let output = exec_forwarding_output(command, false).unwrap();
@@ -202,3 +204,62 @@ fn create_tcp_listener(command: &mut Command, test_case: &TestCase) -> Result<Op
     command.env("BUILD_WRAP_TCP_PORT", port);
     Ok(Some(listener))
 }
+
+/// Creates a temporary `CARGO_HOME` for the `registry_src` and `git_checkouts` build-script tests,
+/// so that those tests never write to the real one.
+///
+/// The temporary `CARGO_HOME` links to everything in the real one, except that its `registry/src`
+/// and `git/checkouts` are new, empty directories. Cargo re-extracts the test's dependencies from
+/// the real registry cache. The `TempDir` is returned so it persists while the command runs.
+/// Other test cases return `None`.
+fn create_cargo_home(
+    command: &mut Command,
+    test_case: &TestCase,
+) -> Result<Option<tempfile::TempDir>> {
+    let TestCase::BuildScript(path) = test_case else {
+        return Ok(None);
+    };
+    if path.file_name() != Some(OsStr::new("registry_src.rs"))
+        && path.file_name() != Some(OsStr::new("git_checkouts.rs"))
+    {
+        return Ok(None);
+    }
+    let cargo_home = env::var_os("CARGO_HOME").map_or_else(
+        || PathBuf::from(env::var_os("HOME").unwrap_or_default()).join(".cargo"),
+        PathBuf::from,
+    );
+    let tempdir = tempdir()?;
+    link_entries_except(&cargo_home, tempdir.path(), &["registry", "git"])?;
+    link_entries_except(
+        &cargo_home.join("registry"),
+        &tempdir.path().join("registry"),
+        &["src"],
+    )?;
+    link_entries_except(
+        &cargo_home.join("git"),
+        &tempdir.path().join("git"),
+        &["checkouts"],
+    )?;
+    create_dir_all(tempdir.path().join("registry/src"))?;
+    create_dir_all(tempdir.path().join("git/checkouts"))?;
+    command.env("CARGO_HOME", tempdir.path());
+    Ok(Some(tempdir))
+}
+
+/// Creates `to` and symlinks into it each entry of `from` not named in `except`. Does nothing
+/// beyond creating `to` if `from` does not exist.
+fn link_entries_except(from: &Path, to: &Path, except: &[&str]) -> Result<()> {
+    create_dir_all(to)?;
+    if !from.try_exists()? {
+        return Ok(());
+    }
+    for entry in read_dir(from)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        if except.iter().any(|name| file_name == *name) {
+            continue;
+        }
+        symlink(entry.path(), to.join(&file_name))?;
+    }
+    Ok(())
+}